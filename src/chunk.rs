@@ -4,6 +4,8 @@ use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::fmt;
+use std::fmt::Write;
+use std::fs;
 use std::fs::File;
 use std::fs::ReadDir;
 use std::io::BufReader;
@@ -21,12 +23,12 @@ use num::FromPrimitive;
 use num::ToPrimitive;
 use num_bigint::BigInt;
 use num_traits::Zero;
-use regex::Regex;
+use regex::{Locations, Regex};
 use serde::{Deserialize, Serialize};
 use std::process::{ChildStderr, ChildStdout};
 
 use opcode::{to_opcode, OpCode};
-use vm::VM;
+use vm::{VmError, VM};
 
 /// A chunk is a parsed/processed piece of code.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -197,6 +199,115 @@ impl HashWithIndex {
     }
 }
 
+/// A string paired with its current (byte) index, for use within
+/// the various string generators.
+#[derive(Debug, Clone)]
+pub struct StringWithIndex {
+    pub i: usize,
+    pub s: String,
+}
+
+impl StringWithIndex {
+    pub fn new(i: usize, s: String) -> StringWithIndex {
+        StringWithIndex { i, s }
+    }
+}
+
+/// The current value, end value, and step for an integer range,
+/// for use within the range generator.
+#[derive(Debug, Clone)]
+pub struct RangeGenerator {
+    pub i: BigInt,
+    pub end: BigInt,
+    pub step: BigInt,
+}
+
+impl RangeGenerator {
+    pub fn new(start: BigInt, end: BigInt, step: BigInt) -> RangeGenerator {
+        RangeGenerator {
+            i: start,
+            end,
+            step,
+        }
+    }
+
+    /// Returns the next value from the range, or None if the range
+    /// has been exhausted.
+    pub fn next_value(&mut self) -> Option<Value> {
+        let in_range = if self.step.sign() == num_bigint::Sign::Minus {
+            self.i > self.end
+        } else {
+            self.i < self.end
+        };
+        if !in_range {
+            return None;
+        }
+        let value = match self.i.to_i32() {
+            Some(n) => Value::Int(n),
+            None => Value::BigInt(self.i.clone()),
+        };
+        self.i += &self.step;
+        Some(value)
+    }
+}
+
+/// A regex paired with a subject string and the current search
+/// position, for use within the match-all generator.
+#[derive(Debug, Clone)]
+pub struct MatchGenerator {
+    pub regex: Rc<Regex>,
+    pub s: String,
+    pub last_end: usize,
+    pub last_match: Option<usize>,
+    pub global: bool,
+    pub done: bool,
+}
+
+impl MatchGenerator {
+    pub fn new(regex: Rc<Regex>, s: String, global: bool) -> MatchGenerator {
+        MatchGenerator {
+            regex,
+            s,
+            last_end: 0,
+            last_match: None,
+            global,
+            done: false,
+        }
+    }
+
+    /// Returns the capture locations for the next match, if there is
+    /// one.  Empty matches immediately following another match are
+    /// skipped, in the same way as for Regex::captures_iter.  If the
+    /// regex is not global, then at most one match is returned.
+    pub fn next_locations(&mut self) -> Option<Locations> {
+        let mut locs = self.regex.locations();
+        while !self.done && self.last_end <= self.s.len() {
+            let m_opt = self
+                .regex
+                .read_captures_at(&mut locs, &self.s, self.last_end);
+            let (start, end) = match m_opt {
+                Some(m) => (m.start(), m.end()),
+                None => break,
+            };
+            if start == end {
+                self.last_end = end + self.s[end..].chars().next().map_or(1, |c| c.len_utf8());
+                if Some(end) == self.last_match {
+                    continue;
+                }
+            } else {
+                self.last_end = end;
+            }
+            self.last_match = Some(end);
+            if !self.global {
+                self.done = true;
+            }
+            return Some(locs);
+        }
+        self.done = true;
+        None
+    }
+}
+
 /// An IPv4 range object.
 #[derive(Debug, Clone)]
 pub struct Ipv4Range {
@@ -454,6 +565,15 @@ pub enum Value {
     IpSet(Rc<RefCell<IpSet>>),
     /// Multiple generators combined together.
     MultiGenerator(Rc<RefCell<VecDeque<Value>>>),
+    /// A generator over the matches of a regex against a string.
+    MatchGenerator(Rc<RefCell<MatchGenerator>>),
+    /// A generator over the characters (grapheme clusters) of a
+    /// string.
+    CharsGenerator(Rc<RefCell<StringWithIndex>>),
+    /// A generator over the lines of a string.
+    LinesGenerator(Rc<RefCell<StringWithIndex>>),
+    /// A generator over a range of integers.
+    RangeGenerator(Rc<RefCell<RangeGenerator>>),
 }
 
 impl fmt::Debug for Value {
@@ -550,6 +670,18 @@ impl fmt::Debug for Value {
             Value::MultiGenerator(_) => {
                 write!(f, "((MultiGenerator))")
             }
+            Value::MatchGenerator(_) => {
+                write!(f, "((MatchGenerator))")
+            }
+            Value::CharsGenerator(_) => {
+                write!(f, "((CharsGenerator))")
+            }
+            Value::LinesGenerator(_) => {
+                write!(f, "((LinesGenerator))")
+            }
+            Value::RangeGenerator(_) => {
+                write!(f, "((RangeGenerator))")
+            }
         }
     }
 }
@@ -567,25 +699,62 @@ pub enum ValueSD {
     String(String, String),
     Command(String, HashSet<char>),
     CommandUncaptured(String),
+    List(Vec<ValueSD>),
 }
 
-/// Takes a chunk, an instruction index, and an error message as its
-/// arguments.  Prints the error message, including filename, line number
-/// and column number elements (if applicable).
-pub fn print_error(chunk: Rc<RefCell<Chunk>>, i: usize, error: &str) {
-    let point = chunk.borrow().get_point(i);
-    let name = &chunk.borrow().name;
-    let error_start = if name == "(main)" {
-        String::new()
-    } else {
-        format!("{}:", name)
-    };
-    match point {
-        Some((line, col)) => {
-            eprintln!("{}{}:{}: {}", error_start, line, col, error);
-        }
-        _ => {
-            eprintln!("{}{}", error_start, error);
+impl ValueSD {
+    /// Convert a value into its serialisable form.  Returns an error
+    /// if the value's type cannot be stored as a constant.
+    fn from_value(value_rr: &Value) -> Result<ValueSD, VmError> {
+        let value_sd = match value_rr {
+            Value::Null => ValueSD::Null,
+            Value::Int(n) => ValueSD::Int(*n),
+            Value::Float(n) => ValueSD::Float(*n),
+            Value::BigInt(n) => ValueSD::BigInt(n.to_str_radix(10)),
+            Value::String(st) => ValueSD::String(
+                st.borrow().string.to_string(),
+                st.borrow().escaped_string.to_string(),
+            ),
+            Value::Command(s, params) => ValueSD::Command(s.to_string(), (**params).clone()),
+            Value::CommandUncaptured(s) => ValueSD::CommandUncaptured(s.to_string()),
+            Value::Bool(b) => ValueSD::Bool(*b),
+            Value::List(lst) => {
+                let mut lst_sd = Vec::new();
+                for v in lst.borrow().iter() {
+                    lst_sd.push(ValueSD::from_value(v)?);
+                }
+                ValueSD::List(lst_sd)
+            }
+            _ => {
+                return Err(VmError::UnsupportedConstant(format!("{:?}", value_rr)));
+            }
+        };
+        Ok(value_sd)
+    }
+
+    /// Convert a serialisable value back into a value.
+    fn to_value(&self) -> Value {
+        match self {
+            ValueSD::Null => Value::Null,
+            ValueSD::Bool(b) => Value::Bool(*b),
+            ValueSD::Int(n) => Value::Int(*n),
+            ValueSD::Float(n) => Value::Float(*n),
+            ValueSD::BigInt(n) => {
+                let nn = n.parse::<num_bigint::BigInt>().unwrap();
+                Value::BigInt(nn)
+            }
+            ValueSD::String(st1, st2) => {
+                let st = StringTriple::new_with_escaped(st1.to_string(), st2.to_string(), None);
+                Value::String(Rc::new(RefCell::new(st)))
+            }
+            ValueSD::Command(s, params) => {
+                Value::Command(Rc::new(s.to_string()), Rc::new((*params).clone()))
+            }
+            ValueSD::CommandUncaptured(s) => Value::CommandUncaptured(Rc::new(s.to_string())),
+            ValueSD::List(lst_sd) => {
+                let lst = lst_sd.iter().map(|v| v.to_value()).collect();
+                Value::List(Rc::new(RefCell::new(lst)))
+            }
         }
     }
 }
@@ -629,49 +798,15 @@ impl Chunk {
 
     /// Add a constant to the current chunk, and return its index in
     /// the constants list (for later calls to `get_constant`).
-    pub fn add_constant(&mut self, value_rr: Value) -> i32 {
-        let value_sd = match value_rr {
-            Value::Null => ValueSD::Null,
-            Value::Int(n) => ValueSD::Int(n),
-            Value::Float(n) => ValueSD::Float(n),
-            Value::BigInt(n) => ValueSD::BigInt(n.to_str_radix(10)),
-            Value::String(st) => ValueSD::String(
-                st.borrow().string.to_string(),
-                st.borrow().escaped_string.to_string(),
-            ),
-            Value::Command(s, params) => ValueSD::Command(s.to_string(), (*params).clone()),
-            Value::CommandUncaptured(s) => ValueSD::CommandUncaptured(s.to_string()),
-            Value::Bool(b) => ValueSD::Bool(b),
-            _ => {
-                eprintln!("constant type cannot be added to chunk! {:?}", value_rr);
-                std::process::abort();
-            }
-        };
+    pub fn add_constant(&mut self, value_rr: Value) -> Result<i32, VmError> {
+        let value_sd = ValueSD::from_value(&value_rr)?;
         self.constants.push(value_sd);
-        (self.constants.len() - 1) as i32
+        Ok((self.constants.len() - 1) as i32)
     }
 
     /// Get a constant from the current chunk.
     pub fn get_constant(&self, i: i32) -> Value {
-        let value_sd = &self.constants[i as usize];
-        match value_sd {
-            ValueSD::Null => Value::Null,
-            ValueSD::Bool(b) => Value::Bool(*b),
-            ValueSD::Int(n) => Value::Int(*n),
-            ValueSD::Float(n) => Value::Float(*n),
-            ValueSD::BigInt(n) => {
-                let nn = n.parse::<num_bigint::BigInt>().unwrap();
-                Value::BigInt(nn)
-            }
-            ValueSD::String(st1, st2) => {
-                let st = StringTriple::new_with_escaped(st1.to_string(), st2.to_string(), None);
-                Value::String(Rc::new(RefCell::new(st)))
-            }
-            ValueSD::Command(s, params) => {
-                Value::Command(Rc::new(s.to_string()), Rc::new((*params).clone()))
-            }
-            ValueSD::CommandUncaptured(s) => Value::CommandUncaptured(Rc::new(s.to_string())),
-        }
+        self.constants[i as usize].to_value()
     }
 
     /// Get a constant value from the current chunk.  If the relevant
@@ -685,6 +820,29 @@ impl Chunk {
         }
     }
 
+    /// Set the constant value at the given index, extending the
+    /// constant values list as required.
+    pub fn set_constant_value(&mut self, i: i32, value_rr: Value) {
+        let i = i as usize;
+        if self.constant_values.len() <= i {
+            self.constant_values.resize(i + 1, Value::Null);
+        }
+        self.constant_values[i] = value_rr;
+    }
+
+    /// Initialise the constant values for the current chunk (and any
+    /// nested functions) from the chunk's constants.  The constant
+    /// values are not serialised, so this is called after a chunk is
+    /// loaded.
+    pub fn init_constant_values(&mut self) {
+        self.constant_values = (0..self.constants.len())
+            .map(|i| self.get_constant(i as i32))
+            .collect();
+        for f in self.functions.values() {
+            f.borrow_mut().init_constant_values();
+        }
+    }
+
     /// Get a constant int value from the current chunk.
     pub fn get_constant_int(&self, i: i32) -> i32 {
         let value_sd = &self.constants[i as usize];
@@ -878,19 +1036,66 @@ impl Chunk {
         }
     }
 
+    /// Serialise the chunk (including any nested functions) to the
+    /// file at the given path.
+    pub fn save_to_path(&self, path: &str) -> Result<(), String> {
+        let encoded =
+            bincode::serialize(self).map_err(|e| format!("unable to serialise chunk: {}", e))?;
+        fs::write(path, encoded).map_err(|e| format!("unable to write chunk: {}", e))
+    }
+
+    /// Deserialise a chunk from the file at the given path.  Since
+    /// `constant_values` is not serialised, it is rebuilt from
+    /// `constants` (for the chunk and its nested functions) after the
+    /// chunk is loaded.
+    pub fn load_from_path(path: &str) -> Result<Chunk, String> {
+        let data = fs::read(path).map_err(|e| format!("unable to read chunk: {}", e))?;
+        let mut chunk: Chunk = bincode::deserialize(&data)
+            .map_err(|e| format!("unable to deserialise chunk: {}", e))?;
+        chunk.init_constant_values();
+        Ok(chunk)
+    }
+
     /// Print the disassembly for the current chunk to standard
     /// output.
     pub fn disassemble(&self, name: &str) {
-        println!("== {} ==", name);
+        print!("{}", self.disassemble_to_string(name));
+    }
+
+    /// Return the disassembly for the current chunk as a string.
+    pub fn disassemble_to_string(&self, name: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "== {} ==", name).unwrap();
 
         let mut i = 0;
         while i < self.data.len() {
             let opcode = to_opcode(self.data[i]);
-            print!("{:^4} ", i);
-            match opcode {
-                OpCode::Clone => {
-                    println!("OP_CLONE");
-                }
+            let start_i = i;
+            let operand_count = match opcode {
+                OpCode::SetLocalVar | OpCode::GetLocalVar | OpCode::GLVShift | OpCode::GLVCall => 1,
+                OpCode::Constant
+                | OpCode::AddConstant
+                | OpCode::SubtractConstant
+                | OpCode::DivideConstant
+                | OpCode::MultiplyConstant
+                | OpCode::EqConstant
+                | OpCode::CallConstant
+                | OpCode::CallImplicitConstant
+                | OpCode::Jump
+                | OpCode::JumpR
+                | OpCode::JumpNe
+                | OpCode::JumpNeR => 2,
+                OpCode::JumpNeREqC => 4,
+                _ => 0,
+            };
+            /* If the chunk ends before all of the operands for this
+             * opcode, then the remaining data cannot be decoded. */
+            if i + operand_count >= self.data.len() {
+                writeln!(out, "{:^4} (truncated)", start_i).unwrap();
+                break;
+            }
+            let desc = match opcode {
+                OpCode::Clone => "OP_CLONE".to_string(),
                 OpCode::Constant => {
                     i += 1;
                     let i_upper = self.data[i];
@@ -898,7 +1103,7 @@ impl Chunk {
                     let i_lower = self.data[i];
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
-                    println!("OP_CONSTANT {:?}", value);
+                    format!("OP_CONSTANT {:?}", value)
                 }
                 OpCode::AddConstant => {
                     i += 1;
@@ -907,7 +1112,7 @@ impl Chunk {
                     let i_lower = self.data[i];
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
-                    println!("OP_ADDCONSTANT {:?}", value);
+                    format!("OP_ADDCONSTANT {:?}", value)
                 }
                 OpCode::SubtractConstant => {
                     i += 1;
@@ -916,7 +1121,7 @@ impl Chunk {
                     let i_lower = self.data[i];
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
-                    println!("OP_SUBTRACTCONSTANT {:?}", value);
+                    format!("OP_SUBTRACTCONSTANT {:?}", value)
                 }
                 OpCode::DivideConstant => {
                     i += 1;
@@ -925,7 +1130,7 @@ impl Chunk {
                     let i_lower = self.data[i];
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
-                    println!("OP_DIVIDECONSTANT {:?}", value);
+                    format!("OP_DIVIDECONSTANT {:?}", value)
                 }
                 OpCode::MultiplyConstant => {
                     i += 1;
@@ -934,7 +1139,7 @@ impl Chunk {
                     let i_lower = self.data[i];
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
-                    println!("OP_MULTIPLYCONSTANT {:?}", value);
+                    format!("OP_MULTIPLYCONSTANT {:?}", value)
                 }
                 OpCode::EqConstant => {
                     i += 1;
@@ -943,71 +1148,47 @@ impl Chunk {
                     let i_lower = self.data[i];
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
-                    println!("OP_EQCONSTANT {:?}", value);
-                }
-                OpCode::Add => {
-                    println!("OP_ADD");
-                }
-                OpCode::Subtract => {
-                    println!("OP_SUBTRACT");
-                }
-                OpCode::Multiply => {
-                    println!("OP_MULTIPLY");
-                }
-                OpCode::Divide => {
-                    println!("OP_DIVIDE");
-                }
-                OpCode::EndFn => {
-                    println!("OP_ENDFN");
-                }
-                OpCode::Call => {
-                    println!("OP_CALL");
-                }
-                OpCode::CallImplicit => {
-                    println!("OP_CALLIMPLICIT");
-                }
-                OpCode::Function => {
-                    println!("OP_FUNCTION");
-                }
-                OpCode::Var => {
-                    println!("OP_VAR");
-                }
-                OpCode::SetVar => {
-                    println!("OP_SETVAR");
-                }
-                OpCode::GetVar => {
-                    println!("OP_GETVAR");
-                }
+                    format!("OP_EQCONSTANT {:?}", value)
+                }
+                OpCode::Add => "OP_ADD".to_string(),
+                OpCode::Subtract => "OP_SUBTRACT".to_string(),
+                OpCode::Multiply => "OP_MULTIPLY".to_string(),
+                OpCode::Divide => "OP_DIVIDE".to_string(),
+                OpCode::EndFn => "OP_ENDFN".to_string(),
+                OpCode::Call => "OP_CALL".to_string(),
+                OpCode::CallImplicit => "OP_CALLIMPLICIT".to_string(),
+                OpCode::Function => "OP_FUNCTION".to_string(),
+                OpCode::Var => "OP_VAR".to_string(),
+                OpCode::SetVar => "OP_SETVAR".to_string(),
+                OpCode::GetVar => "OP_GETVAR".to_string(),
                 OpCode::SetLocalVar => {
                     i += 1;
                     let var_i = self.data[i];
-                    println!("OP_SETLOCALVAR {}", var_i);
+                    format!("OP_SETLOCALVAR {}", var_i)
                 }
                 OpCode::GetLocalVar => {
                     i += 1;
                     let var_i = self.data[i];
-                    println!("OP_GETLOCALVAR {}", var_i);
+                    format!("OP_GETLOCALVAR {}", var_i)
                 }
                 OpCode::GLVShift => {
                     i += 1;
                     let var_i = self.data[i];
-                    println!("OP_GLVSHIFT {}", var_i);
+                    format!("OP_GLVSHIFT {}", var_i)
                 }
                 OpCode::GLVCall => {
                     i += 1;
                     let var_i = self.data[i];
-                    println!("OP_GLVCALL {}", var_i);
-                }
-                OpCode::PopLocalVar => {
-                    println!("OP_POPLOCALVAR");
+                    format!("OP_GLVCALL {}", var_i)
                 }
+                OpCode::PopLocalVar => "OP_POPLOCALVAR".to_string(),
                 OpCode::Jump => {
                     i += 1;
                     let i1: usize = self.data[i].try_into().unwrap();
                     i += 1;
                     let i2: usize = self.data[i].try_into().unwrap();
                     let jump_i: usize = (i1 << 8) | i2;
-                    println!("OP_JUMP {:?}", jump_i);
+                    format!("OP_JUMP {:?}", jump_i)
                 }
                 OpCode::JumpR => {
                     i += 1;
@@ -1015,7 +1196,7 @@ impl Chunk {
                     i += 1;
                     let i2: usize = self.data[i].try_into().unwrap();
                     let jump_i: usize = (i1 << 8) | i2;
-                    println!("OP_JUMPR {:?}", jump_i);
+                    format!("OP_JUMPR {:?}", jump_i)
                 }
                 OpCode::JumpNe => {
                     i += 1;
@@ -1023,7 +1204,7 @@ impl Chunk {
                     i += 1;
                     let i2: usize = self.data[i].try_into().unwrap();
                     let jump_i: usize = (i1 << 8) | i2;
-                    println!("OP_JUMPNE {:?}", jump_i);
+                    format!("OP_JUMPNE {:?}", jump_i)
                 }
                 OpCode::JumpNeR => {
                     i += 1;
@@ -1031,7 +1212,7 @@ impl Chunk {
                     i += 1;
                     let i2: usize = self.data[i].try_into().unwrap();
                     let jump_i: usize = (i1 << 8) | i2;
-                    println!("OP_JUMPNER {:?}", jump_i);
+                    format!("OP_JUMPNER {:?}", jump_i)
                 }
                 OpCode::JumpNeREqC => {
                     i += 1;
@@ -1047,119 +1228,45 @@ impl Chunk {
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
 
-                    println!("OP_JUMPNEREQC {:?} {:?}", jump_i, value);
-                }
-                OpCode::Cmp => {
-                    println!("OP_CMP");
-                }
-                OpCode::Eq => {
-                    println!("OP_EQ");
-                }
-                OpCode::Gt => {
-                    println!("OP_GT");
-                }
-                OpCode::Lt => {
-                    println!("OP_LT");
-                }
-                OpCode::Print => {
-                    println!("OP_PRINT");
-                }
-                OpCode::Dup => {
-                    println!("OP_DUP");
-                }
-                OpCode::Swap => {
-                    println!("OP_SWAP");
-                }
-                OpCode::Drop => {
-                    println!("OP_DROP");
-                }
-                OpCode::Rot => {
-                    println!("OP_ROT");
-                }
-                OpCode::Over => {
-                    println!("OP_OVER");
-                }
-                OpCode::Depth => {
-                    println!("OP_DEPTH");
-                }
-                OpCode::Clear => {
-                    println!("OP_CLEAR");
-                }
-                OpCode::StartList => {
-                    println!("OP_STARTLIST");
-                }
-                OpCode::EndList => {
-                    println!("OP_ENDLIST");
-                }
-                OpCode::StartHash => {
-                    println!("OP_STARTHASH");
-                }
-                OpCode::StartSet => {
-                    println!("OP_STARTSET");
-                }
-                OpCode::Shift => {
-                    println!("OP_SHIFT");
-                }
-                OpCode::Yield => {
-                    println!("OP_YIELD");
-                }
-                OpCode::IsNull => {
-                    println!("OP_ISNULL");
-                }
-                OpCode::IsList => {
-                    println!("OP_ISLIST");
-                }
-                OpCode::IsCallable => {
-                    println!("OP_ISCALLABLE");
-                }
-                OpCode::IsShiftable => {
-                    println!("OP_ISSHIFTABLE");
-                }
-                OpCode::Open => {
-                    println!("OP_OPEN");
-                }
-                OpCode::Readline => {
-                    println!("OP_READLINE");
-                }
-                OpCode::Error => {
-                    println!("OP_ERROR");
-                }
-                OpCode::Return => {
-                    println!("OP_RETURN");
-                }
-                OpCode::Str => {
-                    println!("OP_STR");
-                }
-                OpCode::Int => {
-                    println!("OP_INT");
-                }
-                OpCode::Flt => {
-                    println!("OP_FLT")
-                }
-                OpCode::Rand => {
-                    println!("OP_RAND")
-                }
-                OpCode::Push => {
-                    println!("OP_PUSH")
-                }
-                OpCode::Pop => {
-                    println!("OP_POP")
-                }
-                OpCode::DupIsNull => {
-                    println!("OP_DUPISNULL")
-                }
-                OpCode::ToggleMode => {
-                    println!("OP_TOGGLEMODE")
-                }
-                OpCode::PrintStack => {
-                    println!("OP_PRINTSTACK")
-                }
-                OpCode::ToFunction => {
-                    println!("OP_TOFUNCTION")
-                }
-                OpCode::Import => {
-                    println!("OP_IMPORT")
-                }
+                    format!("OP_JUMPNEREQC {:?} {:?}", jump_i, value)
+                }
+                OpCode::Cmp => "OP_CMP".to_string(),
+                OpCode::Eq => "OP_EQ".to_string(),
+                OpCode::Gt => "OP_GT".to_string(),
+                OpCode::Lt => "OP_LT".to_string(),
+                OpCode::Print => "OP_PRINT".to_string(),
+                OpCode::Dup => "OP_DUP".to_string(),
+                OpCode::Swap => "OP_SWAP".to_string(),
+                OpCode::Drop => "OP_DROP".to_string(),
+                OpCode::Rot => "OP_ROT".to_string(),
+                OpCode::Over => "OP_OVER".to_string(),
+                OpCode::Depth => "OP_DEPTH".to_string(),
+                OpCode::Clear => "OP_CLEAR".to_string(),
+                OpCode::StartList => "OP_STARTLIST".to_string(),
+                OpCode::EndList => "OP_ENDLIST".to_string(),
+                OpCode::StartHash => "OP_STARTHASH".to_string(),
+                OpCode::StartSet => "OP_STARTSET".to_string(),
+                OpCode::Shift => "OP_SHIFT".to_string(),
+                OpCode::Yield => "OP_YIELD".to_string(),
+                OpCode::IsNull => "OP_ISNULL".to_string(),
+                OpCode::IsList => "OP_ISLIST".to_string(),
+                OpCode::IsCallable => "OP_ISCALLABLE".to_string(),
+                OpCode::IsShiftable => "OP_ISSHIFTABLE".to_string(),
+                OpCode::Open => "OP_OPEN".to_string(),
+                OpCode::Readline => "OP_READLINE".to_string(),
+                OpCode::Error => "OP_ERROR".to_string(),
+                OpCode::Return => "OP_RETURN".to_string(),
+                OpCode::Str => "OP_STR".to_string(),
+                OpCode::Int => "OP_INT".to_string(),
+                OpCode::Flt => "OP_FLT".to_string(),
+                OpCode::Rand => "OP_RAND".to_string(),
+                OpCode::Push => "OP_PUSH".to_string(),
+                OpCode::Pop => "OP_POP".to_string(),
+                OpCode::DupIsNull => "OP_DUPISNULL".to_string(),
+                OpCode::ToggleMode => "OP_TOGGLEMODE".to_string(),
+                OpCode::PrintStack => "OP_PRINTSTACK".to_string(),
+                OpCode::ToFunction => "OP_TOFUNCTION".to_string(),
+                OpCode::Import => "OP_IMPORT".to_string(),
                 OpCode::CallConstant => {
                     i += 1;
                     let i_upper = self.data[i];
@@ -1167,7 +1274,7 @@ impl Chunk {
                     let i_lower = self.data[i];
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
-                    println!("OP_CALLCONSTANT {:?}", value);
+                    format!("OP_CALLCONSTANT {:?}", value)
                 }
                 OpCode::CallImplicitConstant => {
                     i += 1;
@@ -1176,40 +1283,26 @@ impl Chunk {
                     let i_lower = self.data[i];
                     let constant_i = (((i_upper as u16) << 8) & 0xFF00) | (i_lower as u16);
                     let value = self.get_constant(constant_i as i32);
-                    println!("OP_CALLIMPLICITCONSTANT {:?}", value);
-                }
-                OpCode::Bool => {
-                    println!("OP_BOOL");
-                }
-                OpCode::IsBool => {
-                    println!("OP_ISBOOL");
-                }
-                OpCode::IsInt => {
-                    println!("OP_ISINT");
-                }
-                OpCode::IsBigInt => {
-                    println!("OP_ISBIGINT");
-                }
-                OpCode::IsStr => {
-                    println!("OP_ISSTR");
-                }
-                OpCode::IsFlt => {
-                    println!("OP_ISFLT");
-                }
-                OpCode::BigInt => {
-                    println!("OP_BIGINT");
-                }
-                OpCode::Unknown => {
-                    println!("(Unknown)");
-                }
-            }
+                    format!("OP_CALLIMPLICITCONSTANT {:?}", value)
+                }
+                OpCode::Bool => "OP_BOOL".to_string(),
+                OpCode::IsBool => "OP_ISBOOL".to_string(),
+                OpCode::IsInt => "OP_ISINT".to_string(),
+                OpCode::IsBigInt => "OP_ISBIGINT".to_string(),
+                OpCode::IsStr => "OP_ISSTR".to_string(),
+                OpCode::IsFlt => "OP_ISFLT".to_string(),
+                OpCode::BigInt => "OP_BIGINT".to_string(),
+                OpCode::Unknown => "(Unknown)".to_string(),
+            };
+            writeln!(out, "{:^4} {}", start_i, desc).unwrap();
             i += 1;
         }
 
         for (k, v) in self.functions.iter() {
-            println!("== {}.{} ==", name, k);
-            v.borrow().disassemble(k);
+            writeln!(out, "== {}.{} ==", name, k).unwrap();
+            out.push_str(&v.borrow().disassemble_to_string(k));
         }
+        out
     }
 }
 
@@ -1228,7 +1321,7 @@ macro_rules! to_str {
                 Some(&lib_str_b.string)
             }
             _ => {
-                lib_str_bk = $val.to_string();
+                lib_str_bk = $val.to_string().unwrap_or(None);
                 match lib_str_bk {
                     Some(s) => {
                         lib_str_str = s;
@@ -1242,14 +1335,21 @@ macro_rules! to_str {
 }
 
 impl Value {
-    /// Convert the current value into a string.  Not intended for use
-    /// with Value::String.
-    pub fn to_string(&self) -> Option<String> {
+    /// Convert the current value into a string.  Returns None if the
+    /// value cannot be converted into a string.  Not intended for
+    /// use with Value::String (for which an error is returned), since
+    /// callers can use the underlying string directly in that case.
+    pub fn to_string(&self) -> Result<Option<String>, VmError> {
+        match self {
+            Value::String(_) => Err(VmError::UnexpectedString),
+            _ => Ok(self.non_string_to_string()),
+        }
+    }
+
+    /// Helper function for to_string, for values that are not of
+    /// type Value::String.
+    fn non_string_to_string(&self) -> Option<String> {
         match self {
-            Value::String(_) => {
-                eprintln!("to_string should not be called with Value::String!");
-                std::process::abort();
-            }
             Value::Int(n) => {
                 let s = format!("{}", n);
                 Some(s)
@@ -1394,13 +1494,13 @@ impl Value {
     pub fn to_bool(&self) -> bool {
         match self {
             Value::Bool(b) => *b,
-            Value::Int(0) => false,
+            Value::Int(n) => *n != 0,
             Value::Float(n) => *n != 0.0,
             Value::String(st) => {
                 let ss = &st.borrow().string;
                 !ss.is_empty() && ss != "0" && ss != "0.0"
             }
-            Value::BigInt(n) => *n == Zero::zero(),
+            Value::BigInt(n) => *n != Zero::zero(),
             Value::Null => false,
             _ => true,
         }
@@ -1474,6 +1574,18 @@ impl Value {
             Value::Ipv6Range(_) => self.clone(),
             Value::IpSet(_) => self.clone(),
             Value::MultiGenerator(_) => self.clone(),
+            Value::MatchGenerator(match_gen_ref) => {
+                Value::MatchGenerator(Rc::new(RefCell::new(match_gen_ref.borrow().clone())))
+            }
+            Value::CharsGenerator(chars_gen_ref) => {
+                Value::CharsGenerator(Rc::new(RefCell::new(chars_gen_ref.borrow().clone())))
+            }
+            Value::LinesGenerator(lines_gen_ref) => {
+                Value::LinesGenerator(Rc::new(RefCell::new(lines_gen_ref.borrow().clone())))
+            }
+            Value::RangeGenerator(range_gen_ref) => {
+                Value::RangeGenerator(Rc::new(RefCell::new(range_gen_ref.borrow().clone())))
+            }
         }
     }
 
@@ -1509,6 +1621,10 @@ impl Value {
             (Value::Ipv6Range(..), Value::Ipv6Range(..)) => true,
             (Value::IpSet(..), Value::IpSet(..)) => true,
             (Value::MultiGenerator(..), Value::MultiGenerator(..)) => true,
+            (Value::MatchGenerator(..), Value::MatchGenerator(..)) => true,
+            (Value::CharsGenerator(..), Value::CharsGenerator(..)) => true,
+            (Value::LinesGenerator(..), Value::LinesGenerator(..)) => true,
+            (Value::RangeGenerator(..), Value::RangeGenerator(..)) => true,
             (..) => false,
         }
     }
@@ -1524,6 +1640,10 @@ impl Value {
                 | Value::DirectoryHandle(..)
                 | Value::IpSet(..)
                 | Value::MultiGenerator(..)
+                | Value::MatchGenerator(..)
+                | Value::CharsGenerator(..)
+                | Value::LinesGenerator(..)
+                | Value::RangeGenerator(..)
         )
     }
 
@@ -1559,6 +1679,10 @@ impl Value {
             Value::Ipv6Range(..) => "ip",
             Value::IpSet(..) => "ips",
             Value::MultiGenerator(..) => "multi-gen",
+            Value::MatchGenerator(..) => "match-gen",
+            Value::CharsGenerator(..) => "chars-gen",
+            Value::LinesGenerator(..) => "lines-gen",
+            Value::RangeGenerator(..) => "range-gen",
         };
         s.to_string()
     }
@@ -1,7 +1,6 @@
 use std::cell::RefCell;
 use std::collections::HashSet;
 use std::convert::TryInto;
-use std::fs;
 use std::io::BufRead;
 use std::io::ErrorKind;
 use std::io::Read;
@@ -550,6 +549,17 @@ impl<'a> Scanner<'a> {
     }
 }
 
+/// A macro for adding a constant to a chunk.  The compiler only adds
+/// constants of types that can be stored in a chunk, so a failure
+/// here indicates a bug in the compiler.
+macro_rules! add_constant {
+    ($chunk:expr, $value:expr) => {
+        $chunk
+            .add_constant($value)
+            .expect("compiler constant must be supported")
+    };
+}
+
 /// A Compiler compiles program code (by way of a scanner) into
 /// bytecode, in the form of a chunk.
 #[derive(Debug)]
@@ -784,7 +794,7 @@ impl Compiler {
                         name_str.as_str().to_string(),
                         None,
                     ))));
-                    let i = chunk.add_constant(name_str_rr);
+                    let i = add_constant!(chunk, name_str_rr);
                     chunk.add_opcode(OpCode::Function);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -813,7 +823,7 @@ impl Compiler {
                 }
                 TokenType::True => {
                     let value_rr = Value::Bool(true);
-                    let i = chunk.add_constant(value_rr);
+                    let i = add_constant!(chunk, value_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -822,7 +832,7 @@ impl Compiler {
                 }
                 TokenType::False => {
                     let value_rr = Value::Bool(false);
-                    let i = chunk.add_constant(value_rr);
+                    let i = add_constant!(chunk, value_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -831,7 +841,7 @@ impl Compiler {
                 }
                 TokenType::Int(n) => {
                     let value_rr = Value::Int(n);
-                    let i = chunk.add_constant(value_rr);
+                    let i = add_constant!(chunk, value_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -840,7 +850,7 @@ impl Compiler {
                 }
                 TokenType::BigInt(n) => {
                     let value_rr = Value::BigInt(n);
-                    let i = chunk.add_constant(value_rr);
+                    let i = add_constant!(chunk, value_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -849,7 +859,7 @@ impl Compiler {
                 }
                 TokenType::Float(n) => {
                     let value_rr = Value::Float(n);
-                    let i = chunk.add_constant(value_rr);
+                    let i = add_constant!(chunk, value_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -967,7 +977,7 @@ impl Compiler {
                                 }
                             }
                             let value_rr = Value::Int(0);
-                            let i = chunk.add_constant(value_rr);
+                            let i = add_constant!(chunk, value_rr);
                             chunk.add_opcode(OpCode::Constant);
                             let i_upper = (i >> 8) & 0xFF;
                             let i_lower = i & 0xFF;
@@ -1021,7 +1031,7 @@ impl Compiler {
                             }
                         }
                         if !success {
-                            let i = chunk.add_constant(last_constant_rr);
+                            let i = add_constant!(chunk, last_constant_rr);
                             chunk.add_opcode(OpCode::Constant);
                             let i_upper = (i >> 8) & 0xFF;
                             let i_lower = i & 0xFF;
@@ -1074,7 +1084,7 @@ impl Compiler {
                             }
                         }
                         if !success {
-                            let i = chunk.add_constant(last_constant_rr);
+                            let i = add_constant!(chunk, last_constant_rr);
                             chunk.add_opcode(OpCode::Constant);
                             let i_upper = (i >> 8) & 0xFF;
                             let i_lower = i & 0xFF;
@@ -1334,7 +1344,7 @@ impl Compiler {
                         let s_rr = Value::String(Rc::new(RefCell::new(
                             StringTriple::new_with_escaped(s_raw, s, None),
                         )));
-                        let i = chunk.add_constant(s_rr);
+                        let i = add_constant!(chunk, s_rr);
 
                         if is_implicit {
                             chunk.add_opcode(OpCode::CallImplicitConstant);
@@ -1354,7 +1364,7 @@ impl Compiler {
                 TokenType::Command(s, params) => {
                     let s_raw = unescape_string(&s);
                     let s_rr = Value::Command(Rc::new(s_raw), Rc::new(params));
-                    let i = chunk.add_constant(s_rr);
+                    let i = add_constant!(chunk, s_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -1364,7 +1374,7 @@ impl Compiler {
                 TokenType::CommandUncaptured(s) => {
                     let s_raw = unescape_string(&s);
                     let s_rr = Value::CommandUncaptured(Rc::new(s_raw));
-                    let i = chunk.add_constant(s_rr);
+                    let i = add_constant!(chunk, s_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -1375,7 +1385,7 @@ impl Compiler {
                 TokenType::CommandExplicit(s, params) => {
                     let s_raw = unescape_string(&s);
                     let s_rr = Value::Command(Rc::new(s_raw), Rc::new(params));
-                    let i = chunk.add_constant(s_rr);
+                    let i = add_constant!(chunk, s_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -1388,7 +1398,7 @@ impl Compiler {
                     let s_rr = Value::String(Rc::new(RefCell::new(
                         StringTriple::new_with_escaped(s_raw, s, None),
                     )));
-                    let i = chunk.add_constant(s_rr);
+                    let i = add_constant!(chunk, s_rr);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -1396,7 +1406,7 @@ impl Compiler {
                     chunk.add_byte(i_lower as u8);
                 }
                 TokenType::Null => {
-                    let i = chunk.add_constant(Value::Null);
+                    let i = add_constant!(chunk, Value::Null);
                     chunk.add_opcode(OpCode::Constant);
                     let i_upper = (i >> 8) & 0xFF;
                     let i_lower = i & 0xFF;
@@ -1459,18 +1469,7 @@ impl Compiler {
     /// Takes a file path as its single argument.  Deserialises a
     /// chunk from that file and returns the chunk.
     pub fn deserialise(&mut self, file: &str) -> Option<Chunk> {
-        let data_res = fs::read(file);
-        match data_res {
-            Ok(data) => {
-                let data_chars: &[u8] = &data;
-                let decoded_res = bincode::deserialize(data_chars);
-                match decoded_res {
-                    Ok(decoded) => Some(decoded),
-                    Err(_) => None,
-                }
-            }
-            Err(_) => None,
-        }
+        Chunk::load_from_path(file).ok()
     }
 }
 
@@ -31,11 +31,10 @@ extern crate tempfile;
 extern crate term_size;
 extern crate termion;
 extern crate unicode_segmentation;
-extern crate utime;
 extern crate xml;
 
 #[macro_use]
 pub mod chunk;
 pub mod compiler;
-mod opcode;
+pub mod opcode;
 pub mod vm;
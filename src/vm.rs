@@ -3,10 +3,14 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::convert::TryInto;
+use std::fmt;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::Write;
 use std::ops::Index;
 use std::ops::IndexMut;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::str;
 use std::str::FromStr;
@@ -15,10 +19,11 @@ use std::sync::Arc;
 
 use indexmap::IndexMap;
 use lazy_static::lazy_static;
+use rand::RngCore;
 use regex::{Regex, RegexBuilder};
 use sysinfo::{System, SystemExt};
 
-use chunk::{print_error, Chunk, GeneratorObject, StringTriple, Value, ValueSD};
+use chunk::{Chunk, GeneratorObject, StringTriple, Value, ValueSD};
 use compiler::Compiler;
 use opcode::{to_opcode, OpCode};
 
@@ -33,6 +38,7 @@ mod vm_io;
 mod vm_ip;
 mod vm_json;
 mod vm_list;
+mod vm_mac;
 mod vm_print;
 mod vm_regex;
 mod vm_sort;
@@ -48,6 +54,33 @@ pub enum ListType {
     Set,
 }
 
+/// Errors that may be returned by the VM (and associated chunk
+/// functions) to embedding code, in place of aborting the process.
+#[derive(Debug)]
+pub enum VmError {
+    /// A value of the given type (per its debug representation)
+    /// cannot be stored as a chunk constant.
+    UnsupportedConstant(String),
+    /// A string value was passed to a function that does not accept
+    /// string values.
+    UnexpectedString,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::UnsupportedConstant(s) => {
+                write!(f, "constant type cannot be added to chunk: {}", s)
+            }
+            VmError::UnexpectedString => {
+                write!(f, "string value not expected")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
 /// For running compiled bytecode.
 pub struct VM {
     /// Whether to print debug information to standard error while
@@ -82,6 +115,38 @@ pub struct VM {
     local_tz: chrono_tz::Tz,
     /// The UTC timezone.
     utc_tz: chrono_tz::Tz,
+    /// The most recent value returned by next-id.
+    last_id: u64,
+    /// The random number generator.  This is the thread-local
+    /// generator, unless a seed has been set by way of srand.
+    rng: Box<dyn RngCore>,
+    /// The writer to which print and println write.  This is
+    /// standard output, unless it has been replaced by way of
+    /// set_output.
+    output: Box<dyn Write>,
+    /// The writer to which error messages are printed.  This is
+    /// standard error, unless it has been replaced by way of
+    /// set_error_output.
+    error_output: RefCell<Box<dyn Write>>,
+    /// The number of try calls currently being evaluated.  While
+    /// this is non-zero, errors are recorded in captured_error
+    /// instead of being printed.
+    error_capture_depth: usize,
+    /// The first error that occurred while evaluating a try call,
+    /// along with its line and column number (if applicable).
+    captured_error: RefCell<Option<(String, Option<(u32, u32)>)>>,
+    /// The working directory prior to the most recent cd call, for
+    /// use by `cd -`.
+    previous_dir: Option<PathBuf>,
+    /// Whether a glob pattern in a command that does not match any
+    /// files should cause an error (instead of being passed to the
+    /// command unchanged).
+    failglob: bool,
+    /// Whether the VM is in shell mode, where a bare word that does
+    /// not resolve to a function, but does name an executable in
+    /// PATH, is run as a command.  (In code mode, which is the
+    /// default, such words are put onto the stack as strings.)
+    shell_mode: bool,
 }
 
 lazy_static! {
@@ -99,9 +164,19 @@ lazy_static! {
         map.insert("&", VM::core_and as fn(&mut VM) -> i32);
         map.insert("/", VM::opcode_divide as fn(&mut VM) -> i32);
         map.insert("<=>", VM::opcode_cmp as fn(&mut VM) -> i32);
+        map.insert("cmp", VM::core_cmp as fn(&mut VM) -> i32);
+        map.insert("vcmp", VM::core_vcmp as fn(&mut VM) -> i32);
+        map.insert("gmin", VM::core_gmin as fn(&mut VM) -> i32);
+        map.insert("gmax", VM::core_gmax as fn(&mut VM) -> i32);
+        map.insert("gsum", VM::core_gsum as fn(&mut VM) -> i32);
+        map.insert("gavg", VM::core_gavg as fn(&mut VM) -> i32);
         map.insert("=", VM::opcode_eq as fn(&mut VM) -> i32);
         map.insert(">", VM::opcode_gt as fn(&mut VM) -> i32);
         map.insert("<", VM::opcode_lt as fn(&mut VM) -> i32);
+        map.insert(">=", VM::opcode_ge as fn(&mut VM) -> i32);
+        map.insert("<=", VM::opcode_le as fn(&mut VM) -> i32);
+        map.insert("!=", VM::opcode_ne as fn(&mut VM) -> i32);
+        map.insert("eq?", VM::core_deep_eq as fn(&mut VM) -> i32);
         map.insert("print", VM::opcode_print as fn(&mut VM) -> i32);
         map.insert("drop", VM::opcode_drop as fn(&mut VM) -> i32);
         map.insert("clear", VM::opcode_clear as fn(&mut VM) -> i32);
@@ -109,6 +184,12 @@ lazy_static! {
         map.insert("over", VM::opcode_over as fn(&mut VM) -> i32);
         map.insert("swap", VM::opcode_swap as fn(&mut VM) -> i32);
         map.insert("rot", VM::opcode_rot as fn(&mut VM) -> i32);
+        map.insert("pick", VM::opcode_pick as fn(&mut VM) -> i32);
+        map.insert("nip", VM::opcode_nip as fn(&mut VM) -> i32);
+        map.insert("tuck", VM::opcode_tuck as fn(&mut VM) -> i32);
+        map.insert("2dup", VM::opcode_2dup as fn(&mut VM) -> i32);
+        map.insert("2drop", VM::opcode_2drop as fn(&mut VM) -> i32);
+        map.insert("2swap", VM::opcode_2swap as fn(&mut VM) -> i32);
         map.insert("depth", VM::opcode_depth as fn(&mut VM) -> i32);
         map.insert("is-null", VM::opcode_isnull as fn(&mut VM) -> i32);
         map.insert("is-list", VM::opcode_islist as fn(&mut VM) -> i32);
@@ -117,8 +198,20 @@ lazy_static! {
         map.insert("open", VM::opcode_open as fn(&mut VM) -> i32);
         map.insert("tempfile", VM::opcode_tempfile as fn(&mut VM) -> i32);
         map.insert("tempdir", VM::opcode_tempdir as fn(&mut VM) -> i32);
+        map.insert("mktemp", VM::core_mktemp as fn(&mut VM) -> i32);
+        map.insert("mktempdir", VM::core_mktempdir as fn(&mut VM) -> i32);
         map.insert("readline", VM::opcode_readline as fn(&mut VM) -> i32);
+        map.insert("read", VM::core_read as fn(&mut VM) -> i32);
         map.insert("println", VM::core_println as fn(&mut VM) -> i32);
+        map.insert("pp", VM::core_pp as fn(&mut VM) -> i32);
+        map.insert("table", VM::core_table as fn(&mut VM) -> i32);
+        map.insert("color", VM::core_color as fn(&mut VM) -> i32);
+        map.insert("progress-bar", VM::core_progress_bar as fn(&mut VM) -> i32);
+        map.insert(
+            "progress-spinner",
+            VM::core_progress_spinner as fn(&mut VM) -> i32,
+        );
+        map.insert("progress-clear", VM::core_progress_clear as fn(&mut VM) -> i32);
         map.insert("rm", VM::core_rm as fn(&mut VM) -> i32);
         map.insert("writeline", VM::core_writeline as fn(&mut VM) -> i32);
         map.insert("close", VM::core_close as fn(&mut VM) -> i32);
@@ -133,37 +226,80 @@ lazy_static! {
         map.insert("stat", VM::core_stat as fn(&mut VM) -> i32);
         map.insert("lstat", VM::core_lstat as fn(&mut VM) -> i32);
         map.insert("ps", VM::core_ps as fn(&mut VM) -> i32);
+        map.insert("sys.mem", VM::core_sys_mem as fn(&mut VM) -> i32);
+        map.insert("sys.cpu", VM::core_sys_cpu as fn(&mut VM) -> i32);
+        map.insert("sys.load", VM::core_sys_load as fn(&mut VM) -> i32);
         map.insert("kill", VM::core_kill as fn(&mut VM) -> i32);
+        map.insert("getpid", VM::core_getpid as fn(&mut VM) -> i32);
         map.insert("m", VM::core_m as fn(&mut VM) -> i32);
         map.insert("s", VM::core_s as fn(&mut VM) -> i32);
         map.insert("c", VM::core_c as fn(&mut VM) -> i32);
+        map.insert("match", VM::core_match as fn(&mut VM) -> i32);
+        map.insert("match-all", VM::core_match_all as fn(&mut VM) -> i32);
+        map.insert("rsplit", VM::core_rsplit as fn(&mut VM) -> i32);
+        map.insert("regex-quote", VM::core_regex_quote as fn(&mut VM) -> i32);
         map.insert("++", VM::core_append as fn(&mut VM) -> i32);
         map.insert("push", VM::opcode_push as fn(&mut VM) -> i32);
         map.insert("unshift", VM::core_unshift as fn(&mut VM) -> i32);
         map.insert("pop", VM::opcode_pop as fn(&mut VM) -> i32);
+        map.insert("nth", VM::core_nth as fn(&mut VM) -> i32);
+        map.insert("slice", VM::core_slice as fn(&mut VM) -> i32);
+        map.insert("insert-at", VM::core_insert_at as fn(&mut VM) -> i32);
+        map.insert("remove-at", VM::core_remove_at as fn(&mut VM) -> i32);
         map.insert("len", VM::core_len as fn(&mut VM) -> i32);
+        map.insert("byte-len", VM::core_byte_len as fn(&mut VM) -> i32);
         map.insert("empty", VM::core_empty as fn(&mut VM) -> i32);
         map.insert("is-dir", VM::core_is_dir as fn(&mut VM) -> i32);
         map.insert("split", VM::core_split as fn(&mut VM) -> i32);
         map.insert("splitr", VM::core_splitr as fn(&mut VM) -> i32);
+        map.insert("chars", VM::core_chars as fn(&mut VM) -> i32);
+        map.insert("lines", VM::core_lines as fn(&mut VM) -> i32);
+        map.insert("squeeze-ws", VM::core_squeeze_ws as fn(&mut VM) -> i32);
+        map.insert("count-str", VM::core_count_str as fn(&mut VM) -> i32);
         map.insert("get", VM::core_get as fn(&mut VM) -> i32);
         map.insert("set", VM::core_set as fn(&mut VM) -> i32);
         map.insert("keys", VM::core_keys as fn(&mut VM) -> i32);
         map.insert("values", VM::core_values as fn(&mut VM) -> i32);
         map.insert("each", VM::core_each as fn(&mut VM) -> i32);
+        map.insert("hash-eq", VM::core_hash_eq as fn(&mut VM) -> i32);
+        map.insert("flatten-hash", VM::core_flatten_hash as fn(&mut VM) -> i32);
+        map.insert("unflatten-hash", VM::core_unflatten_hash as fn(&mut VM) -> i32);
         map.insert("from-json", VM::core_from_json as fn(&mut VM) -> i32);
         map.insert("to-json", VM::core_to_json as fn(&mut VM) -> i32);
         map.insert("from-xml", VM::core_from_xml as fn(&mut VM) -> i32);
         map.insert("to-xml", VM::core_to_xml as fn(&mut VM) -> i32);
         map.insert("bool", VM::opcode_bool as fn(&mut VM) -> i32);
+        map.insert("and", VM::core_bool_and as fn(&mut VM) -> i32);
+        map.insert("or", VM::core_bool_or as fn(&mut VM) -> i32);
+        map.insert("not", VM::core_bool_not as fn(&mut VM) -> i32);
+        map.insert("assert", VM::core_assert as fn(&mut VM) -> i32);
+        map.insert("assert-msg", VM::core_assert_msg as fn(&mut VM) -> i32);
+        map.insert("assert-eq", VM::core_assert_eq as fn(&mut VM) -> i32);
+        map.insert("coalesce", VM::core_coalesce as fn(&mut VM) -> i32);
+        map.insert("default", VM::core_default as fn(&mut VM) -> i32);
+        map.insert("??", VM::core_default as fn(&mut VM) -> i32);
+        map.insert("try", VM::core_try as fn(&mut VM) -> i32);
         map.insert("str", VM::opcode_str as fn(&mut VM) -> i32);
         map.insert("int", VM::opcode_int as fn(&mut VM) -> i32);
         map.insert("float", VM::opcode_flt as fn(&mut VM) -> i32);
         map.insert("rand", VM::opcode_rand as fn(&mut VM) -> i32);
+        map.insert("srand", VM::core_srand as fn(&mut VM) -> i32);
+        map.insert("rand-int", VM::core_rand_int as fn(&mut VM) -> i32);
+        map.insert("sample", VM::core_sample as fn(&mut VM) -> i32);
+        map.insert("shuffle", VM::core_shuffle as fn(&mut VM) -> i32);
+        map.insert(
+            "sample-reservoir",
+            VM::core_reservoir_sample as fn(&mut VM) -> i32,
+        );
+        map.insert("next-id", VM::core_next_id as fn(&mut VM) -> i32);
+        map.insert("uuid", VM::core_uuid as fn(&mut VM) -> i32);
+        map.insert("range", VM::core_range as fn(&mut VM) -> i32);
         map.insert("shift", VM::opcode_shift as fn(&mut VM) -> i32);
         map.insert("join", VM::core_join as fn(&mut VM) -> i32);
         map.insert("|", VM::core_pipe as fn(&mut VM) -> i32);
+        map.insert("set-failglob", VM::core_set_failglob as fn(&mut VM) -> i32);
         map.insert("clone", VM::opcode_clone as fn(&mut VM) -> i32);
+        map.insert("mode", VM::core_mode as fn(&mut VM) -> i32);
         map.insert("now", VM::core_now as fn(&mut VM) -> i32);
         map.insert("lcnow", VM::core_lcnow as fn(&mut VM) -> i32);
         map.insert("strftime", VM::core_strftime as fn(&mut VM) -> i32);
@@ -174,6 +310,11 @@ lazy_static! {
         map.insert("-time", VM::core_subtime as fn(&mut VM) -> i32);
         map.insert("strptime", VM::core_strptime as fn(&mut VM) -> i32);
         map.insert("strptimez", VM::core_strptimez as fn(&mut VM) -> i32);
+        map.insert(
+            "humanize-duration",
+            VM::core_humanize_duration as fn(&mut VM) -> i32,
+        );
+        map.insert("parse-duration", VM::core_parse_duration as fn(&mut VM) -> i32);
         map.insert("ip", VM::core_ip as fn(&mut VM) -> i32);
         map.insert("ip.from-int", VM::core_ip_from_int as fn(&mut VM) -> i32);
         map.insert("ip.addr", VM::core_ip_addr as fn(&mut VM) -> i32);
@@ -184,10 +325,31 @@ lazy_static! {
             "ip.last-addr-int",
             VM::core_ip_last_addr_int as fn(&mut VM) -> i32,
         );
+        map.insert("ip.network", VM::core_ip_network as fn(&mut VM) -> i32);
+        map.insert("ip.broadcast", VM::core_ip_broadcast as fn(&mut VM) -> i32);
+        map.insert("ip.next", VM::core_ip_next as fn(&mut VM) -> i32);
+        map.insert("ip.prev", VM::core_ip_prev as fn(&mut VM) -> i32);
+        map.insert("ip.random", VM::core_ip_random as fn(&mut VM) -> i32);
         map.insert("ip.size", VM::core_ip_size as fn(&mut VM) -> i32);
+        map.insert("ip.is-private", VM::core_ip_is_private as fn(&mut VM) -> i32);
+        map.insert("ip.is-loopback", VM::core_ip_is_loopback as fn(&mut VM) -> i32);
+        map.insert(
+            "ip.is-multicast",
+            VM::core_ip_is_multicast as fn(&mut VM) -> i32,
+        );
         map.insert("ip.version", VM::core_ip_version as fn(&mut VM) -> i32);
         map.insert("ip.prefixes", VM::core_ip_prefixes as fn(&mut VM) -> i32);
+        map.insert("ip.summarize", VM::core_ip_summarize as fn(&mut VM) -> i32);
+        map.insert("ip.split-by-count", VM::core_ip_split as fn(&mut VM) -> i32);
+        map.insert("ip.exclude", VM::core_ip_exclude as fn(&mut VM) -> i32);
+        map.insert("ip.ptr-zone", VM::core_ip_ptr_zone as fn(&mut VM) -> i32);
         map.insert("ips", VM::core_ips as fn(&mut VM) -> i32);
+        map.insert("ips.cidrs", VM::core_ips_cidrs as fn(&mut VM) -> i32);
+        map.insert("ips.size", VM::core_ips_size as fn(&mut VM) -> i32);
+        map.insert("mac", VM::core_mac as fn(&mut VM) -> i32);
+        map.insert("mac.to-int", VM::core_mac_to_int as fn(&mut VM) -> i32);
+        map.insert("mac.from-int", VM::core_mac_from_int as fn(&mut VM) -> i32);
+        map.insert("mac.oui", VM::core_mac_oui as fn(&mut VM) -> i32);
         map.insert("union", VM::core_union as fn(&mut VM) -> i32);
         map.insert("isect", VM::core_isect as fn(&mut VM) -> i32);
         map.insert("diff", VM::core_diff as fn(&mut VM) -> i32);
@@ -199,19 +361,38 @@ lazy_static! {
         map.insert("is-float", VM::opcode_is_flt as fn(&mut VM) -> i32);
         map.insert("is-set", VM::opcode_is_set as fn(&mut VM) -> i32);
         map.insert("is-hash", VM::opcode_is_hash as fn(&mut VM) -> i32);
+        map.insert("expect-type", VM::core_expect_type as fn(&mut VM) -> i32);
         map.insert("bigint", VM::opcode_bigint as fn(&mut VM) -> i32);
         map.insert("chr", VM::core_chr as fn(&mut VM) -> i32);
         map.insert("ord", VM::core_ord as fn(&mut VM) -> i32);
+        map.insert("codepoints", VM::core_codepoints as fn(&mut VM) -> i32);
+        map.insert("from-codepoints", VM::core_from_codepoints as fn(&mut VM) -> i32);
         map.insert("hex", VM::core_hex as fn(&mut VM) -> i32);
         map.insert("oct", VM::core_oct as fn(&mut VM) -> i32);
+        map.insert("bin", VM::core_bin as fn(&mut VM) -> i32);
+        map.insert("to-hex", VM::core_to_hex as fn(&mut VM) -> i32);
+        map.insert("to-oct", VM::core_to_oct as fn(&mut VM) -> i32);
+        map.insert("commafy", VM::core_commafy as fn(&mut VM) -> i32);
+        map.insert("humanize-bytes", VM::core_humanize_bytes as fn(&mut VM) -> i32);
+        map.insert("parse-bytes", VM::core_parse_bytes as fn(&mut VM) -> i32);
+        map.insert("to-bin", VM::core_to_bin as fn(&mut VM) -> i32);
         map.insert("lc", VM::core_lc as fn(&mut VM) -> i32);
         map.insert("lcfirst", VM::core_lcfirst as fn(&mut VM) -> i32);
         map.insert("uc", VM::core_uc as fn(&mut VM) -> i32);
         map.insert("ucfirst", VM::core_ucfirst as fn(&mut VM) -> i32);
+        map.insert("title-case", VM::core_title_case as fn(&mut VM) -> i32);
+        map.insert("swap-case", VM::core_swap_case as fn(&mut VM) -> i32);
         map.insert("reverse", VM::core_reverse as fn(&mut VM) -> i32);
         map.insert("sqrt", VM::core_sqrt as fn(&mut VM) -> i32);
         map.insert("**", VM::core_exp as fn(&mut VM) -> i32);
         map.insert("abs", VM::core_abs as fn(&mut VM) -> i32);
+        map.insert("roundto", VM::core_roundto as fn(&mut VM) -> i32);
+        map.insert("is-nan", VM::core_is_nan as fn(&mut VM) -> i32);
+        map.insert("is-inf", VM::core_is_inf as fn(&mut VM) -> i32);
+        map.insert("is-finite", VM::core_is_finite as fn(&mut VM) -> i32);
+        map.insert("nan", VM::core_nan as fn(&mut VM) -> i32);
+        map.insert("inf", VM::core_inf as fn(&mut VM) -> i32);
+        map.insert("-inf", VM::core_neg_inf as fn(&mut VM) -> i32);
         map.insert("delete", VM::core_delete as fn(&mut VM) -> i32);
         map.insert("exists", VM::core_exists as fn(&mut VM) -> i32);
         map.insert("chmod", VM::core_chmod as fn(&mut VM) -> i32);
@@ -219,16 +400,31 @@ lazy_static! {
         map.insert("mkdir", VM::core_mkdir as fn(&mut VM) -> i32);
         map.insert("rmdir", VM::core_rmdir as fn(&mut VM) -> i32);
         map.insert("link", VM::core_link as fn(&mut VM) -> i32);
+        map.insert("symlink", VM::core_symlink as fn(&mut VM) -> i32);
+        map.insert("readlink", VM::core_readlink as fn(&mut VM) -> i32);
         map.insert("sleep", VM::core_sleep as fn(&mut VM) -> i32);
         map.insert("env", VM::core_env as fn(&mut VM) -> i32);
         map.insert("getenv", VM::core_getenv as fn(&mut VM) -> i32);
         map.insert("setenv", VM::core_setenv as fn(&mut VM) -> i32);
+        map.insert("unsetenv", VM::core_unsetenv as fn(&mut VM) -> i32);
+        map.insert("expand", VM::core_expand as fn(&mut VM) -> i32);
+        map.insert("env-get", VM::core_getenv as fn(&mut VM) -> i32);
+        map.insert("env-set", VM::core_setenv as fn(&mut VM) -> i32);
+        map.insert("env-unset", VM::core_unsetenv as fn(&mut VM) -> i32);
+        map.insert("env-all", VM::core_env as fn(&mut VM) -> i32);
         map.insert("md5", VM::core_md5 as fn(&mut VM) -> i32);
         map.insert("sha1", VM::core_sha1 as fn(&mut VM) -> i32);
         map.insert("sha256", VM::core_sha256 as fn(&mut VM) -> i32);
         map.insert("sha512", VM::core_sha512 as fn(&mut VM) -> i32);
+        map.insert("hash-of", VM::core_hash_of as fn(&mut VM) -> i32);
         map.insert("sort", VM::core_sort as fn(&mut VM) -> i32);
         map.insert("sortp", VM::core_sortp as fn(&mut VM) -> i32);
+        map.insert("partition", VM::core_partition as fn(&mut VM) -> i32);
+        map.insert("group-by", VM::core_group_by as fn(&mut VM) -> i32);
+        map.insert("any?", VM::core_any as fn(&mut VM) -> i32);
+        map.insert("all?", VM::core_all as fn(&mut VM) -> i32);
+        map.insert("find", VM::core_find as fn(&mut VM) -> i32);
+        map.insert("find-index", VM::core_find_index as fn(&mut VM) -> i32);
         map.insert("fmt", VM::core_fmt as fn(&mut VM) -> i32);
         map
     };
@@ -244,24 +440,18 @@ lazy_static! {
         set.insert("lsh");
         set.insert("2rot");
         set.insert("lshr");
-        set.insert("<=");
         set.insert("lsr");
         set.insert("product");
-        set.insert(">=");
         set.insert("map");
-        set.insert("range");
         set.insert("all");
         set.insert("map-generator");
-        set.insert("and");
         set.insert("map-list");
-        set.insert("shuffle");
         set.insert("any");
         set.insert("max");
         set.insert("chomp");
         set.insert("grep");
         set.insert("min");
         set.insert("grep-generator");
-        set.insert("nip");
         set.insert("f<");
         set.insert("grep-list");
         set.insert("no-upwards");
@@ -270,15 +460,17 @@ lazy_static! {
         set.insert("none");
         set.insert("take");
         set.insert("first");
-        set.insert("not");
         set.insert("take-all");
         set.insert("foldl");
         set.insert("is-list-or-set");
         set.insert("notall");
         set.insert("uniq");
+        set.insert("with-bar");
+        set.insert("progress");
+        set.insert("stdin-lines");
+        set.insert("batch");
         set.insert("for");
         set.insert("ls");
-        set.insert("or");
         set.insert("after");
         set.insert("before");
         set.insert("pairwise");
@@ -341,6 +533,23 @@ lazy_static! {
     static ref RE_ERROR_PART: Regex = Regex::new(".*error:\\s*").unwrap();
 }
 
+/// Returns a boolean indicating whether the given name (which must
+/// not contain a path separator) is that of an executable file in one
+/// of the directories in PATH.
+fn is_executable_in_path(name: &str) -> bool {
+    if name.is_empty() || name.contains('/') {
+        return false;
+    }
+    let path = match std::env::var_os("PATH") {
+        Some(path) => path,
+        None => return false,
+    };
+    std::env::split_paths(&path).any(|dir| match std::fs::metadata(dir.join(name)) {
+        Ok(meta) => meta.is_file() && meta.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    })
+}
+
 impl VM {
     pub fn new(
         print_stack: bool,
@@ -364,15 +573,42 @@ impl VM {
             regexes: HashMap::new(),
             local_tz: chrono_tz::Tz::from_str(&ltz).unwrap(),
             utc_tz: chrono_tz::Tz::from_str("UTC").unwrap(),
+            last_id: 0,
+            rng: Box::new(rand::thread_rng()),
+            output: Box::new(std::io::stdout()),
+            error_output: RefCell::new(Box::new(std::io::stderr())),
+            error_capture_depth: 0,
+            captured_error: RefCell::new(None),
+            previous_dir: None,
+            failglob: false,
+            shell_mode: false,
         }
     }
 
-    /// Takes a chunk, an instruction index, and an error message as its
-    /// arguments.  Prints the error message, including filename, line number
-    /// and column number elements (if applicable).
-    pub fn print_error(&self, error: &str) {
-        let point = self.chunk.borrow().get_point(self.i);
-        let name = &self.chunk.borrow().name;
+    /// Takes a writer as its single argument.  Output from print and
+    /// println is written to this writer, instead of to standard
+    /// output.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Takes a writer as its single argument.  Error messages
+    /// reported by the VM are written to this writer, instead of to
+    /// standard error.
+    pub fn set_error_output(&mut self, output: Box<dyn Write>) {
+        self.error_output = RefCell::new(output);
+    }
+
+    /// Takes a chunk, an index into that chunk, and an error message
+    /// as its arguments.  Writes the error message, including
+    /// filename, line number and column number elements (if
+    /// applicable), to the error output.
+    fn print_error_at(&self, chunk: &Rc<RefCell<Chunk>>, i: usize, error: &str) {
+        if self.capture_error(chunk, i, error) {
+            return;
+        }
+        let point = chunk.borrow().get_point(i);
+        let name = &chunk.borrow().name;
         let error_start = if name == "(main)" {
             String::new()
         } else {
@@ -380,10 +616,55 @@ impl VM {
         };
         match point {
             Some((line, col)) => {
-                eprintln!("{}{}:{}: {}", error_start, line, col, error);
+                self.write_error(&format!("{}{}:{}: {}", error_start, line, col, error));
             }
             _ => {
-                eprintln!("{}{}", error_start, error);
+                self.write_error(&format!("{}{}", error_start, error));
+            }
+        }
+    }
+
+    /// Takes a chunk, an index into that chunk, and an error message
+    /// as its arguments.  If a try call is being evaluated, records
+    /// the error (unless an error has already been recorded) and
+    /// returns true.  Otherwise, returns false.
+    fn capture_error(&self, chunk: &Rc<RefCell<Chunk>>, i: usize, error: &str) -> bool {
+        if self.error_capture_depth == 0 {
+            return false;
+        }
+        let mut captured_error = self.captured_error.borrow_mut();
+        if captured_error.is_none() {
+            let point = chunk.borrow().get_point(i);
+            *captured_error = Some((error.to_string(), point));
+        }
+        true
+    }
+
+    /// Takes an error message as its single argument.  Writes the
+    /// message, followed by a newline, to the error output.
+    fn write_error(&self, message: &str) {
+        let mut output = self.error_output.borrow_mut();
+        let _ = writeln!(output, "{}", message);
+        let _ = output.flush();
+    }
+
+    /// Takes an error message as its single argument.  Prints the
+    /// error message, including filename, line number and column
+    /// number elements (if applicable), followed by the location of
+    /// each of the calls that led to the current chunk being run.
+    pub fn print_error(&self, error: &str) {
+        self.print_error_at(&self.chunk, self.i, error);
+        if self.error_capture_depth > 0 {
+            return;
+        }
+        for (chunk, i) in self.call_stack_chunks.iter().rev() {
+            let chunk = chunk.borrow();
+            if let Some((line, col)) = chunk.get_point(*i) {
+                if chunk.name == "(main)" {
+                    self.write_error(&format!("  called from {}:{}", line, col));
+                } else {
+                    self.write_error(&format!("  called from {}:{}:{}", chunk.name, line, col));
+                }
             }
         }
     }
@@ -395,6 +676,54 @@ impl VM {
         1
     }
 
+    /// Takes a mode name ("shell" or "code") as its single argument,
+    /// and switches the VM into that mode.  In shell mode, a bare
+    /// word that does not resolve to a function, but does name an
+    /// executable in PATH, is run as a command (as though it were
+    /// prefixed with `$`).  In code mode, such words are put onto the
+    /// stack as strings.
+    pub fn core_mode(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("mode requires one argument");
+            return 0;
+        }
+
+        let mode_rr = self.stack.pop().unwrap();
+        let mode_opt: Option<&str>;
+        to_str!(mode_rr, mode_opt);
+
+        match mode_opt {
+            Some("shell") => {
+                self.shell_mode = true;
+                1
+            }
+            Some("code") => {
+                self.shell_mode = false;
+                1
+            }
+            _ => {
+                self.print_error("mode argument must be 'shell' or 'code'");
+                0
+            }
+        }
+    }
+
+    /// Handles a bare word that does not resolve to a function.  In
+    /// shell mode, if the word names an executable in PATH, it is run
+    /// as a command.  Otherwise, the word is put onto the stack as a
+    /// string.
+    fn call_bare_word(&mut self, s: &str) -> bool {
+        if self.shell_mode && is_executable_in_path(s) {
+            return self.core_command_uncaptured(s) == 1;
+        }
+        let value_rr = Value::String(Rc::new(RefCell::new(StringTriple::new(
+            s.to_string(),
+            None,
+        ))));
+        self.stack.push(value_rr);
+        true
+    }
+
     /// Prints the stack.
     pub fn opcode_printstack(&mut self) -> i32 {
         let res = self.print_stack(self.chunk.clone(), self.i, true);
@@ -405,6 +734,67 @@ impl VM {
         }
     }
 
+    /// Takes a callable as its single argument.  Calls the callable.
+    /// If the call succeeds, its results are left on the stack.  If
+    /// it fails, the stack is restored to its state prior to the
+    /// call, and a hash describing the error (with "message", "line"
+    /// and "col" keys) is pushed onto the stack.
+    pub fn core_try(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("try requires one argument");
+            return 0;
+        }
+        let fn_rr = self.stack.pop().unwrap();
+
+        let stack = self.stack.clone();
+        let call_stack_len = self.call_stack_chunks.len();
+        let scopes_len = self.scopes.len();
+        let local_var_stack = self.local_var_stack.clone();
+        let chunk = self.chunk.clone();
+        let i = self.i;
+
+        self.error_capture_depth += 1;
+        let res = self.call(OpCode::Call, fn_rr);
+        self.error_capture_depth -= 1;
+        let captured_error = self.captured_error.borrow_mut().take();
+        if res {
+            return 1;
+        }
+        let (message, point) = match captured_error {
+            Some(ce) => ce,
+            /* The call was interrupted, rather than failing with
+             * an error, so the interruption is not caught. */
+            None => {
+                return 0;
+            }
+        };
+
+        self.stack = stack;
+        self.call_stack_chunks.truncate(call_stack_len);
+        self.scopes.truncate(scopes_len);
+        self.local_var_stack = local_var_stack;
+        self.chunk = chunk;
+        self.i = i;
+
+        let mut map = IndexMap::new();
+        map.insert(
+            "message".to_string(),
+            Value::String(Rc::new(RefCell::new(StringTriple::new(message, None)))),
+        );
+        match point {
+            Some((line, col)) => {
+                map.insert("line".to_string(), Value::Int(line as i32));
+                map.insert("col".to_string(), Value::Int(col as i32));
+            }
+            None => {
+                map.insert("line".to_string(), Value::Null);
+                map.insert("col".to_string(), Value::Null);
+            }
+        }
+        self.stack.push(Value::Hash(Rc::new(RefCell::new(map))));
+        1
+    }
+
     /// Converts a callable (e.g. a string) into a function object.
     /// Calling funcall on the function object will be faster than
     /// calling it on the original string.
@@ -807,17 +1197,10 @@ impl VM {
         }
 
         if is_implicit {
-            let value_rr = Value::String(Rc::new(RefCell::new(StringTriple::new(
-                s.to_string(),
-                None,
-            ))));
-            self.stack.push(value_rr);
-        } else {
-            self.print_error("function not found");
-            return false;
+            return self.call_bare_word(s);
         }
-
-        true
+        self.print_error("function not found");
+        false
     }
 
     /// Attempts to set the constant value at the given index with the
@@ -827,8 +1210,11 @@ impl VM {
         {
             let cfb = &self.chunk.borrow().constant_values;
             let cv = cfb.get(function_str_index as usize);
+            /* A string value here is the initial value for the
+             * constant (see Chunk::init_constant_values), and needs
+             * to be replaced with the callable object. */
             match cv {
-                Some(Value::Null) | None => {
+                Some(Value::Null) | Some(Value::String(_)) | None => {
                     not_present = true;
                 }
                 _ => {
@@ -838,16 +1224,10 @@ impl VM {
         }
         if not_present {
             let sv = self.string_to_callable(function_str);
-            if let Some(v) = sv {
-                self.chunk
-                    .borrow_mut()
-                    .constant_values
-                    .resize(function_str_index as usize, Value::Null);
-                self.chunk
-                    .borrow_mut()
-                    .constant_values
-                    .insert(function_str_index as usize, v);
-            }
+            let v = sv.unwrap_or(Value::Null);
+            self.chunk
+                .borrow_mut()
+                .set_constant_value(function_str_index, v);
         }
     }
 
@@ -988,8 +1368,10 @@ impl VM {
                         let len = self.stack.len();
                         let v1_rr = self.stack.get_mut(len - 1).unwrap();
                         if let Value::Int(ref mut n1) = v1_rr {
-                            *n1 += n;
-                            done = true;
+                            if let Some(sum) = n1.checked_add(n) {
+                                *n1 = sum;
+                                done = true;
+                            }
                         };
                     }
                     if !done {
@@ -1078,8 +1460,10 @@ impl VM {
                         let len = self.stack.len();
                         let v1_rr = self.stack.get_mut(len - 1).unwrap();
                         if let Value::Int(ref mut n1) = v1_rr {
-                            *n1 /= n;
-                            done = true;
+                            if let Some(n2) = n1.checked_div(n) {
+                                *n1 = n2;
+                                done = true;
+                            }
                         }
                     }
                     if !done {
@@ -1251,14 +1635,7 @@ impl VM {
                         }
                     }
                     if copy {
-                        chunk
-                            .borrow_mut()
-                            .constant_values
-                            .resize(i2 as usize, Value::Null);
-                        chunk
-                            .borrow_mut()
-                            .constant_values
-                            .insert(i2 as usize, value_rr);
+                        chunk.borrow_mut().set_constant_value(i2 as i32, value_rr);
                         let cfb = &chunk.borrow().constant_values;
                         let cv_value_rr = cfb.get(i2 as usize).unwrap().clone();
                         match cv_value_rr {
@@ -1297,7 +1674,7 @@ impl VM {
                                 inst = true;
                             }
                             Some(_) => {
-                                let value_rr = iv.unwrap().clone();
+                                let value_rr = iv.unwrap().value_clone();
                                 self.stack.push(value_rr);
                             }
                             _ => {
@@ -1309,13 +1686,8 @@ impl VM {
                         let value_rr = chunk.borrow().get_constant(i2 as i32);
                         chunk
                             .borrow_mut()
-                            .constant_values
-                            .resize(i2 as usize, Value::Null);
-                        chunk
-                            .borrow_mut()
-                            .constant_values
-                            .insert(i2 as usize, value_rr.clone());
-                        self.stack.push(value_rr.clone());
+                            .set_constant_value(i2 as i32, value_rr.value_clone());
+                        self.stack.push(value_rr);
                     }
                 }
                 OpCode::CallConstant | OpCode::CallImplicitConstant => {
@@ -1328,8 +1700,6 @@ impl VM {
                     let value_sd = chunk.borrow().constants[i2 as usize].clone();
                     match value_sd {
                         ValueSD::String(st, _) => {
-                            self.i = i;
-
                             /* todo: the two lookups here may be affecting
                              * performance. */
                             let fsi = (i2 as u32).try_into().unwrap();
@@ -1338,10 +1708,9 @@ impl VM {
                             if let Value::Null = cv {
                                 match op {
                                     OpCode::CallImplicitConstant => {
-                                        let value_rr = Value::String(Rc::new(RefCell::new(
-                                            StringTriple::new(st.to_string(), None),
-                                        )));
-                                        self.stack.push(value_rr);
+                                        if !self.call_bare_word(&st) {
+                                            return 0;
+                                        }
                                         i += 1;
                                         continue;
                                     }
@@ -1665,13 +2034,17 @@ impl VM {
 
                     match error_str_opt {
                         Some(s) => {
-                            let err_str = format!("{}:{}: {}", line, col, s);
-                            eprintln!("{}", err_str);
+                            if !self.capture_error(&chunk, i, s) {
+                                let err_str = format!("{}:{}: {}", line, col, s);
+                                self.write_error(&err_str);
+                            }
                             return 0;
                         }
                         None => {
-                            let err_str = format!("{}:{}: {}", line, col, "(unknown error)");
-                            eprintln!("{}", err_str);
+                            if !self.capture_error(&chunk, i, "(unknown error)") {
+                                let err_str = format!("{}:{}: {}", line, col, "(unknown error)");
+                                self.write_error(&err_str);
+                            }
                             return 0;
                         }
                     }
@@ -1697,7 +2070,7 @@ impl VM {
         }
 
         if list_count > 0 {
-            print_error(chunk, i, "unterminated list start");
+            self.print_error_at(&chunk, i, "unterminated list start");
             return 0;
         }
 
@@ -1738,4 +2111,26 @@ impl VM {
         }
         Some(chunk)
     }
+
+    /// Returns the number of values on the stack.
+    pub fn stack_len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns a copy of the value at the given depth from the top of
+    /// the stack (where zero is the top of the stack), or None if the
+    /// stack is not deep enough.
+    pub fn peek(&self, n: usize) -> Option<Value> {
+        let len = self.stack.len();
+        if n >= len {
+            return None;
+        }
+        Some(self.stack[len - 1 - n].clone())
+    }
+
+    /// Removes the value from the top of the stack and returns it, or
+    /// None if the stack is empty.
+    pub fn pop_result(&mut self) -> Option<Value> {
+        self.stack.pop()
+    }
 }
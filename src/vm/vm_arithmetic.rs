@@ -1,7 +1,9 @@
+use std::mem;
+
 use num::FromPrimitive;
 use num::ToPrimitive;
 use num_bigint::BigInt;
-use num_traits::Signed;
+use num_traits::{Signed, Zero};
 
 use chunk::Value;
 use vm::*;
@@ -13,7 +15,7 @@ fn int_to_bigint(i: i32) -> Value {
 
 /// Convert a bigint to a floating-point value.
 fn bigint_to_float(i: &BigInt) -> Value {
-    Value::Float(FromPrimitive::from_u64(i.to_u64().unwrap()).unwrap())
+    Value::Float(i.to_f64().unwrap())
 }
 
 /// Convert an i32 to a floating-point value.
@@ -57,8 +59,35 @@ fn multiply_ints(n1: i32, n2: i32) -> Value {
     }
 }
 
-/// Divide one integer by anotherand return the result value.  Promote
-/// to bigint if the value cannot be stored in an i32.
+/// Coerce a value to a number in the same way as the arithmetic
+/// forms (e.g. +): numbers are returned as-is, and other values are
+/// converted to an int, a bigint, or a float, in that order of
+/// preference.  Returns None if the value cannot be converted.
+fn to_number(v: &Value) -> Option<Value> {
+    match v {
+        Value::Int(_) | Value::BigInt(_) | Value::Float(_) => Some(v.clone()),
+        _ => v
+            .to_int()
+            .map(Value::Int)
+            .or_else(|| v.to_bigint().map(Value::BigInt))
+            .or_else(|| v.to_float().map(Value::Float)),
+    }
+}
+
+/// Check whether a value is zero, for the purposes of guarding
+/// against division by zero.
+fn is_zero_divisor(v: &Value) -> bool {
+    match v {
+        Value::Int(n) => *n == 0,
+        Value::BigInt(n) => n.is_zero(),
+        Value::Float(f) => *f == 0.0,
+        _ => v.to_float() == Some(0.0),
+    }
+}
+
+/// Divide one integer by another and return the result value.
+/// Promote to bigint if the value cannot be stored in an i32 (i.e.
+/// i32::MIN / -1).  The divisor must not be zero.
 fn divide_ints(n1: i32, n2: i32) -> Value {
     match n2.checked_div(n1) {
         Some(n3) => Value::Int(n3),
@@ -132,8 +161,10 @@ impl VM {
         if let (Value::Int(n1), Value::Int(ref mut n2)) =
             (&v1_rr, self.stack.get_mut(len - 2).unwrap())
         {
-            *n2 += n1;
-            done = true;
+            if let Some(sum) = n2.checked_add(*n1) {
+                *n2 = sum;
+                done = true;
+            }
         }
 
         if !done {
@@ -376,8 +407,9 @@ impl VM {
         }
     }
 
-    /// Takes two values as its arguments, subtracts them, and places
-    /// the result onto the stack.
+    /// Takes two values as its arguments, divides the first by the
+    /// second, and places the result onto the stack.  Division by
+    /// zero is an error for all numeric types.
     pub fn opcode_divide(&mut self) -> i32 {
         let len = self.stack.len();
         if len < 2 {
@@ -386,13 +418,20 @@ impl VM {
         }
 
         let v1_rr = self.stack.pop().unwrap();
+        if is_zero_divisor(&v1_rr) {
+            self.stack.pop();
+            self.print_error("division by zero");
+            return 0;
+        }
         let mut done = false;
 
         if let (Value::Int(n1), Value::Int(ref mut n2)) =
             (&v1_rr, self.stack.get_mut(len - 2).unwrap())
         {
-            *n2 /= n1;
-            done = true;
+            if let Some(n3) = n2.checked_div(*n1) {
+                *n2 = n3;
+                done = true;
+            }
         }
 
         if !done {
@@ -410,7 +449,8 @@ impl VM {
 
     /// Helper function for checking whether two values are equal.
     /// Returns 1 if they are equal, 0 if they are not, and -1 if they
-    /// cannot be compared.
+    /// cannot be compared.  Floating-point comparison follows IEEE
+    /// 754, so NaN is not equal to any value, including itself.
     pub fn opcode_eq_inner(&mut self, v1: &Value, v2: &Value) -> i32 {
         match (v1, v2) {
             (Value::IpSet(s1), Value::IpSet(s2)) => {
@@ -757,6 +797,87 @@ impl VM {
         1
     }
 
+    /// Takes two values as its arguments, checks whether the first
+    /// is greater than or equal to the second, and places the result
+    /// onto the stack.
+    pub fn opcode_ge(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error(">= requires two arguments");
+            return 0;
+        }
+
+        let v1_rr = self.stack.pop().unwrap();
+        let v2_rr = self.stack.pop().unwrap();
+
+        let res = self.opcode_gt_inner(&v1_rr, &v2_rr);
+        let res = if res == 0 {
+            self.opcode_eq_inner(&v1_rr, &v2_rr)
+        } else {
+            res
+        };
+        if res == 1 {
+            self.stack.push(Value::Bool(true));
+        } else if res == 0 {
+            self.stack.push(Value::Bool(false));
+        } else {
+            self.print_error(">= requires two comparable values");
+            return 0;
+        }
+        1
+    }
+
+    /// Takes two values as its arguments, checks whether the first
+    /// is less than or equal to the second, and places the result
+    /// onto the stack.
+    pub fn opcode_le(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("<= requires two arguments");
+            return 0;
+        }
+
+        let v1_rr = self.stack.pop().unwrap();
+        let v2_rr = self.stack.pop().unwrap();
+
+        let res = self.opcode_lt_inner(&v1_rr, &v2_rr);
+        let res = if res == 0 {
+            self.opcode_eq_inner(&v1_rr, &v2_rr)
+        } else {
+            res
+        };
+        if res == 1 {
+            self.stack.push(Value::Bool(true));
+        } else if res == 0 {
+            self.stack.push(Value::Bool(false));
+        } else {
+            self.print_error("<= requires two comparable values");
+            return 0;
+        }
+        1
+    }
+
+    /// Takes two values as its arguments, compares them for
+    /// inequality, and places the result onto the stack.
+    pub fn opcode_ne(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("!= requires two arguments");
+            return 0;
+        }
+
+        let v1_rr = self.stack.pop().unwrap();
+        let v2_rr = self.stack.pop().unwrap();
+
+        let res = self.opcode_eq_inner(&v1_rr, &v2_rr);
+        if res == 1 {
+            self.stack.push(Value::Bool(false));
+        } else if res == 0 {
+            self.stack.push(Value::Bool(true));
+        } else {
+            self.print_error("!= requires two comparable values");
+            return 0;
+        }
+        1
+    }
+
     /// Helper function for comparing two values.  Return 1 if the
     /// second value is greater than the first, 0 if the two values
     /// are equal, -1 if the second value is less than the first, and
@@ -771,7 +892,7 @@ impl VM {
             (Value::Float(_), Value::BigInt(n2)) => self.opcode_cmp_inner(v1, &bigint_to_float(n2)),
             (Value::Int(n1), Value::Float(_)) => self.opcode_cmp_inner(&int_to_float(*n1), v2),
             (Value::Float(_), Value::Int(n2)) => self.opcode_cmp_inner(v1, &int_to_float(*n2)),
-            (Value::Float(n1), Value::Float(n2)) => n2.partial_cmp(n1).unwrap() as i32,
+            (Value::Float(n1), Value::Float(n2)) => n2.partial_cmp(n1).map_or(-2, |o| o as i32),
             (Value::DateTimeNT(d1), Value::DateTimeNT(d2)) => d2.cmp(d1) as i32,
             (Value::DateTimeOT(d1), Value::DateTimeOT(d2)) => d2.cmp(d1) as i32,
             (Value::DateTimeNT(d1), Value::DateTimeOT(d2)) => {
@@ -780,7 +901,7 @@ impl VM {
                 } else if d2 == d1 {
                     0
                 } else {
-                    -1
+                    1
                 }
             }
             (Value::DateTimeOT(d1), Value::DateTimeNT(d2)) => {
@@ -789,7 +910,7 @@ impl VM {
                 } else if d2 == d1 {
                     0
                 } else {
-                    -1
+                    1
                 }
             }
             (_, _) => {
@@ -806,7 +927,7 @@ impl VM {
                 let n1_opt = v1.to_float();
                 let n2_opt = v2.to_float();
                 if let (Some(n1), Some(n2)) = (n1_opt, n2_opt) {
-                    return n2.partial_cmp(&n1).unwrap() as i32;
+                    return n2.partial_cmp(&n1).map_or(-2, |o| o as i32);
                 }
 
                 let i1_str_opt: Option<&str>;
@@ -823,12 +944,13 @@ impl VM {
         }
     }
 
-    /// Takes two values as its arguments, compares them, and places
-    /// the result on the stack (-1 for less than, 0 for equal, and 1
-    /// for greater than).
-    pub fn opcode_cmp(&mut self) -> i32 {
+    /// Takes the name of the calling form as its argument, and
+    /// implements `<=>` and `cmp`.  Takes two values from the stack,
+    /// compares them, and places the result on the stack (-1 for less
+    /// than, 0 for equal, and 1 for greater than).
+    fn cmp_named(&mut self, form: &str) -> i32 {
         if self.stack.len() < 2 {
-            self.print_error("<=> requires two arguments");
+            self.print_error(&format!("{} requires two arguments", form));
             return 0;
         }
 
@@ -839,12 +961,24 @@ impl VM {
         if res == 1 || res == 0 || res == -1 {
             self.stack.push(Value::Int(res));
         } else {
-            self.print_error("<=> requires two comparable values");
+            self.print_error(&format!("{} requires two comparable values", form));
             return 0;
         }
         1
     }
 
+    /// Takes two values as its arguments, compares them, and places
+    /// the result on the stack (-1 for less than, 0 for equal, and 1
+    /// for greater than).
+    pub fn opcode_cmp(&mut self) -> i32 {
+        self.cmp_named("<=>")
+    }
+
+    /// The same as `<=>`, except that errors refer to `cmp`.
+    pub fn core_cmp(&mut self) -> i32 {
+        self.cmp_named("cmp")
+    }
+
     /// Get the square root of a number.
     pub fn core_sqrt(&mut self) -> i32 {
         if self.stack.is_empty() {
@@ -1066,6 +1200,115 @@ impl VM {
         0
     }
 
+    /// Round a number to the specified number of decimal places.  A
+    /// negative number of places rounds to the left of the decimal
+    /// point (e.g. -2 rounds to the nearest hundred).  This scales,
+    /// rounds, and then divides, so the result is subject to the
+    /// usual binary floating-point imprecision: exact decimal
+    /// rounding is not guaranteed.  The number of places must be
+    /// within the exponent range of a float (-308 to 308).
+    pub fn core_roundto(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("roundto requires two arguments");
+            return 0;
+        }
+
+        let places_rr = self.stack.pop().unwrap();
+        let value_rr = self.stack.pop().unwrap();
+
+        let places_opt = places_rr.to_int();
+        let places = match places_opt {
+            Some(n) => n,
+            None => {
+                self.print_error("second roundto argument must be an integer");
+                return 0;
+            }
+        };
+        if places.unsigned_abs() > f64::MAX_10_EXP as u32 {
+            self.print_error("second roundto argument is out of range");
+            return 0;
+        }
+        let f_opt = value_rr.to_float();
+        let f = match f_opt {
+            Some(f) => f,
+            None => {
+                self.print_error("first roundto argument must be a number");
+                return 0;
+            }
+        };
+
+        let scale = 10f64.powi(places.abs());
+        let rounded = if places >= 0 {
+            (f * scale).round() / scale
+        } else {
+            (f / scale).round() * scale
+        };
+        self.stack.push(Value::Float(rounded));
+        1
+    }
+
+    /// Helper function for the floating-point classification
+    /// predicates.  Pops a number, applies the predicate to it, and
+    /// places the boolean result onto the stack.
+    fn core_float_predicate(&mut self, name: &str, pred: fn(f64) -> bool) -> i32 {
+        if self.stack.is_empty() {
+            let err_str = format!("{} requires one argument", name);
+            self.print_error(&err_str);
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let f_opt = match value_rr {
+            Value::Float(f) => Some(f),
+            Value::Int(_) | Value::BigInt(_) => Some(0.0),
+            _ => value_rr.to_float(),
+        };
+        match f_opt {
+            Some(f) => {
+                self.stack.push(Value::Bool(pred(f)));
+                1
+            }
+            None => {
+                let err_str = format!("{} argument must be a number", name);
+                self.print_error(&err_str);
+                0
+            }
+        }
+    }
+
+    /// Check whether a number is NaN.
+    pub fn core_is_nan(&mut self) -> i32 {
+        self.core_float_predicate("is-nan", f64::is_nan)
+    }
+
+    /// Check whether a number is positive or negative infinity.
+    pub fn core_is_inf(&mut self) -> i32 {
+        self.core_float_predicate("is-inf", f64::is_infinite)
+    }
+
+    /// Check whether a number is neither NaN nor infinite.
+    pub fn core_is_finite(&mut self) -> i32 {
+        self.core_float_predicate("is-finite", f64::is_finite)
+    }
+
+    /// Place a NaN floating-point value onto the stack.
+    pub fn core_nan(&mut self) -> i32 {
+        self.stack.push(Value::Float(f64::NAN));
+        1
+    }
+
+    /// Place a positive infinity floating-point value onto the stack.
+    pub fn core_inf(&mut self) -> i32 {
+        self.stack.push(Value::Float(f64::INFINITY));
+        1
+    }
+
+    /// Place a negative infinity floating-point value onto the stack.
+    pub fn core_neg_inf(&mut self) -> i32 {
+        self.stack.push(Value::Float(f64::NEG_INFINITY));
+        1
+    }
+
     /// Helper function for left shift.
     fn core_lsft_inner(&mut self, v1: &Value, v2: &Value) -> i32 {
         match (v1, v2) {
@@ -1460,4 +1703,161 @@ impl VM {
 
         1
     }
+
+    /// Takes the name of the calling form and a callback as its
+    /// arguments.  Pops a list or generator from the stack, and calls
+    /// the callback with each of its elements in turn, after
+    /// coercing them to numbers as per +.  Elements are shifted from
+    /// a generator one at a time, and a list is not modified.
+    /// Returns false if the argument is not shiftable, if an element
+    /// cannot be coerced to a number, or if the callback fails.
+    fn for_each_number(
+        &mut self,
+        form: &str,
+        callback: &mut dyn FnMut(&mut VM, Value) -> bool,
+    ) -> bool {
+        if self.stack.is_empty() {
+            self.print_error(&format!("{} requires one argument", form));
+            return false;
+        }
+
+        let mut shiftable_rr = self.stack.pop().unwrap();
+        self.stack.push(shiftable_rr.clone());
+        self.opcode_isshiftable();
+        if let Some(Value::Bool(false)) = self.stack.pop() {
+            self.print_error(&format!("{} argument must be shiftable", form));
+            return false;
+        }
+
+        let list_opt = match shiftable_rr {
+            Value::List(ref lst) => Some(lst.clone()),
+            _ => None,
+        };
+        let mut index = 0;
+        loop {
+            let element_rr = match list_opt {
+                Some(ref lst) => match lst.borrow().get(index) {
+                    Some(element_rr) => element_rr.clone(),
+                    None => break,
+                },
+                None => {
+                    if self.opcode_shift_inner(&mut shiftable_rr) == 0 {
+                        return false;
+                    }
+                    match self.stack.pop().unwrap() {
+                        Value::Null => break,
+                        element_rr => element_rr,
+                    }
+                }
+            };
+            index += 1;
+            let number_rr = match to_number(&element_rr) {
+                Some(number_rr) => number_rr,
+                None => {
+                    self.print_error(&format!("{} argument must contain only numbers", form));
+                    return false;
+                }
+            };
+            if !callback(self, number_rr) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Takes the name of the calling form and the comparison result
+    /// that causes the current value to be replaced as its arguments.
+    /// Finds the minimum or maximum number in a list or generator,
+    /// or null if it is empty.
+    fn generator_extremum(&mut self, form: &str, replace_on: i32) -> i32 {
+        let mut extremum: Option<Value> = None;
+        let res = self.for_each_number(form, &mut |vm, element_rr| {
+            let replace = match extremum {
+                None => true,
+                Some(ref current_rr) => match vm.opcode_cmp_inner(&element_rr, current_rr) {
+                    -2 => {
+                        vm.print_error(&format!(
+                            "{} argument must contain comparable values",
+                            form
+                        ));
+                        return false;
+                    }
+                    cmp => cmp == replace_on,
+                },
+            };
+            if replace {
+                extremum = Some(element_rr);
+            }
+            true
+        });
+        if !res {
+            return 0;
+        }
+        self.stack.push(extremum.unwrap_or(Value::Null));
+        1
+    }
+
+    /// Takes a list or generator as its single argument, and returns
+    /// the minimum number from it, or null if it is empty.
+    pub fn core_gmin(&mut self) -> i32 {
+        self.generator_extremum("gmin", 1)
+    }
+
+    /// Takes a list or generator as its single argument, and returns
+    /// the maximum number from it, or null if it is empty.
+    pub fn core_gmax(&mut self) -> i32 {
+        self.generator_extremum("gmax", -1)
+    }
+
+    /// Takes the name of the calling form as its single argument.
+    /// Sums the numbers in a list or generator (promoting to bigint
+    /// or float as necessary, as per +), and returns the sum and the
+    /// count of the numbers.
+    fn generator_sum(&mut self, form: &str) -> Option<(Value, usize)> {
+        let mut sum = Value::Int(0);
+        let mut count = 0;
+        let res = self.for_each_number(form, &mut |vm, element_rr| {
+            vm.stack.push(mem::replace(&mut sum, Value::Null));
+            vm.stack.push(element_rr);
+            if vm.opcode_add() == 0 {
+                return false;
+            }
+            sum = vm.stack.pop().unwrap();
+            count += 1;
+            true
+        });
+        if !res {
+            return None;
+        }
+        Some((sum, count))
+    }
+
+    /// Takes a list or generator as its single argument, and returns
+    /// the sum of the numbers from it (zero, if it is empty).
+    pub fn core_gsum(&mut self) -> i32 {
+        match self.generator_sum("gsum") {
+            Some((sum, _)) => {
+                self.stack.push(sum);
+                1
+            }
+            None => 0,
+        }
+    }
+
+    /// Takes a list or generator as its single argument, and returns
+    /// the mean of the numbers from it as a float.  It is an error
+    /// for the argument to be empty.
+    pub fn core_gavg(&mut self) -> i32 {
+        let (sum, count) = match self.generator_sum("gavg") {
+            Some(res) => res,
+            None => return 0,
+        };
+        if count == 0 {
+            self.print_error("gavg argument must not be empty");
+            return 0;
+        }
+        let sum_f = sum.to_float().unwrap();
+        self.stack.push(Value::Float(sum_f / count as f64));
+        1
+    }
 }
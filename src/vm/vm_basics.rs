@@ -1,16 +1,84 @@
 use std::char;
 use std::{thread, time};
 
-use num_bigint::BigInt;
+use num_bigint::{BigInt, Sign};
 use num_traits::FromPrimitive;
 use num_traits::Num;
 use num_traits::ToPrimitive;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
 use unicode_segmentation::UnicodeSegmentation;
 
 use chunk::{StringTriple, Value};
+use vm::vm_json::convert_to_json;
 use vm::*;
 
+/// Returns a bigint selected uniformly at random from the range
+/// [0, bound), where bound is positive.
+pub fn gen_bigint_below(rng: &mut dyn RngCore, bound: &BigInt) -> BigInt {
+    let bits = bound.bits() as usize;
+    let mut bytes = vec![0u8; bits.div_ceil(8)];
+    let mask = 0xff >> (bytes.len() * 8 - bits);
+    loop {
+        rng.fill_bytes(&mut bytes);
+        bytes[0] &= mask;
+        let n = BigInt::from_bytes_be(Sign::Plus, &bytes);
+        if n < *bound {
+            return n;
+        }
+    }
+}
+
+/// Helper function for commafy.  Returns the decimal representation
+/// of a number, or of a string that can be converted to a number
+/// (as an integer if possible, and otherwise as a float).
+fn number_string(value_rr: &Value) -> Option<String> {
+    match value_rr {
+        Value::Int(n) => Some(n.to_string()),
+        Value::BigInt(n) => Some(n.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::String(_) => value_rr
+            .to_bigint()
+            .map(|n| n.to_string())
+            .or_else(|| value_rr.to_float().map(|f| f.to_string())),
+        _ => None,
+    }
+}
+
+/// Takes a string of decimal digits and a separator, and returns the
+/// digits with the separator inserted between each group of three
+/// digits (counting from the right).
+fn group_digits(digits: &str, separator: &str) -> String {
+    let mut result = String::new();
+    let len = digits.len();
+    for (index, c) in digits.chars().enumerate() {
+        if index > 0 && (len - index).is_multiple_of(3) {
+            result.push_str(separator);
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Returns a string describing the value, for use in error
+/// messages.
+fn describe_value(v: &Value) -> String {
+    match v {
+        Value::Null
+        | Value::Bool(_)
+        | Value::Int(_)
+        | Value::Float(_)
+        | Value::String(_)
+        | Value::List(_)
+        | Value::Hash(_) => convert_to_json(v),
+        _ => match v.to_string() {
+            Ok(Some(s)) => s,
+            _ => v.type_string(),
+        },
+    }
+}
+
 impl VM {
     /// Remove the top element from the stack.
     pub fn opcode_drop(&mut self) -> i32 {
@@ -80,6 +148,91 @@ impl VM {
         1
     }
 
+    /// Takes an index as its single argument.  Copies the element at
+    /// that index from the top of the stack (where zero is the top
+    /// element, after the index has been removed) onto the stack.
+    pub fn opcode_pick(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("pick requires one argument");
+            return 0;
+        }
+
+        let index_rr = self.stack.pop().unwrap();
+        let index = match index_rr.to_int() {
+            Some(n) => n,
+            None => {
+                self.print_error("pick argument must be integer");
+                return 0;
+            }
+        };
+        let len = self.stack.len();
+        if index < 0 || (index as usize) >= len {
+            self.print_error("pick index is out of range");
+            return 0;
+        }
+        let element_rr = self.stack[len - 1 - (index as usize)].clone();
+        self.stack.push(element_rr);
+        1
+    }
+
+    /// Remove the second from top element from the stack.
+    pub fn opcode_nip(&mut self) -> i32 {
+        let len = self.stack.len();
+        if len < 2 {
+            self.print_error("nip requires two arguments");
+            return 0;
+        }
+        self.stack.remove(len - 2);
+        1
+    }
+
+    /// Copy the top element from the stack, and insert the copy
+    /// below the second from top element.
+    pub fn opcode_tuck(&mut self) -> i32 {
+        let len = self.stack.len();
+        if len < 2 {
+            self.print_error("tuck requires two arguments");
+            return 0;
+        }
+        let top_rr = self.stack.last().unwrap().clone();
+        self.stack.insert(len - 2, top_rr);
+        1
+    }
+
+    /// Duplicate the top two elements from the stack, as a pair.
+    pub fn opcode_2dup(&mut self) -> i32 {
+        let len = self.stack.len();
+        if len < 2 {
+            self.print_error("2dup requires two arguments");
+            return 0;
+        }
+        self.stack.extend_from_within(len - 2..);
+        1
+    }
+
+    /// Remove the top two elements from the stack.
+    pub fn opcode_2drop(&mut self) -> i32 {
+        let len = self.stack.len();
+        if len < 2 {
+            self.print_error("2drop requires two arguments");
+            return 0;
+        }
+        self.stack.truncate(len - 2);
+        1
+    }
+
+    /// Swap the top pair of elements from the stack with the pair of
+    /// elements below it.
+    pub fn opcode_2swap(&mut self) -> i32 {
+        let len = self.stack.len();
+        if len < 4 {
+            self.print_error("2swap requires four arguments");
+            return 0;
+        }
+        self.stack[len - 4..].rotate_left(2);
+        1
+    }
+
     /// Push the current depth of the stack onto the stack.
     #[allow(unused_variables)]
     pub fn opcode_depth(&mut self) -> i32 {
@@ -88,7 +241,8 @@ impl VM {
     }
 
     /// Adds the length of the topmost element onto the stack.
-    /// Supports lists, hashes, sets, strings, and generators.
+    /// Supports lists, hashes, sets, strings, and generators.  The
+    /// length of a string is its number of grapheme clusters.
     pub fn core_len(&mut self) -> i32 {
         if self.stack.is_empty() {
             self.print_error("len requires one argument");
@@ -113,7 +267,7 @@ impl VM {
                 return 1;
             }
             Value::String(st) => {
-                let len = st.borrow().string.len();
+                let len = st.borrow().string.graphemes(true).count();
                 self.stack.push(Value::Int(len as i32));
                 return 1;
             }
@@ -141,6 +295,29 @@ impl VM {
         }
     }
 
+    /// Adds the length of the topmost element, in bytes, onto the
+    /// stack.
+    pub fn core_byte_len(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("byte-len requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        match value_opt {
+            Some(s) => {
+                self.stack.push(Value::Int(s.len() as i32));
+                1
+            }
+            None => {
+                self.print_error("byte-len argument must be string");
+                0
+            }
+        }
+    }
+
     /// Checks whether the length of the topmost element is zero.
     /// Supports lists, hashes, sets, strings, and generators.
     pub fn core_empty(&mut self) -> i32 {
@@ -426,6 +603,167 @@ impl VM {
         1
     }
 
+    /// Takes two values and returns a boolean indicating whether both
+    /// of them evaluate to true.  Both values are evaluated, so this
+    /// does not short-circuit.
+    pub fn core_bool_and(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("and requires two arguments");
+            return 0;
+        }
+
+        let v1_rr = self.stack.pop().unwrap();
+        let v2_rr = self.stack.pop().unwrap();
+        self.stack
+            .push(Value::Bool(v2_rr.to_bool() && v1_rr.to_bool()));
+        1
+    }
+
+    /// Takes two values and returns a boolean indicating whether at
+    /// least one of them evaluates to true.  Both values are
+    /// evaluated, so this does not short-circuit.
+    pub fn core_bool_or(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("or requires two arguments");
+            return 0;
+        }
+
+        let v1_rr = self.stack.pop().unwrap();
+        let v2_rr = self.stack.pop().unwrap();
+        self.stack
+            .push(Value::Bool(v2_rr.to_bool() || v1_rr.to_bool()));
+        1
+    }
+
+    /// Takes a value and returns a boolean indicating whether it
+    /// evaluates to false.
+    pub fn core_bool_not(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("not requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        self.stack.push(Value::Bool(!value_rr.to_bool()));
+        1
+    }
+
+    /// Takes a value as its single argument.  If the value evaluates
+    /// to false, reports an error and halts execution.  Otherwise,
+    /// does nothing.
+    pub fn core_assert(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("assert requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        if value_rr.to_bool() {
+            return 1;
+        }
+        self.print_error("assertion failed");
+        0
+    }
+
+    /// Takes a value and a message string as its arguments.  If the
+    /// value evaluates to false, reports an error that includes the
+    /// message and halts execution.  Otherwise, does nothing.
+    pub fn core_assert_msg(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("assert-msg requires two arguments");
+            return 0;
+        }
+
+        let message_rr = self.stack.pop().unwrap();
+        let value_rr = self.stack.pop().unwrap();
+
+        let message_opt: Option<&str>;
+        to_str!(message_rr, message_opt);
+        match message_opt {
+            None => {
+                self.print_error("assert-msg second argument must be a string");
+                0
+            }
+            Some(_) if value_rr.to_bool() => 1,
+            Some(m) => {
+                self.print_error(&format!("assertion failed: {}", m));
+                0
+            }
+        }
+    }
+
+    /// Takes two values as its arguments.  If the values are not
+    /// equal (per `eq?`), reports an error that includes both values
+    /// and halts execution.  Otherwise, does nothing.
+    pub fn core_assert_eq(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("assert-eq requires two arguments");
+            return 0;
+        }
+
+        let v2_rr = self.stack.pop().unwrap();
+        let v1_rr = self.stack.pop().unwrap();
+
+        let mut seen = Vec::new();
+        if self.deep_eq_inner(&v1_rr, &v2_rr, &mut seen) {
+            return 1;
+        }
+        self.print_error(&format!(
+            "assert-eq failed: {} != {}",
+            describe_value(&v1_rr),
+            describe_value(&v2_rr)
+        ));
+        0
+    }
+
+    /// Takes a list as its single argument, and returns the first
+    /// element of the list that is not null.  If all of the elements
+    /// are null (or the list is empty), returns null.
+    pub fn core_coalesce(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("coalesce requires one argument");
+            return 0;
+        }
+
+        let lst_rr = self.stack.pop().unwrap();
+        match lst_rr {
+            Value::List(lst) => {
+                let res = lst
+                    .borrow()
+                    .iter()
+                    .find(|v| !matches!(v, Value::Null))
+                    .cloned()
+                    .unwrap_or(Value::Null);
+                self.stack.push(res);
+                1
+            }
+            _ => {
+                self.print_error("coalesce argument must be list");
+                0
+            }
+        }
+    }
+
+    /// Takes a value and a default value as its arguments.  Returns
+    /// the first value if it is not null, and the default value
+    /// otherwise.  Only null triggers the default: other false values
+    /// (e.g. 0 or the empty string) are returned as-is.  Available as
+    /// both `default` and `??`.
+    pub fn core_default(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("default requires two arguments");
+            return 0;
+        }
+
+        let v2_rr = self.stack.pop().unwrap();
+        let v1_rr = self.stack.pop().unwrap();
+        match v1_rr {
+            Value::Null => self.stack.push(v2_rr),
+            _ => self.stack.push(v1_rr),
+        }
+        1
+    }
+
     /// Check whether a value is of boolean type.
     pub fn opcode_is_bool(&mut self) -> i32 {
         if self.stack.is_empty() {
@@ -517,6 +855,40 @@ impl VM {
         1
     }
 
+    /// Takes a value and a type name as its arguments.  If the type
+    /// of the value (as per its type string) does not match the type
+    /// name, reports an error.  Otherwise, leaves the value on the
+    /// stack.  Type names that cover multiple types (e.g. "ip", for
+    /// both single addresses and ranges) are supported.
+    pub fn core_expect_type(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("expect-type requires two arguments");
+            return 0;
+        }
+
+        let type_rr = self.stack.pop().unwrap();
+        let type_opt: Option<&str>;
+        to_str!(type_rr, type_opt);
+
+        match type_opt {
+            Some(type_name) => {
+                let value_type = self.stack.last().unwrap().type_string();
+                if value_type != type_name {
+                    self.print_error(&format!(
+                        "expected {} argument, got {}",
+                        type_name, value_type
+                    ));
+                    return 0;
+                }
+                1
+            }
+            _ => {
+                self.print_error("second expect-type argument must be string");
+                0
+            }
+        }
+    }
+
     /// Get a random floating-point value.
     pub fn opcode_rand(&mut self) -> i32 {
         if self.stack.is_empty() {
@@ -528,8 +900,7 @@ impl VM {
         let value_opt = value_rr.to_float();
         match value_opt {
             Some(n) => {
-                let mut rng = rand::thread_rng();
-                let rand_value = rng.gen_range(0.0..n);
+                let rand_value = self.rng.gen_range(0.0..n);
                 self.stack.push(Value::Float(rand_value));
             }
             _ => {
@@ -541,6 +912,234 @@ impl VM {
         1
     }
 
+    /// Takes a lower bound and an upper bound as its arguments, and
+    /// returns an integer selected uniformly at random from the range
+    /// that includes the lower bound and excludes the upper bound.
+    /// Bigint bounds are supported.
+    pub fn core_rand_int(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("rand-int requires two arguments");
+            return 0;
+        }
+
+        let high_rr = self.stack.pop().unwrap();
+        let low_rr = self.stack.pop().unwrap();
+        if let (Some(low), Some(high)) = (low_rr.to_int(), high_rr.to_int()) {
+            if low >= high {
+                self.print_error("rand-int lower bound must be less than upper bound");
+                return 0;
+            }
+            let n = self.rng.gen_range(low..high);
+            self.stack.push(Value::Int(n));
+            return 1;
+        }
+        match (low_rr.to_bigint(), high_rr.to_bigint()) {
+            (Some(low), Some(high)) => {
+                if low >= high {
+                    self.print_error("rand-int lower bound must be less than upper bound");
+                    return 0;
+                }
+                let n = low.clone() + gen_bigint_below(&mut self.rng, &(high - low));
+                match n.to_i32() {
+                    Some(n) => self.stack.push(Value::Int(n)),
+                    None => self.stack.push(Value::BigInt(n)),
+                }
+            }
+            (Some(_), _) => {
+                self.print_error("second rand-int argument must be integer");
+                return 0;
+            }
+            (_, _) => {
+                self.print_error("first rand-int argument must be integer");
+                return 0;
+            }
+        }
+
+        1
+    }
+
+    /// Seeds the random number generator used by rand and the other
+    /// random forms, so that subsequent results are reproducible.
+    pub fn core_srand(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("srand requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt = value_rr.to_int();
+        match value_opt {
+            Some(n) => {
+                self.rng = Box::new(StdRng::seed_from_u64(n as u64));
+            }
+            _ => {
+                self.print_error("srand argument must be integer");
+                return 0;
+            }
+        }
+
+        1
+    }
+
+    /// Takes a shiftable object and a sample size as its arguments.
+    /// Returns a list containing that many elements selected
+    /// uniformly at random from the shiftable object, using
+    /// reservoir sampling so that the object is only read once, and
+    /// only the sample is held in memory.  If the object has fewer
+    /// elements than the sample size, all of its elements are
+    /// returned.
+    pub fn core_reservoir_sample(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("sample-reservoir requires two arguments");
+            return 0;
+        }
+
+        let k_rr = self.stack.pop().unwrap();
+        let k = match k_rr.to_int() {
+            Some(n) if n >= 0 => n as usize,
+            _ => {
+                self.print_error("sample-reservoir second argument must be non-negative integer");
+                return 0;
+            }
+        };
+
+        let mut shiftable_rr = self.stack.pop().unwrap();
+        let mut reservoir = VecDeque::new();
+        let mut seen: usize = 0;
+        {
+            let mut add = |rng: &mut dyn RngCore, e: Value| {
+                if reservoir.len() < k {
+                    reservoir.push_back(e);
+                } else {
+                    let j = rng.gen_range(0..=seen);
+                    if j < k {
+                        reservoir[j] = e;
+                    }
+                }
+                seen += 1;
+            };
+
+            match shiftable_rr {
+                Value::List(ref lst) => {
+                    for e in lst.borrow().iter() {
+                        add(&mut self.rng, e.clone());
+                    }
+                }
+                _ => loop {
+                    let shift_res = self.opcode_shift_inner(&mut shiftable_rr);
+                    if shift_res == 0 {
+                        return 0;
+                    }
+                    let element_rr = self.stack.pop().unwrap();
+                    if let Value::Null = element_rr {
+                        break;
+                    }
+                    add(&mut self.rng, element_rr);
+                },
+            }
+        }
+
+        self.stack
+            .push(Value::List(Rc::new(RefCell::new(reservoir))));
+        1
+    }
+
+    /// Takes a list as its single argument, and returns an element
+    /// selected uniformly at random from that list.  If the list is
+    /// empty, returns null.
+    pub fn core_sample(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("sample requires one argument");
+            return 0;
+        }
+
+        let lst_rr = self.stack.pop().unwrap();
+        match lst_rr {
+            Value::List(lst) => {
+                let lstb = lst.borrow();
+                if lstb.is_empty() {
+                    self.stack.push(Value::Null);
+                } else {
+                    let i = self.rng.gen_range(0..lstb.len());
+                    self.stack.push(lstb[i].clone());
+                }
+            }
+            _ => {
+                self.print_error("sample argument must be list");
+                return 0;
+            }
+        }
+        1
+    }
+
+    /// Takes a list or generator as its single argument, and returns
+    /// a new list containing the same elements in a random order.
+    /// The argument list is not modified.
+    pub fn core_shuffle(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("shuffle requires one argument");
+            return 0;
+        }
+
+        let lst_rr = self.stack.pop().unwrap();
+        let mut elements: Vec<Value> = match lst_rr {
+            Value::List(lst) => lst.borrow().iter().cloned().collect(),
+            _ if lst_rr.is_generator() => {
+                self.stack.push(lst_rr);
+                let res = self.generator_to_list();
+                if res == 0 {
+                    return 0;
+                }
+                match self.stack.pop().unwrap() {
+                    Value::List(lst) => lst.borrow().iter().cloned().collect(),
+                    _ => unreachable!(),
+                }
+            }
+            _ => {
+                self.print_error("shuffle argument must be list or generator");
+                return 0;
+            }
+        };
+        elements.shuffle(&mut self.rng);
+        self.stack.push(Value::List(Rc::new(RefCell::new(
+            elements.into_iter().collect(),
+        ))));
+        1
+    }
+
+    /// Returns the next number from a sequence of integers that
+    /// starts at 1 and increases by one on each call.  The sequence
+    /// is specific to the current VM (i.e. the current run).
+    pub fn core_next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        match self.last_id.to_i32() {
+            Some(n) => self.stack.push(Value::Int(n)),
+            None => self.stack.push(Value::BigInt(BigInt::from(self.last_id))),
+        }
+        1
+    }
+
+    /// Returns a random (version 4) UUID string, in lowercase
+    /// hyphenated form.
+    pub fn core_uuid(&mut self) -> i32 {
+        let mut bytes = [0u8; 16];
+        self.rng.fill_bytes(&mut bytes);
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let uuid = format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        );
+        let st = Rc::new(RefCell::new(StringTriple::new(uuid, None)));
+        self.stack.push(Value::String(st));
+        1
+    }
+
     /// Return a deep clone of the argument (compare dup).
     pub fn opcode_clone(&mut self) -> i32 {
         if self.stack.is_empty() {
@@ -633,60 +1232,372 @@ impl VM {
         1
     }
 
-    /// Converts a hex string into an integer or bigint.
-    pub fn core_hex(&mut self) -> i32 {
+    /// Converts a string into a list of the integers associated with
+    /// each of its characters.
+    pub fn core_codepoints(&mut self) -> i32 {
         if self.stack.is_empty() {
-            self.print_error("hex requires one argument");
+            self.print_error("codepoints requires one argument");
             return 0;
         }
         let value_rr = self.stack.pop().unwrap();
         let value_opt: Option<&str>;
         to_str!(value_rr, value_opt);
         if value_opt.is_none() {
-            self.print_error("hex argument must be string");
+            self.print_error("codepoints argument must be string");
+            return 0;
+        }
+        let lst = value_opt
+            .unwrap()
+            .chars()
+            .map(|c| Value::Int(c as i32))
+            .collect::<VecDeque<Value>>();
+        self.stack.push(Value::List(Rc::new(RefCell::new(lst))));
+        1
+    }
+
+    /// Converts a list of integers into a string, where each integer
+    /// is the value associated with a character.
+    pub fn core_from_codepoints(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("from-codepoints requires one argument");
+            return 0;
+        }
+        let value_rr = self.stack.pop().unwrap();
+        let lst = match value_rr {
+            Value::List(lst) => lst,
+            _ => {
+                self.print_error("from-codepoints argument must be list");
+                return 0;
+            }
+        };
+        let mut new_st = String::new();
+        for e in lst.borrow().iter() {
+            let c_opt = e
+                .to_int()
+                .and_then(|n| n.try_into().ok())
+                .and_then(char::from_u32);
+            match c_opt {
+                Some(c) => new_st.push(c),
+                None => {
+                    self.print_error("from-codepoints argument must be list of characters");
+                    return 0;
+                }
+            }
+        }
+        let st = Rc::new(RefCell::new(StringTriple::new(new_st, None)));
+        self.stack.push(Value::String(st));
+        1
+    }
+
+    /// Takes the name of the calling form, a radix, and the prefix
+    /// that may be used for numbers in that radix as its arguments.
+    /// Converts the string argument into an integer or bigint.
+    /// Negative numbers are written in sign-magnitude form (e.g.
+    /// "-ff" for -255), with the optional prefix (in either case)
+    /// following the sign.
+    fn parse_radix(&mut self, form: &str, radix: u32, prefix: &str, radix_name: &str) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error(&format!("{} requires one argument", form));
+            return 0;
+        }
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        let value_str = match value_opt {
+            Some(s) => s,
+            None => {
+                self.print_error(&format!("{} argument must be string", form));
+                return 0;
+            }
+        };
+        let (sign, digits) = match value_str.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", value_str),
+        };
+        let upper_prefix = prefix.to_uppercase();
+        let digits = digits
+            .strip_prefix(prefix)
+            .or_else(|| digits.strip_prefix(upper_prefix.as_str()))
+            .unwrap_or(digits);
+        /* from_str_radix accepts some characters that are not
+         * digits (e.g. a leading '+', or '_' separators in the
+         * bigint case), so check the digits explicitly. */
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            self.print_error(&format!("{} argument must be {} string", form, radix_name));
             return 0;
         }
-        let value_str = value_opt.unwrap().replace("0x", "");
-        let n_i32: Result<i32, _> = i32::from_str_radix(&value_str, 16);
+        let value_str = format!("{}{}", sign, digits);
+        let n_i32: Result<i32, _> = i32::from_str_radix(&value_str, radix);
         if let Ok(n) = n_i32 {
             self.stack.push(Value::Int(n));
             return 1;
         }
-        let n_bi: Result<BigInt, _> = BigInt::from_str_radix(&value_str, 16);
+        let n_bi: Result<BigInt, _> = BigInt::from_str_radix(&value_str, radix);
         if let Ok(bi) = n_bi {
             self.stack.push(Value::BigInt(bi));
             return 1;
         }
-        self.print_error("hex argument must be hexadecimal string");
+        self.print_error(&format!("{} argument must be {} string", form, radix_name));
         0
     }
 
+    /// Takes the name of the calling form and a radix as its
+    /// arguments.  Converts the integer or bigint argument into a
+    /// string in that radix.  Negative numbers are written in
+    /// sign-magnitude form (e.g. "-ff" for -255), rather than in
+    /// two's complement form.
+    fn format_radix(&mut self, form: &str, radix: u32) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error(&format!("{} requires one argument", form));
+            return 0;
+        }
+        let value_rr = self.stack.pop().unwrap();
+        let n_opt = match value_rr {
+            Value::Float(_) => None,
+            _ => value_rr.to_bigint(),
+        };
+        match n_opt {
+            Some(n) => {
+                let st = StringTriple::new(n.to_str_radix(radix), None);
+                self.stack.push(Value::String(Rc::new(RefCell::new(st))));
+                1
+            }
+            None => {
+                self.print_error(&format!("{} argument must be integer", form));
+                0
+            }
+        }
+    }
+
+    /// Converts a hex string into an integer or bigint.
+    pub fn core_hex(&mut self) -> i32 {
+        self.parse_radix("hex", 16, "0x", "hexadecimal")
+    }
+
     /// Converts an octal string into an integer or bigint.
     pub fn core_oct(&mut self) -> i32 {
+        self.parse_radix("oct", 8, "0o", "octal")
+    }
+
+    /// Converts a binary string into an integer or bigint.
+    pub fn core_bin(&mut self) -> i32 {
+        self.parse_radix("bin", 2, "0b", "binary")
+    }
+
+    /// Converts an integer or bigint into a hex string.
+    pub fn core_to_hex(&mut self) -> i32 {
+        self.format_radix("to-hex", 16)
+    }
+
+    /// Converts an integer or bigint into an octal string.
+    pub fn core_to_oct(&mut self) -> i32 {
+        self.format_radix("to-oct", 8)
+    }
+
+    /// Converts an integer or bigint into a binary string.
+    pub fn core_to_bin(&mut self) -> i32 {
+        self.format_radix("to-bin", 2)
+    }
+
+    /// Takes a number (or a numeric string) and an optional separator
+    /// string as its arguments.  Returns the number as a string, with
+    /// the separator (a comma, by default) inserted between each group
+    /// of three digits in the integer part of the number.
+    pub fn core_commafy(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("commafy requires one argument");
+            return 0;
+        }
+
+        /* The top value is a separator if it is a string that is not
+         * itself a number, and the value beneath it is a number. */
+        let mut separator = ",".to_string();
+        if self.stack.len() >= 2 {
+            let len = self.stack.len();
+            if let Value::String(st) = &self.stack[len - 1] {
+                if number_string(&self.stack[len - 1]).is_none()
+                    && number_string(&self.stack[len - 2]).is_some()
+                {
+                    separator = st.borrow().string.to_string();
+                    self.stack.pop();
+                }
+            }
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let number_str = match number_string(&value_rr) {
+            Some(number_str) => number_str,
+            None => {
+                self.print_error("commafy argument must be number");
+                return 0;
+            }
+        };
+
+        let (sign, unsigned) = match number_str.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", number_str.as_str()),
+        };
+        let (int_part, frac_part) = match unsigned.find('.') {
+            Some(index) => unsigned.split_at(index),
+            None => (unsigned, ""),
+        };
+        let result = if int_part.chars().all(|c| c.is_ascii_digit()) {
+            format!(
+                "{}{}{}",
+                sign,
+                group_digits(int_part, &separator),
+                frac_part
+            )
+        } else {
+            number_str.clone()
+        };
+
+        let st = StringTriple::new(result, None);
+        self.stack.push(Value::String(Rc::new(RefCell::new(st))));
+        1
+    }
+
+    /// Takes a byte count and an optional boolean as its arguments.
+    /// Returns the byte count as a human-readable string, using
+    /// binary units (KiB, MiB, etc.), or decimal SI units (kB, MB,
+    /// etc.) if the boolean is true.
+    pub fn core_humanize_bytes(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("humanize-bytes requires one argument");
+            return 0;
+        }
+
+        let decimal = self.pop_optional_flag(1);
+
+        let value_rr = self.stack.pop().unwrap();
+        let n_opt = match value_rr {
+            Value::Float(_) => None,
+            _ => value_rr.to_bigint(),
+        };
+        let n = match n_opt {
+            Some(n) if n.sign() != Sign::Minus => n,
+            _ => {
+                self.print_error("humanize-bytes argument must be non-negative integer");
+                return 0;
+            }
+        };
+
+        let (base, units) = if decimal {
+            (
+                1000.0,
+                ["B", "kB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"],
+            )
+        } else {
+            (
+                1024.0,
+                ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB"],
+            )
+        };
+        let result = if n < BigInt::from(base as i32) {
+            format!("{} B", n)
+        } else {
+            let mut size = n.to_f64().unwrap();
+            let mut unit = 0;
+            while size >= base && unit < units.len() - 1 {
+                size /= base;
+                unit += 1;
+            }
+            /* Move up a unit if rounding to one decimal place would
+             * otherwise produce e.g. "1024.0 KiB". */
+            if (size * 10.0).round() / 10.0 >= base && unit < units.len() - 1 {
+                size /= base;
+                unit += 1;
+            }
+            format!("{:.1} {}", size, units[unit])
+        };
+
+        let st = StringTriple::new(result, None);
+        self.stack.push(Value::String(Rc::new(RefCell::new(st))));
+        1
+    }
+
+    /// Takes a byte size string (e.g. "1.5K", "2GiB", "10MB") as its
+    /// single argument, and returns the corresponding number of
+    /// bytes.  Suffixes without a trailing "B", or with "iB", are
+    /// binary units, while suffixes with a trailing "B" alone are
+    /// decimal SI units.
+    pub fn core_parse_bytes(&mut self) -> i32 {
         if self.stack.is_empty() {
-            self.print_error("oct requires one argument");
+            self.print_error("parse-bytes requires one argument");
             return 0;
         }
+
         let value_rr = self.stack.pop().unwrap();
         let value_opt: Option<&str>;
         to_str!(value_rr, value_opt);
-        if value_opt.is_none() {
-            self.print_error("oct argument must be string");
+        let value = match value_opt {
+            Some(value) => value.trim(),
+            _ => {
+                self.print_error("parse-bytes argument must be string");
+                return 0;
+            }
+        };
+
+        let split_index = value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len());
+        let (number_str, suffix) = value.split_at(split_index);
+        let suffix = suffix.trim_start();
+        let (prefix, rest) = match suffix.chars().next() {
+            Some(c) if c != 'b' && c != 'B' => suffix.split_at(c.len_utf8()),
+            _ => ("", suffix),
+        };
+        let exponent = match prefix.to_ascii_uppercase().as_str() {
+            "" => 0,
+            "K" => 1,
+            "M" => 2,
+            "G" => 3,
+            "T" => 4,
+            "P" => 5,
+            "E" => 6,
+            "Z" => 7,
+            "Y" => 8,
+            _ => -1,
+        };
+        let base: u32 = match rest {
+            "" | "iB" | "ib" if exponent > 0 => 1024,
+            "" | "B" | "b" => {
+                if exponent > 0 {
+                    1000
+                } else {
+                    1
+                }
+            }
+            _ => 0,
+        };
+        if exponent < 0
+            || base == 0
+            || number_str.is_empty()
+            || number_str.matches('.').count() > 1
+            || number_str == "."
+        {
+            self.print_error("parse-bytes argument must be byte size string");
             return 0;
         }
-        let value_str = value_opt.unwrap();
-        let n_i32: Result<i32, _> = i32::from_str_radix(value_str, 8);
-        if let Ok(n) = n_i32 {
-            self.stack.push(Value::Int(n));
-            return 1;
-        }
-        let n_bi: Result<BigInt, _> = BigInt::from_str_radix(value_str, 8);
-        if let Ok(bi) = n_bi {
-            self.stack.push(Value::BigInt(bi));
-            return 1;
+
+        let multiplier = BigInt::from(base).pow(exponent as u32);
+        let bytes = if number_str.contains('.') {
+            let number: f64 = number_str.parse().unwrap();
+            if exponent == 0 && number.fract() != 0.0 {
+                self.print_error("parse-bytes argument must not have fractional byte count");
+                return 0;
+            }
+            let multiplier_f = multiplier.to_f64().unwrap();
+            BigInt::from_f64((number * multiplier_f).round()).unwrap()
+        } else {
+            BigInt::from_str_radix(number_str, 10).unwrap() * multiplier
+        };
+
+        match bytes.to_i32() {
+            Some(n) => self.stack.push(Value::Int(n)),
+            None => self.stack.push(Value::BigInt(bytes)),
         }
-        self.print_error("oct argument must be string");
-        0
+        1
     }
 
     /// Converts a string to lowercase.
@@ -785,6 +1696,71 @@ impl VM {
         1
     }
 
+    /// Converts the first character of each whitespace-delimited
+    /// word in a string to uppercase.  Word boundaries are determined
+    /// per the Unicode word boundary rules, so that (for example) an
+    /// apostrophe within a word does not start a new word.
+    pub fn core_title_case(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("title-case requires one argument");
+            return 0;
+        }
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        if value_opt.is_none() {
+            self.print_error("title-case argument must be string");
+            return 0;
+        }
+        let mut new_st = String::new();
+        let mut at_word_start = true;
+        for segment in value_opt.unwrap().split_word_bounds() {
+            let is_space = segment.chars().all(char::is_whitespace);
+            if at_word_start && !is_space {
+                let mut iter = segment.chars();
+                if let Some(c) = iter.next() {
+                    new_st.extend(c.to_uppercase());
+                }
+                new_st.extend(iter);
+            } else {
+                new_st.push_str(segment);
+            }
+            at_word_start = is_space;
+        }
+        let st = Rc::new(RefCell::new(StringTriple::new(new_st, None)));
+        self.stack.push(Value::String(st));
+        1
+    }
+
+    /// Converts each uppercase character in a string to lowercase,
+    /// and each lowercase character to uppercase.
+    pub fn core_swap_case(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("swap-case requires one argument");
+            return 0;
+        }
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        if value_opt.is_none() {
+            self.print_error("swap-case argument must be string");
+            return 0;
+        }
+        let mut new_st = String::new();
+        for c in value_opt.unwrap().chars() {
+            if c.is_uppercase() {
+                new_st.extend(c.to_lowercase());
+            } else if c.is_lowercase() {
+                new_st.extend(c.to_uppercase());
+            } else {
+                new_st.push(c);
+            }
+        }
+        let st = Rc::new(RefCell::new(StringTriple::new(new_st, None)));
+        self.stack.push(Value::String(st));
+        1
+    }
+
     /// Reverses a list or a string.
     pub fn core_reverse(&mut self) -> i32 {
         if self.stack.is_empty() {
@@ -802,6 +1778,10 @@ impl VM {
                 self.stack.push(new_lst);
                 1
             }
+            _ if value_rr.is_generator() => {
+                self.print_error("reverse argument must not be generator (use take-all)");
+                0
+            }
             _ => {
                 let value_opt: Option<&str>;
                 to_str!(value_rr, value_opt);
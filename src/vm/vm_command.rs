@@ -30,8 +30,10 @@ lazy_static! {
 }
 
 /// Splits a string on whitespace, taking into account quoted values
-/// (for both single-quotes and double-quotes).
-fn split_command(s: &str) -> Option<VecDeque<String>> {
+/// (for both single-quotes and double-quotes).  Each element is
+/// paired with a flag indicating whether it was quoted (or escaped),
+/// in which case it is not subject to glob expansion.
+fn split_command(s: &str) -> Option<VecDeque<(String, bool)>> {
     let elements = s.split_whitespace();
     let mut final_elements = Vec::new();
     let mut buffer = Vec::new();
@@ -46,11 +48,11 @@ fn split_command(s: &str) -> Option<VecDeque<String>> {
                     if delimiter == '"' {
                         let new_str2 = START_DOUBLE_QUOTE.replace(&new_str, "");
                         let new_str3 = END_DOUBLE_QUOTE.replace(&new_str2, "");
-                        final_elements.push(new_str3.to_string());
+                        final_elements.push((new_str3.to_string(), true));
                     } else {
                         let new_str2 = START_SINGLE_QUOTE.replace(&new_str, "");
                         let new_str3 = END_SINGLE_QUOTE.replace(&new_str2, "");
-                        final_elements.push(new_str3.to_string());
+                        final_elements.push((new_str3.to_string(), true));
                     }
                     buffer.clear();
                 } else {
@@ -70,7 +72,8 @@ fn split_command(s: &str) -> Option<VecDeque<String>> {
             let new_str3 = END_DOUBLE_QUOTE.replace(&new_str2, "");
             let new_str4 = START_SINGLE_QUOTE.replace(&new_str3, "");
             let new_str5 = END_SINGLE_QUOTE.replace(&new_str4, "");
-            final_elements.push(new_str5.to_string());
+            let quoted = new_str5.len() != e_str.len();
+            final_elements.push((new_str5.to_string(), quoted));
         }
     }
     if !buffer.is_empty() {
@@ -78,24 +81,129 @@ fn split_command(s: &str) -> Option<VecDeque<String>> {
     }
 
     let mut lst = VecDeque::new();
-    for e in final_elements.iter() {
+    for (e, quoted) in final_elements.into_iter() {
         if lst.is_empty() {
-            lst.push_back(e.to_string());
+            lst.push_back((e, quoted));
         } else {
-            let back = lst.back().unwrap();
+            let (back, _) = lst.back().unwrap();
             if END_SLASH_EXTRA.is_match(back) {
-                let back = lst.pop_back().unwrap();
+                let (back, _) = lst.pop_back().unwrap();
                 let back2 = END_SLASH.replace_all(&back, "");
                 let back3 = format!("{} {}", back2, e);
-                lst.push_back(back3);
+                lst.push_back((back3, true));
             } else {
-                lst.push_back(e.to_string());
+                lst.push_back((e, quoted));
             }
         }
     }
     Some(lst)
 }
 
+/// Returns a boolean indicating whether the string contains any glob
+/// metacharacters.
+fn has_glob_chars(s: &str) -> bool {
+    s.contains('*') || s.contains('?') || s.contains('[')
+}
+
+/// Converts a single glob path component (supporting `*`, `?` and
+/// `[...]` character classes) into an anchored regex.
+fn glob_component_to_regex(component: &str) -> Option<Regex> {
+    let mut re = String::from("^");
+    let chars: Vec<char> = component.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '[' => match chars[i + 1..].iter().position(|c| *c == ']') {
+                Some(len) if len > 0 => {
+                    let class: String = chars[i + 1..i + 1 + len].iter().collect();
+                    re.push('[');
+                    match class.strip_prefix('!') {
+                        Some(rest) => {
+                            re.push('^');
+                            re.push_str(&rest.replace('\\', "\\\\"));
+                        }
+                        None => re.push_str(&class.replace('\\', "\\\\")),
+                    }
+                    re.push(']');
+                    i += len + 1;
+                }
+                _ => re.push_str("\\["),
+            },
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    re.push('$');
+    Regex::new(&re).ok()
+}
+
+/// Expands a glob pattern against the filesystem (relative paths are
+/// resolved against the current working directory), returning the
+/// sorted list of matching paths.  Leading dots in file names are
+/// only matched explicitly.
+fn expand_glob(pattern: &str) -> Vec<String> {
+    let mut paths = vec![if pattern.starts_with('/') {
+        "/".to_string()
+    } else {
+        "".to_string()
+    }];
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let join = |prefix: &str, name: &str| -> String {
+        if prefix.is_empty() || prefix.ends_with('/') {
+            format!("{}{}", prefix, name)
+        } else {
+            format!("{}/{}", prefix, name)
+        }
+    };
+    for (index, component) in components.iter().enumerate() {
+        let is_last = index == components.len() - 1;
+        let mut new_paths = Vec::new();
+        if !has_glob_chars(component) {
+            for path in paths.iter() {
+                new_paths.push(join(path, component));
+            }
+        } else {
+            let regex = match glob_component_to_regex(component) {
+                Some(regex) => regex,
+                None => return Vec::new(),
+            };
+            for path in paths.iter() {
+                let dir = if path.is_empty() { "." } else { path };
+                let entries = match std::fs::read_dir(dir) {
+                    Ok(entries) => entries,
+                    Err(_) => continue,
+                };
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if name.starts_with('.') && !component.starts_with('.') {
+                        continue;
+                    }
+                    if !regex.is_match(&name) {
+                        continue;
+                    }
+                    let new_path = join(path, &name);
+                    if !is_last && !std::path::Path::new(&new_path).is_dir() {
+                        continue;
+                    }
+                    new_paths.push(new_path);
+                }
+            }
+        }
+        paths = new_paths;
+    }
+    let mut matches: Vec<String> = paths
+        .into_iter()
+        .filter(|path| std::fs::symlink_metadata(path).is_ok())
+        .collect();
+    if pattern.ends_with('/') {
+        matches = matches.into_iter().map(|path| path + "/").collect();
+    }
+    matches.sort();
+    matches
+}
+
 fn restore_env(env: HashMap<String, String>) {
     for (key, value) in env {
         env::set_var(key, value);
@@ -161,7 +269,7 @@ impl VM {
 
         let mut prev_env = HashMap::new();
         while !elements.is_empty() {
-            let element = elements.get(0).unwrap();
+            let (element, _) = elements.get(0).unwrap();
             let captures = ENV_VAR.captures_iter(element);
             let mut has = false;
             for capture in captures {
@@ -187,9 +295,26 @@ impl VM {
             self.print_error("unable to execute empty command");
             return None;
         }
-        let executable = executable_opt.unwrap();
+        let (executable, _) = executable_opt.unwrap();
         let executable_final = LEADING_WS.replace_all(executable, "").to_string();
-        let args = element_iter.map(|v| v.to_string()).collect::<Vec<_>>();
+        let mut args = Vec::new();
+        for (arg, quoted) in element_iter {
+            if *quoted || !has_glob_chars(arg) {
+                args.push(arg.to_string());
+                continue;
+            }
+            let matches = expand_glob(arg);
+            if !matches.is_empty() {
+                args.extend(matches);
+            } else if self.failglob {
+                restore_env(prev_env);
+                let err_str = format!("no matches found for pattern: {}", arg);
+                self.print_error(&err_str);
+                return None;
+            } else {
+                args.push(arg.to_string());
+            }
+        }
         Some((executable_final, args, prev_env))
     }
 
@@ -270,6 +395,29 @@ impl VM {
         1
     }
 
+    /// Takes a boolean as its single argument.  If the boolean is
+    /// true, then a glob pattern in a command that does not match any
+    /// files causes an error.  Otherwise (the default), the pattern
+    /// is passed to the command unchanged.
+    pub fn core_set_failglob(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("set-failglob requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        match value_rr {
+            Value::Bool(b) => {
+                self.failglob = b;
+                1
+            }
+            _ => {
+                self.print_error("set-failglob argument must be boolean");
+                0
+            }
+        }
+    }
+
     /// Takes a generator and a command as its arguments.  Takes
     /// output from the generator and pipes it to the standard input
     /// of the command, and places a generator over the command's
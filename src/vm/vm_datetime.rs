@@ -6,9 +6,22 @@ use std::str::FromStr;
 use chrono::format::{parse, Parsed, StrftimeItems};
 use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
 use chronoutil::RelativeDuration;
+use num::FromPrimitive;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
 
 use vm::*;
 
+/// The units supported by humanize-duration and parse-duration,
+/// along with the number of milliseconds in each.
+const DURATION_UNITS: [(&str, u64); 5] = [
+    ("d", 86_400_000),
+    ("h", 3_600_000),
+    ("m", 60_000),
+    ("s", 1000),
+    ("ms", 1),
+];
+
 impl VM {
     /// Returns the current time as a date-time object, offset at UTC.
     pub fn core_now(&mut self) -> i32 {
@@ -454,4 +467,135 @@ impl VM {
             }
         }
     }
+
+    /// Takes a number of seconds as its single argument, and returns
+    /// a string describing that duration in days, hours, minutes,
+    /// seconds, and milliseconds (e.g. "1h 2m 3s"), omitting units
+    /// with a value of zero.
+    pub fn core_humanize_duration(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("humanize-duration requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let ms_opt = match value_rr {
+            Value::Int(n) => Some(n as i64 * 1000),
+            Value::BigInt(ref n) => n.to_i64().and_then(|n| n.checked_mul(1000)),
+            Value::Float(f) if (f * 1000.0).abs() < i64::MAX as f64 => {
+                Some((f * 1000.0).round() as i64)
+            }
+            _ => None,
+        };
+        let ms = match ms_opt {
+            Some(ms) => ms,
+            None => {
+                self.print_error("humanize-duration argument must be number of seconds");
+                return 0;
+            }
+        };
+
+        let mut remaining = ms.unsigned_abs();
+        let mut parts = Vec::new();
+        for (unit, unit_ms) in DURATION_UNITS.iter() {
+            let count = remaining / unit_ms;
+            remaining %= unit_ms;
+            if count > 0 {
+                parts.push(format!("{}{}", count, unit));
+            }
+        }
+        let result = if parts.is_empty() {
+            "0s".to_string()
+        } else if ms < 0 {
+            format!("-{}", parts.join(" "))
+        } else {
+            parts.join(" ")
+        };
+
+        let st = StringTriple::new(result, None);
+        self.stack.push(Value::String(Rc::new(RefCell::new(st))));
+        1
+    }
+
+    /// Takes a duration string (e.g. "1h30m", "2d 4h", "250ms") as
+    /// its single argument, and returns the number of seconds in that
+    /// duration.  A leading minus sign negates the duration.  The
+    /// result is an integer if the duration is a whole number of
+    /// seconds, and a float otherwise.
+    pub fn core_parse_duration(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("parse-duration requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        let value = match value_opt {
+            Some(value) => value,
+            _ => {
+                self.print_error("parse-duration argument must be string");
+                return 0;
+            }
+        };
+
+        /* A leading minus sign applies to the whole duration, as per
+         * the output of humanize-duration. */
+        let mut total_ms = 0.0;
+        let mut rest = value.trim_start();
+        let negative = match rest.strip_prefix('-') {
+            Some(after_sign) => {
+                rest = after_sign.trim_start();
+                true
+            }
+            None => false,
+        };
+        if rest.is_empty() {
+            self.print_error("parse-duration argument must be duration string");
+            return 0;
+        }
+        while !rest.is_empty() {
+            let number_len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let (number_str, after_number) = rest.split_at(number_len);
+            let unit_len = after_number
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(after_number.len());
+            let (unit, after_unit) = after_number.split_at(unit_len);
+            let number_opt = number_str.parse::<f64>().ok();
+            let unit_ms_opt = DURATION_UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .map(|(_, unit_ms)| *unit_ms);
+            match (number_opt, unit_ms_opt) {
+                (Some(number), Some(unit_ms)) => {
+                    total_ms += number * unit_ms as f64;
+                }
+                _ => {
+                    self.print_error("parse-duration argument must be duration string");
+                    return 0;
+                }
+            }
+            rest = after_unit.trim_start();
+        }
+
+        let total_ms = if negative {
+            -total_ms.round()
+        } else {
+            total_ms.round()
+        };
+        if total_ms % 1000.0 == 0.0 {
+            let seconds = total_ms / 1000.0;
+            if seconds >= i32::MIN as f64 && seconds <= i32::MAX as f64 {
+                self.stack.push(Value::Int(seconds as i32));
+            } else {
+                self.stack
+                    .push(Value::BigInt(BigInt::from_f64(seconds).unwrap()));
+            }
+        } else {
+            self.stack.push(Value::Float(total_ms / 1000.0));
+        }
+        1
+    }
 }
@@ -1,12 +1,103 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
 use sha1::{Digest, Sha1};
 use sha2::{Sha256, Sha512};
 
 use chunk::{StringTriple, Value};
 use vm::*;
 
+/// Appends a length-prefixed string to the fingerprint encoding.
+fn fingerprint_str(tag: u8, s: &str, out: &mut Vec<u8>) {
+    out.push(tag);
+    out.extend_from_slice(&(s.len() as u64).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Helper function for hash-of.  Appends a canonical encoding of the
+/// value to the output buffer.  The keys of hashes and sets are
+/// sorted, so that insertion order does not affect the encoding.
+/// `seen` contains the composite values currently being encoded, so
+/// that cyclic structures can be detected.  Returns an error message
+/// if the value (or a nested value) cannot be fingerprinted.
+fn fingerprint_encode(
+    value_rr: &Value,
+    out: &mut Vec<u8>,
+    seen: &mut Vec<usize>,
+) -> Result<(), &'static str> {
+    match value_rr {
+        Value::Null => out.push(b'n'),
+        Value::Bool(b) => {
+            out.push(b'b');
+            out.push(*b as u8);
+        }
+        /* ints, bigints, and integral floats are encoded in the
+         * same way, so that equal numbers have the same fingerprint
+         * regardless of their representation. */
+        Value::Int(n) => fingerprint_str(b'i', &n.to_string(), out),
+        Value::BigInt(n) => fingerprint_str(b'i', &n.to_string(), out),
+        Value::Float(f) => match BigInt::from_f64(*f) {
+            Some(n) if f.fract() == 0.0 => {
+                fingerprint_str(b'i', &n.to_string(), out);
+            }
+            _ => {
+                out.push(b'f');
+                out.extend_from_slice(&f.to_bits().to_be_bytes());
+            }
+        },
+        Value::String(st) => fingerprint_str(b's', &st.borrow().string, out),
+        Value::DateTimeNT(dt) => fingerprint_str(b'd', &format!("{:?}", dt), out),
+        Value::DateTimeOT(dt) => fingerprint_str(b'd', &format!("{:?}", dt), out),
+        Value::Ipv4(_)
+        | Value::Ipv6(_)
+        | Value::Ipv4Range(_)
+        | Value::Ipv6Range(_)
+        | Value::IpSet(_) => {
+            let s = value_rr.to_string().unwrap_or(None).unwrap_or_default();
+            fingerprint_str(b'a', &s, out);
+        }
+        Value::List(lst) => {
+            let ptr = lst.as_ptr() as usize;
+            if seen.contains(&ptr) {
+                return Err("hash-of argument must not be cyclic");
+            }
+            seen.push(ptr);
+            out.push(b'l');
+            out.extend_from_slice(&(lst.borrow().len() as u64).to_be_bytes());
+            for v in lst.borrow().iter() {
+                fingerprint_encode(v, out, seen)?;
+            }
+            seen.pop();
+        }
+        Value::Hash(map) | Value::Set(map) => {
+            let ptr = map.as_ptr() as usize;
+            if seen.contains(&ptr) {
+                return Err("hash-of argument must not be cyclic");
+            }
+            seen.push(ptr);
+            let is_hash = matches!(value_rr, Value::Hash(_));
+            out.push(if is_hash { b'h' } else { b'S' });
+            let map_b = map.borrow();
+            out.extend_from_slice(&(map_b.len() as u64).to_be_bytes());
+            let mut keys: Vec<&String> = map_b.keys().collect();
+            keys.sort();
+            for k in keys {
+                fingerprint_str(b'k', k, out);
+                if is_hash {
+                    fingerprint_encode(map_b.get(k).unwrap(), out, seen)?;
+                }
+            }
+            seen.pop();
+        }
+        _ => {
+            return Err("hash-of argument must be hashable");
+        }
+    }
+    Ok(())
+}
+
 impl VM {
     /// Takes a string as its single argument.  Hashes the string
     /// using the MD5 algorithm and adds the result to the stack.
@@ -83,7 +174,7 @@ impl VM {
                 self.stack.push(Value::String(Rc::new(RefCell::new(st))));
             }
             _ => {
-                self.print_error("sha1 argument must be string");
+                self.print_error("sha256 argument must be string");
                 return 0;
             }
         }
@@ -111,10 +202,38 @@ impl VM {
                 self.stack.push(Value::String(Rc::new(RefCell::new(st))));
             }
             _ => {
-                self.print_error("sha1 argument must be string");
+                self.print_error("sha512 argument must be string");
                 return 0;
             }
         }
         1
     }
+
+    /// Takes a value as its single argument, and returns a 64-bit
+    /// fingerprint for that value as a bigint.  Lists, hashes and
+    /// sets are fingerprinted by their contents, with hash and set
+    /// keys being sorted first, so that structurally equal values
+    /// have the same fingerprint.  Functions, generators, and similar
+    /// values cannot be fingerprinted.
+    pub fn core_hash_of(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("hash-of requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let mut encoded = Vec::new();
+        let mut seen = Vec::new();
+        if let Err(e) = fingerprint_encode(&value_rr, &mut encoded, &mut seen) {
+            self.print_error(e);
+            return 0;
+        }
+
+        let digest = Sha256::digest(&encoded);
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        let n = u64::from_be_bytes(bytes);
+        self.stack.push(Value::BigInt(BigInt::from(n)));
+        1
+    }
 }
@@ -96,4 +96,106 @@ impl VM {
 
         1
     }
+
+    /// Takes an environment variable name as its argument.  Removes
+    /// the environment variable with that name, if it exists.
+    pub fn core_unsetenv(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("unsetenv requires one argument");
+            return 0;
+        }
+
+        let key_rr = self.stack.pop().unwrap();
+        let key_opt: Option<&str>;
+        to_str!(key_rr, key_opt);
+        match key_opt {
+            Some(s) if !s.is_empty() && !s.contains('=') => {
+                env::remove_var(s);
+                1
+            }
+            _ => {
+                self.print_error("unsetenv argument must be a variable name");
+                0
+            }
+        }
+    }
+
+    /// Takes a string and an optional boolean as its arguments.
+    /// Replaces `$VAR` and `${VAR}` references in the string with the
+    /// values of the corresponding environment variables, and puts
+    /// the result onto the stack.  `\$` produces a literal dollar
+    /// sign.  Unset variables are replaced with the empty string,
+    /// unless the boolean is true, in which case they cause an error.
+    pub fn core_expand(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("expand requires one argument");
+            return 0;
+        }
+
+        let strict = self.pop_optional_flag(1);
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        let value = match value_opt {
+            Some(value) => value,
+            _ => {
+                self.print_error("expand argument must be a string");
+                return 0;
+            }
+        };
+
+        let chars: Vec<char> = value.chars().collect();
+        let mut result = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '\\' && chars.get(i + 1) == Some(&'$') {
+                result.push('$');
+                i += 2;
+                continue;
+            }
+            if c != '$' {
+                result.push(c);
+                i += 1;
+                continue;
+            }
+            let (name, next): (String, usize) = if chars.get(i + 1) == Some(&'{') {
+                match chars[i + 2..].iter().position(|c| *c == '}') {
+                    Some(len) => (chars[i + 2..i + 2 + len].iter().collect(), i + 3 + len),
+                    None => (String::new(), i + 1),
+                }
+            } else {
+                let len = chars[i + 1..]
+                    .iter()
+                    .enumerate()
+                    .take_while(|(index, c)| {
+                        c.is_ascii_alphabetic() || **c == '_' || (*index > 0 && c.is_ascii_digit())
+                    })
+                    .count();
+                (chars[i + 1..i + 1 + len].iter().collect(), i + 1 + len)
+            };
+            if name.is_empty() {
+                result.push('$');
+                i += 1;
+                continue;
+            }
+            match env::var_os(&name) {
+                Some(var_value) => result.push_str(&var_value.to_string_lossy()),
+                None if strict => {
+                    let err_str = format!("unable to expand: {} is not set", name);
+                    self.print_error(&err_str);
+                    return 0;
+                }
+                None => {}
+            }
+            i = next;
+        }
+
+        self.stack
+            .push(Value::String(Rc::new(RefCell::new(StringTriple::new(
+                result, None,
+            )))));
+        1
+    }
 }
@@ -4,6 +4,112 @@ use std::rc::Rc;
 use chunk::{HashWithIndex, Value};
 use vm::*;
 
+/// Helper function for flatten-hash.  Adds the leaf values from the
+/// given value to the flat hash, using the prefix as the key for the
+/// value itself and as the basis for the keys of any nested values.
+fn flatten_value(prefix: &str, value_rr: &Value, flat: &mut IndexMap<String, Value>) {
+    match value_rr {
+        Value::Hash(map) if !map.borrow().is_empty() => {
+            for (k, v) in map.borrow().iter() {
+                flatten_value(&format!("{}.{}", prefix, k), v, flat);
+            }
+        }
+        Value::List(lst) if !lst.borrow().is_empty() => {
+            for (i, v) in lst.borrow().iter().enumerate() {
+                flatten_value(&format!("{}.{}", prefix, i), v, flat);
+            }
+        }
+        _ => {
+            flat.insert(prefix.to_string(), value_rr.clone());
+        }
+    }
+}
+
+/// The unflatten-hash error for a path that uses a non-integer
+/// segment for a list.
+const LIST_PATH_ERROR: &str = "unflatten-hash list path elements must be integers";
+/// The unflatten-hash error for a path that conflicts with another
+/// path (e.g. "a" and "a.b").
+const CONFLICT_ERROR: &str = "unflatten-hash key paths must not conflict";
+
+/// Helper function for unflatten-hash.  Sets the value at the given
+/// path within the root value, creating intermediate hashes and lists
+/// as required.  A path segment that is a non-negative integer is
+/// treated as a list index.  Returns an error message if the path
+/// could not be set (e.g. because it uses a non-integer segment for a
+/// list, or because it conflicts with a path that has already been
+/// set).
+fn unflatten_set(root: &Value, path: &[&str], value_rr: Value) -> Result<(), &'static str> {
+    let (last, init) = path.split_last().unwrap();
+    let mut container = root.clone();
+    for (i, seg) in init.iter().enumerate() {
+        let child = match &container {
+            Value::Hash(map) => map.borrow().get(*seg).cloned(),
+            Value::List(lst) => match seg.parse::<usize>() {
+                Ok(n) => lst.borrow().get(n).cloned(),
+                Err(_) => return Err(LIST_PATH_ERROR),
+            },
+            _ => return Err(LIST_PATH_ERROR),
+        };
+        let child = match child {
+            Some(c @ Value::Hash(_)) | Some(c @ Value::List(_)) => c,
+            /* A list may contain nulls that were added when it was
+             * extended, and these may be replaced.  Any other
+             * existing value is a leaf for a shorter path. */
+            Some(Value::Null) if matches!(container, Value::List(_)) => {
+                new_container(&container, seg, path[i + 1])?
+            }
+            Some(_) => return Err(CONFLICT_ERROR),
+            None => new_container(&container, seg, path[i + 1])?,
+        };
+        container = child;
+    }
+    container_set(&container, last, value_rr)
+}
+
+/// Helper function for unflatten_set.  Creates a hash or list (per
+/// the next path segment) for the given key in the container, and
+/// returns it.
+fn new_container(container: &Value, key: &str, next: &str) -> Result<Value, &'static str> {
+    let c = if next.parse::<usize>().is_ok() {
+        Value::List(Rc::new(RefCell::new(VecDeque::new())))
+    } else {
+        Value::Hash(Rc::new(RefCell::new(IndexMap::new())))
+    };
+    container_set(container, key, c.clone())?;
+    Ok(c)
+}
+
+/// Helper function for unflatten_set.  Sets the value for the given
+/// key in a hash or list, extending the list with nulls if required.
+/// Returns an error message if the key already has a value.
+fn container_set(container: &Value, key: &str, value_rr: Value) -> Result<(), &'static str> {
+    match container {
+        Value::Hash(map) => {
+            let mut map = map.borrow_mut();
+            if map.contains_key(key) {
+                return Err(CONFLICT_ERROR);
+            }
+            map.insert(key.to_string(), value_rr);
+            Ok(())
+        }
+        Value::List(lst) => match key.parse::<usize>() {
+            Ok(n) => {
+                let mut lst = lst.borrow_mut();
+                if lst.len() <= n {
+                    lst.resize(n + 1, Value::Null);
+                } else if !matches!(lst[n], Value::Null) {
+                    return Err(CONFLICT_ERROR);
+                }
+                lst[n] = value_rr;
+                Ok(())
+            }
+            Err(_) => Err(LIST_PATH_ERROR),
+        },
+        _ => Err(LIST_PATH_ERROR),
+    }
+}
+
 impl VM {
     /// Takes a hash or list (or generator) and a key string or list
     /// index (or list of keys/indexes) as its arguments.  Puts the
@@ -340,4 +446,176 @@ impl VM {
         }
         1
     }
+
+    /// Helper function for comparing two values for equality, where
+    /// hashes are compared without regard to the order of their keys.
+    /// Lists are compared element by element, and all other values
+    /// are compared as per `=`.  `seen` contains the pairs of
+    /// composite values currently being compared, so that cyclic
+    /// structures do not cause infinite recursion.
+    pub fn deep_eq_inner(
+        &mut self,
+        v1: &Value,
+        v2: &Value,
+        seen: &mut Vec<(usize, usize)>,
+    ) -> bool {
+        match (v1, v2) {
+            (Value::Hash(h1), Value::Hash(h2)) | (Value::Set(h1), Value::Set(h2)) => {
+                let pair = (h1.as_ptr() as usize, h2.as_ptr() as usize);
+                if Rc::ptr_eq(h1, h2) || seen.contains(&pair) {
+                    return true;
+                }
+                if h1.borrow().len() != h2.borrow().len() {
+                    return false;
+                }
+                seen.push(pair);
+                let mut res = true;
+                for (k, hv1) in h1.borrow().iter() {
+                    let hv2 = match h2.borrow().get(k) {
+                        Some(hv2) => hv2.clone(),
+                        None => {
+                            res = false;
+                            break;
+                        }
+                    };
+                    if !self.deep_eq_inner(hv1, &hv2, seen) {
+                        res = false;
+                        break;
+                    }
+                }
+                seen.pop();
+                res
+            }
+            (Value::List(l1), Value::List(l2)) => {
+                let pair = (l1.as_ptr() as usize, l2.as_ptr() as usize);
+                if Rc::ptr_eq(l1, l2) || seen.contains(&pair) {
+                    return true;
+                }
+                if l1.borrow().len() != l2.borrow().len() {
+                    return false;
+                }
+                seen.push(pair);
+                let mut res = true;
+                for (lv1, lv2) in l1.borrow().iter().zip(l2.borrow().iter()) {
+                    if !self.deep_eq_inner(lv1, lv2, seen) {
+                        res = false;
+                        break;
+                    }
+                }
+                seen.pop();
+                res
+            }
+            (Value::Hash(_), _)
+            | (_, Value::Hash(_))
+            | (Value::Set(_), _)
+            | (_, Value::Set(_))
+            | (Value::List(_), _)
+            | (_, Value::List(_)) => false,
+            _ => self.opcode_eq_inner(v1, v2) == 1,
+        }
+    }
+
+    /// Takes two hashes and returns a boolean indicating whether they
+    /// contain the same keys and values, ignoring the order in which
+    /// the keys were inserted.  Nested hashes are compared in the
+    /// same way.
+    pub fn core_hash_eq(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("hash-eq requires two arguments");
+            return 0;
+        }
+
+        let h1_rr = self.stack.pop().unwrap();
+        let h2_rr = self.stack.pop().unwrap();
+
+        match (&h1_rr, &h2_rr) {
+            (Value::Hash(_), Value::Hash(_)) => {
+                let mut seen = Vec::new();
+                let res = self.deep_eq_inner(&h2_rr, &h1_rr, &mut seen);
+                self.stack.push(Value::Bool(res));
+                1
+            }
+            _ => {
+                self.print_error("hash-eq arguments must be hashes");
+                0
+            }
+        }
+    }
+
+    /// Takes two values and returns a boolean indicating whether they
+    /// are structurally equal.  Lists are compared element by
+    /// element, hashes and sets are compared by their keys and values
+    /// (ignoring insertion order), and all other values are compared
+    /// as per `=`.
+    pub fn core_deep_eq(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("eq? requires two arguments");
+            return 0;
+        }
+
+        let v1_rr = self.stack.pop().unwrap();
+        let v2_rr = self.stack.pop().unwrap();
+
+        let mut seen = Vec::new();
+        let res = self.deep_eq_inner(&v2_rr, &v1_rr, &mut seen);
+        self.stack.push(Value::Bool(res));
+        1
+    }
+
+    /// Takes a hash as its single argument, and returns a flat hash
+    /// mapping from dotted key paths (e.g. "a.b.c") to the leaf
+    /// values of the original hash.  List elements are included in
+    /// the path by index.
+    pub fn core_flatten_hash(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("flatten-hash requires one argument");
+            return 0;
+        }
+
+        let hash_rr = self.stack.pop().unwrap();
+        match hash_rr {
+            Value::Hash(map) => {
+                let mut flat = IndexMap::new();
+                for (k, v) in map.borrow().iter() {
+                    flatten_value(k, v, &mut flat);
+                }
+                self.stack.push(Value::Hash(Rc::new(RefCell::new(flat))));
+                1
+            }
+            _ => {
+                self.print_error("flatten-hash argument must be hash");
+                0
+            }
+        }
+    }
+
+    /// Takes a flat hash with dotted key paths as its single
+    /// argument, and returns the corresponding nested hash.  This is
+    /// the inverse of flatten-hash.
+    pub fn core_unflatten_hash(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("unflatten-hash requires one argument");
+            return 0;
+        }
+
+        let hash_rr = self.stack.pop().unwrap();
+        match hash_rr {
+            Value::Hash(map) => {
+                let root = Value::Hash(Rc::new(RefCell::new(IndexMap::new())));
+                for (k, v) in map.borrow().iter() {
+                    let path: Vec<&str> = k.split('.').collect();
+                    if let Err(e) = unflatten_set(&root, &path, v.clone()) {
+                        self.print_error(e);
+                        return 0;
+                    }
+                }
+                self.stack.push(root);
+                1
+            }
+            _ => {
+                self.print_error("unflatten-hash argument must be hash");
+                0
+            }
+        }
+    }
 }
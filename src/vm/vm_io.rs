@@ -1,39 +1,49 @@
 use std::cell::RefCell;
 use std::fs::metadata;
+use std::fs::DirBuilder;
 use std::fs::File;
+use std::fs::OpenOptions;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::BufWriter;
+use std::io::ErrorKind;
 use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
 use std::rc::Rc;
 
-use lazy_static::lazy_static;
-use regex::Regex;
+use nix::unistd::User;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
 use tempfile::{NamedTempFile, TempDir};
 
 use chunk::{StringTriple, Value};
 use vm::*;
 
-lazy_static! {
-    static ref HOME_DIR_TILDE: Regex = Regex::new("~").unwrap();
-}
-
-/// Takes a path, and replaces any ~ characters with the user's home
-/// directory (if available).
-fn tilde_expansion(input_s: &str) -> String {
-    let homedir_res = std::env::var("HOME");
-    let final_s;
-    match homedir_res {
-        Ok(homedir) => {
-            let s = "".to_owned() + &homedir;
-            final_s = HOME_DIR_TILDE.replace_all(input_s, &*s).to_string();
-        }
-        _ => {
-            final_s = input_s.to_string();
+/// Takes a path, and if it begins with "~" or "~user", replaces that
+/// component with the home directory of the current user or the
+/// specified user (if available).  Tildes elsewhere in the path are
+/// left alone.
+pub fn tilde_expansion(input_s: &str) -> String {
+    if !input_s.starts_with('~') {
+        return input_s.to_string();
+    }
+    let (name, rest) = match input_s.find('/') {
+        Some(index) => input_s.split_at(index),
+        None => (input_s, ""),
+    };
+    let homedir_opt = if name == "~" {
+        std::env::var("HOME").ok()
+    } else {
+        match User::from_name(&name[1..]) {
+            Ok(Some(user)) => Some(user.dir.to_string_lossy().to_string()),
+            _ => None,
         }
+    };
+    match homedir_opt {
+        Some(homedir) => homedir + rest,
+        None => input_s.to_string(),
     }
-
-    final_s
 }
 
 impl VM {
@@ -110,6 +120,37 @@ impl VM {
         1
     }
 
+    /// Reads one line from standard input, and places it onto the
+    /// stack (without the ending newline).  If standard input is at
+    /// EOF, places null onto the stack.
+    pub fn core_read(&mut self) -> i32 {
+        let mut line = String::new();
+        match std::io::stdin().lock().read_line(&mut line) {
+            Ok(0) => {
+                self.stack.push(Value::Null);
+                1
+            }
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                self.stack
+                    .push(Value::String(Rc::new(RefCell::new(StringTriple::new(
+                        line, None,
+                    )))));
+                1
+            }
+            Err(e) => {
+                let err_str = format!("unable to read from standard input: {}", e);
+                self.print_error(&err_str);
+                0
+            }
+        }
+    }
+
     /// Takes a FileReader object as its single argument.  Reads one
     /// line from that object and places it onto the stack (including
     /// the ending newline).
@@ -313,7 +354,8 @@ impl VM {
 
         match path_str_opt {
             Some(s) => {
-                let metadata_res = metadata(s);
+                let ss = tilde_expansion(s);
+                let metadata_res = metadata(ss);
                 match metadata_res {
                     Ok(metadata) => {
                         let is_dir = metadata.is_dir();
@@ -386,4 +428,65 @@ impl VM {
             }
         }
     }
+
+    /// Takes a description of the entry type (for error messages) and
+    /// a function for creating a filesystem entry at a given path as
+    /// its arguments.  Creates an entry with a random name (per the
+    /// VM's random number generator) in the system's temporary
+    /// directory, retrying if the name is already in use, and puts
+    /// its path on the stack.
+    fn make_temp_entry(
+        &mut self,
+        entry_type: &str,
+        create: fn(&Path) -> std::io::Result<()>,
+    ) -> i32 {
+        let temp_dir = std::env::temp_dir();
+        loop {
+            let name: String = (&mut self.rng)
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(char::from)
+                .collect();
+            let path = temp_dir.join(format!("cosh-{}", name));
+            match create(&path) {
+                Ok(_) => {
+                    self.stack
+                        .push(Value::String(Rc::new(RefCell::new(StringTriple::new(
+                            path.to_str().unwrap().to_string(),
+                            None,
+                        )))));
+                    return 1;
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    let err_str = format!("unable to create temporary {}: {}", entry_type, e);
+                    self.print_error(&err_str);
+                    return 0;
+                }
+            }
+        }
+    }
+
+    /// Creates a new empty file with a unique name in the system's
+    /// temporary directory, and puts its path on the stack.  The file
+    /// is readable and writable by the current user only.
+    pub fn core_mktemp(&mut self) -> i32 {
+        self.make_temp_entry("file", |path| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)
+                .map(|_| ())
+        })
+    }
+
+    /// Creates a new directory with a unique name in the system's
+    /// temporary directory, and puts its path on the stack.  The
+    /// directory is accessible by the current user only.
+    pub fn core_mktempdir(&mut self) -> i32 {
+        self.make_temp_entry("directory", |path| {
+            DirBuilder::new().mode(0o700).create(path)
+        })
+    }
 }
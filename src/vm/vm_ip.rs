@@ -4,8 +4,10 @@ use ipnet::{Ipv4Net, Ipv6Net};
 use iprange::IpRange;
 use num_bigint::{BigInt, BigUint};
 use num_traits::{FromPrimitive, ToPrimitive, Zero};
+use rand::Rng;
 
 use chunk::{IpSet, Ipv4Range, Ipv6Range};
+use vm::vm_basics::gen_bigint_below;
 use vm::*;
 
 /// Convert an IPv4 address to a u32.
@@ -130,7 +132,204 @@ fn ipv6range_to_nets(ipv6range: Ipv6Range) -> VecDeque<Ipv6Net> {
     lst
 }
 
+/// Get the first and last addresses of an IPv4 object as integers.
+fn ipv4_bounds(value: &Value) -> Option<(u32, u32)> {
+    match value {
+        Value::Ipv4(ipv4net) => Some((
+            ipv4_addr_to_int(ipv4net.network()),
+            ipv4_addr_to_int(ipv4net.broadcast()),
+        )),
+        Value::Ipv4Range(ipv4range) => {
+            Some((ipv4_addr_to_int(ipv4range.s), ipv4_addr_to_int(ipv4range.e)))
+        }
+        _ => None,
+    }
+}
+
+/// Get the first and last addresses of an IPv6 object as integers.
+fn ipv6_bounds(value: &Value) -> Option<(BigUint, BigUint)> {
+    match value {
+        Value::Ipv6(ipv6net) => Some((
+            ipv6_addr_to_int(ipv6net.network()),
+            ipv6_addr_to_int(ipv6net.broadcast()),
+        )),
+        Value::Ipv6Range(ipv6range) => {
+            Some((ipv6_addr_to_int(ipv6range.s), ipv6_addr_to_int(ipv6range.e)))
+        }
+        _ => None,
+    }
+}
+
+/// Get the number of addresses covered by an IP object.
+fn ip_size(value: &Value) -> Option<BigInt> {
+    if let Some((s, e)) = ipv4_bounds(value) {
+        Some(BigInt::from(e) - BigInt::from(s) + 1)
+    } else {
+        ipv6_bounds(value).map(|(s, e)| BigInt::from(e - s) + 1)
+    }
+}
+
+/// IPv4 private address blocks (RFC 1918), as network/prefix length
+/// pairs.
+const IPV4_PRIVATE: &[(u32, u32)] = &[(0x0A000000, 8), (0xAC100000, 12), (0xC0A80000, 16)];
+/// IPv4 loopback address blocks.
+const IPV4_LOOPBACK: &[(u32, u32)] = &[(0x7F000000, 8)];
+/// IPv4 multicast address blocks.
+const IPV4_MULTICAST: &[(u32, u32)] = &[(0xE0000000, 4)];
+/// IPv6 private (unique local) address blocks.
+const IPV6_PRIVATE: &[(u128, u32)] = &[(0xfc00 << 112, 7)];
+/// IPv6 loopback address blocks.
+const IPV6_LOOPBACK: &[(u128, u32)] = &[(1, 128)];
+/// IPv6 multicast address blocks.
+const IPV6_MULTICAST: &[(u128, u32)] = &[(0xff00 << 112, 8)];
+
+/// Returns a boolean indicating whether the IPv4 range from s to e
+/// falls entirely within one of the given blocks.
+fn ipv4_within(s: u32, e: u32, blocks: &[(u32, u32)]) -> bool {
+    blocks.iter().any(|&(network, len)| {
+        let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+        (s & mask) == network && (e & mask) == network
+    })
+}
+
+/// Returns a boolean indicating whether the IPv6 range from s to e
+/// falls entirely within one of the given blocks.
+fn ipv6_within(s: u128, e: u128, blocks: &[(u128, u32)]) -> bool {
+    blocks.iter().any(|&(network, len)| {
+        let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+        (s & mask) == network && (e & mask) == network
+    })
+}
+
 impl VM {
+    /// Takes the name of the calling form and the IPv4 and IPv6
+    /// address blocks for the relevant address space as its
+    /// arguments.  Returns a boolean indicating whether the IP object
+    /// argument falls entirely within that address space.
+    fn ip_within(
+        &mut self,
+        form: &str,
+        ipv4_blocks: &[(u32, u32)],
+        ipv6_blocks: &[(u128, u32)],
+    ) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error(&format!("{} requires one argument", form));
+            return 0;
+        }
+
+        let ip_rr = self.stack.pop().unwrap();
+        let res = if let Some((s, e)) = ipv4_bounds(&ip_rr) {
+            ipv4_within(s, e, ipv4_blocks)
+        } else if let Some((s, e)) = ipv6_bounds(&ip_rr) {
+            ipv6_within(s.to_u128().unwrap(), e.to_u128().unwrap(), ipv6_blocks)
+        } else {
+            self.print_error(&format!("{} argument must be ip object", form));
+            return 0;
+        };
+        self.stack.push(Value::Bool(res));
+        1
+    }
+
+    /// Takes the name of the calling form and a boolean indicating
+    /// whether to step forwards or backwards as its arguments.
+    /// Replaces the single-address IP object argument with an IP
+    /// object for the adjacent address.
+    fn ip_step(&mut self, form: &str, forward: bool) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error(&format!("{} requires one argument", form));
+            return 0;
+        }
+
+        let ip_rr = self.stack.pop().unwrap();
+        let end = if forward { "last" } else { "first" };
+        if let Some((s, e)) = ipv4_bounds(&ip_rr) {
+            if s != e {
+                self.print_error(&format!(
+                    "{} argument must be single-address IP object",
+                    form
+                ));
+                return 0;
+            }
+            let n_opt = if forward {
+                s.checked_add(1)
+            } else {
+                s.checked_sub(1)
+            };
+            match n_opt {
+                Some(n) => {
+                    let ipv4 = int_to_ipv4_addr(n);
+                    self.stack
+                        .push(Value::Ipv4(Ipv4Net::new(ipv4, 32).unwrap()));
+                    1
+                }
+                None => {
+                    self.print_error(&format!("{} argument is {} IPv4 address", form, end));
+                    0
+                }
+            }
+        } else if let Some((s, e)) = ipv6_bounds(&ip_rr) {
+            if s != e {
+                self.print_error(&format!(
+                    "{} argument must be single-address IP object",
+                    form
+                ));
+                return 0;
+            }
+            let s = s.to_u128().unwrap();
+            let n_opt = if forward {
+                s.checked_add(1)
+            } else {
+                s.checked_sub(1)
+            };
+            match n_opt {
+                Some(n) => {
+                    let ipv6 = int_to_ipv6_addr(BigUint::from(n));
+                    self.stack
+                        .push(Value::Ipv6(Ipv6Net::new(ipv6, 128).unwrap()));
+                    1
+                }
+                None => {
+                    self.print_error(&format!("{} argument is {} IPv6 address", form, end));
+                    0
+                }
+            }
+        } else {
+            self.print_error(&format!("{} argument must be ip object", form));
+            0
+        }
+    }
+
+    /// Returns an IP object for the address following the
+    /// single-address IP object argument.
+    pub fn core_ip_next(&mut self) -> i32 {
+        self.ip_step("ip.next", true)
+    }
+
+    /// Returns an IP object for the address preceding the
+    /// single-address IP object argument.
+    pub fn core_ip_prev(&mut self) -> i32 {
+        self.ip_step("ip.prev", false)
+    }
+
+    /// Returns a boolean indicating whether the IP object is within
+    /// private address space (RFC 1918 for IPv4, fc00::/7 for IPv6).
+    pub fn core_ip_is_private(&mut self) -> i32 {
+        self.ip_within("ip.is-private", IPV4_PRIVATE, IPV6_PRIVATE)
+    }
+
+    /// Returns a boolean indicating whether the IP object is within
+    /// loopback address space (127.0.0.0/8 for IPv4, ::1 for IPv6).
+    pub fn core_ip_is_loopback(&mut self) -> i32 {
+        self.ip_within("ip.is-loopback", IPV4_LOOPBACK, IPV6_LOOPBACK)
+    }
+
+    /// Returns a boolean indicating whether the IP object is within
+    /// multicast address space (224.0.0.0/4 for IPv4, ff00::/8 for
+    /// IPv6).
+    pub fn core_ip_is_multicast(&mut self) -> i32 {
+        self.ip_within("ip.is-multicast", IPV4_MULTICAST, IPV6_MULTICAST)
+    }
+
     /// Parses an IP address or range and returns an IP object.
     pub fn core_ip(&mut self) -> i32 {
         if self.stack.is_empty() {
@@ -173,7 +372,7 @@ impl VM {
 
                         match (ipv4_fst, ipv4_snd) {
                             (Ok(ipv4_fst_obj), Ok(ipv4_snd_obj)) => {
-                                if ipv4_fst_obj >= ipv4_snd_obj {
+                                if ipv4_fst_obj > ipv4_snd_obj {
                                     self.print_error("ip argument must be valid IP address string");
                                     return 0;
                                 }
@@ -253,7 +452,7 @@ impl VM {
 
                     match (ipv6_fst, ipv6_snd) {
                         (Ok(ipv6_fst_obj), Ok(ipv6_snd_obj)) => {
-                            if ipv6_fst_obj >= ipv6_snd_obj {
+                            if ipv6_fst_obj > ipv6_snd_obj {
                                 self.print_error("ip argument must be valid IP address string");
                                 return 0;
                             }
@@ -408,24 +607,18 @@ impl VM {
             Value::Ipv4Range(ipv4range) => {
                 let s = ipv4range.s;
                 let e = ipv4range.e;
-                let s_num = ipv4_addr_to_int(s);
-                let e_num = ipv4_addr_to_int(e);
-                if s_num == 0 && e_num == 0xFFFFFFFF {
-                    self.stack.push(Value::Int(0));
-                    return 1;
-                }
-                let mut host_count = e_num - s_num + 1;
-                let mut len = 32;
-                if host_count & (host_count - 1) == 0 {
-                    loop {
-                        if host_count == 1 {
-                            break;
-                        } else {
-                            host_count >>= 1;
-                            len -= 1;
-                        }
-                    }
-                    self.stack.push(Value::Int(len));
+                /* The host count is calculated using u64, so that
+                 * it cannot overflow for the range covering the
+                 * entire address space. */
+                let s_num = ipv4_addr_to_int(s) as u64;
+                let e_num = ipv4_addr_to_int(e) as u64;
+                let host_count = e_num - s_num + 1;
+                /* The range only has a length if it is the same as
+                 * a prefix, i.e. if it has a power-of-two number of
+                 * hosts and starts on a boundary for that number. */
+                if host_count.is_power_of_two() && (s_num & (host_count - 1)) == 0 {
+                    let len = 32 - host_count.trailing_zeros();
+                    self.stack.push(Value::Int(len as i32));
                     1
                 } else {
                     self.print_error("ip.len argument has no length");
@@ -444,9 +637,10 @@ impl VM {
                 let e_num = ipv6_addr_to_int(e);
                 let zero = BigUint::zero();
                 let one = BigUint::from(1u8);
-                let mut host_count = e_num - s_num + one.clone();
+                let mut host_count = e_num - s_num.clone() + one.clone();
                 let mut len = 128;
-                if host_count.clone() & (host_count.clone() - one.clone()) == zero {
+                let mask = host_count.clone() - one.clone();
+                if host_count.clone() & mask.clone() == zero && s_num & mask == zero {
                     loop {
                         if host_count == one {
                             break;
@@ -617,6 +811,56 @@ impl VM {
         1
     }
 
+    /// Returns the network address (i.e. the first address) of the
+    /// IP object as a string.
+    pub fn core_ip_network(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("ip.network requires one argument");
+            return 0;
+        }
+
+        let ip_rr = self.stack.pop().unwrap();
+        let network = if let Some((s, _)) = ipv4_bounds(&ip_rr) {
+            format!("{}", int_to_ipv4_addr(s))
+        } else if let Some((s, _)) = ipv6_bounds(&ip_rr) {
+            format!("{}", int_to_ipv6_addr(s))
+        } else {
+            self.print_error("ip.network argument must be ip object");
+            return 0;
+        };
+
+        let sp = StringTriple::new(network, None);
+        self.stack.push(Value::String(Rc::new(RefCell::new(sp))));
+        1
+    }
+
+    /// Returns the broadcast address (i.e. the last address) of the
+    /// IPv4 object as a string.  IPv6 does not have broadcast
+    /// addresses, so IPv6 objects are not supported.
+    pub fn core_ip_broadcast(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("ip.broadcast requires one argument");
+            return 0;
+        }
+
+        let ip_rr = self.stack.pop().unwrap();
+        let broadcast = match ipv4_bounds(&ip_rr) {
+            Some((_, e)) => format!("{}", int_to_ipv4_addr(e)),
+            None => {
+                if ipv6_bounds(&ip_rr).is_some() {
+                    self.print_error("ip.broadcast argument must be IPv4 object");
+                } else {
+                    self.print_error("ip.broadcast argument must be ip object");
+                }
+                return 0;
+            }
+        };
+
+        let sp = StringTriple::new(broadcast, None);
+        self.stack.push(Value::String(Rc::new(RefCell::new(sp))));
+        1
+    }
+
     /// Returns the number of hosts covered by this IP object.
     pub fn core_ip_size(&mut self) -> i32 {
         if self.stack.is_empty() {
@@ -625,61 +869,45 @@ impl VM {
         }
 
         let ip_rr = self.stack.pop().unwrap();
-        match ip_rr {
-            Value::Ipv4(ipv4net) => {
-                if ipv4_addr_to_int(ipv4net.network()) == 0 && ipv4net.prefix_len() == 0 {
-                    let size_val = Value::BigInt(BigInt::from_u32(0xFFFFFFFF).unwrap());
-                    self.stack.push(size_val);
-                    return 1;
-                }
-                let ipv4addr_int = ipv4_addr_to_int(ipv4net.network());
-                let lastaddr_int = ipv4addr_int | ((1 << (32 - ipv4net.prefix_len())) - 1);
-                let size = lastaddr_int - ipv4addr_int + 1;
-                let size_val = Value::BigInt(BigInt::from_u32(size).unwrap());
-                self.stack.push(size_val);
-                return 1;
-            }
-            Value::Ipv4Range(ipv4range) => {
-                let s = ipv4range.s;
-                let e = ipv4range.e;
-                let s_num = ipv4_addr_to_int(s);
-                let e_num = ipv4_addr_to_int(e);
-                if s_num == 0 && e_num == 0xFFFFFFFF {
-                    self.stack.push(Value::BigInt(
-                        BigInt::from_u32(0xFFFFFFFF).unwrap() + BigInt::from(1u8),
-                    ));
-                    return 1;
-                }
-                let host_count = e_num - s_num + 1;
-                self.stack
-                    .push(Value::BigInt(BigInt::from_u32(host_count).unwrap()));
-                return 1;
-            }
-            Value::Ipv6(ipv6net) => {
-                let prefix_mask =
-                    (BigUint::from(1u8) << (128 - ipv6net.prefix_len())) - BigUint::from(1u8);
-                let ipv6addr_int = ipv6_addr_to_int(ipv6net.network());
-                let lastaddr_int = ipv6addr_int.clone() | prefix_mask;
-                let size = lastaddr_int - ipv6addr_int + BigUint::from(1u8);
-                let size_val = Value::BigInt(BigInt::from(size));
-                self.stack.push(size_val);
-            }
-            Value::Ipv6Range(ipv6range) => {
-                let s = ipv6range.s;
-                let e = ipv6range.e;
-                let s_num = ipv6_addr_to_int(s);
-                let e_num = ipv6_addr_to_int(e);
-                let host_count = e_num - s_num + BigUint::from(1u8);
-                self.stack.push(Value::BigInt(BigInt::from(host_count)));
-                return 1;
+        match ip_size(&ip_rr) {
+            Some(size) => {
+                self.stack.push(Value::BigInt(size));
+                1
             }
-            _ => {
+            None => {
                 self.print_error("ip.size argument must be ip object");
-                return 0;
+                0
             }
         }
+    }
 
-        1
+    /// Returns an IP object for an address selected uniformly at
+    /// random from the IP object argument.
+    pub fn core_ip_random(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("ip.random requires one argument");
+            return 0;
+        }
+
+        let ip_rr = self.stack.pop().unwrap();
+        if let Some((s, e)) = ipv4_bounds(&ip_rr) {
+            let n = self.rng.gen_range(s..=e);
+            let ipv4 = int_to_ipv4_addr(n);
+            self.stack
+                .push(Value::Ipv4(Ipv4Net::new(ipv4, 32).unwrap()));
+            1
+        } else if let Some((s, e)) = ipv6_bounds(&ip_rr) {
+            let s = BigInt::from(s);
+            let e = BigInt::from(e);
+            let n = gen_bigint_below(&mut self.rng, &(e - s.clone() + 1)) + s;
+            let ipv6 = int_to_ipv6_addr(n.to_biguint().unwrap());
+            self.stack
+                .push(Value::Ipv6(Ipv6Net::new(ipv6, 128).unwrap()));
+            1
+        } else {
+            self.print_error("ip.random argument must be ip object");
+            0
+        }
     }
 
     /// Returns the IP object version.
@@ -761,6 +989,262 @@ impl VM {
         1
     }
 
+    /// Takes a list of IP addresses/prefixes and returns the minimal
+    /// list of prefixes that covers exactly the same address space
+    /// (i.e. adjacent and contained entries are merged together).
+    /// IPv4 prefixes precede IPv6 prefixes in the result.
+    pub fn core_ip_summarize(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("ip.summarize requires one argument");
+            return 0;
+        }
+
+        let lst_rr = self.stack.pop().unwrap();
+        if !matches!(lst_rr, Value::List(_)) {
+            self.print_error("ip.summarize argument must be a list");
+            return 0;
+        }
+        let res = self.value_to_nets(lst_rr);
+        match res {
+            Some((ipv4_nets, ipv6_nets)) => {
+                let mut ipv4_range: IpRange<Ipv4Net> = IpRange::new();
+                for el in ipv4_nets.iter() {
+                    ipv4_range.add(*el);
+                }
+                ipv4_range.simplify();
+                let mut ipv4_lst = ipv4_range.iter().collect::<Vec<Ipv4Net>>();
+                ipv4_lst.sort_by_key(|a| a.network());
+
+                let mut ipv6_range: IpRange<Ipv6Net> = IpRange::new();
+                for el in ipv6_nets.iter() {
+                    ipv6_range.add(*el);
+                }
+                ipv6_range.simplify();
+                let mut ipv6_lst = ipv6_range.iter().collect::<Vec<Ipv6Net>>();
+                ipv6_lst.sort_by_key(|a| a.network());
+
+                let mut rlst = VecDeque::new();
+                for el in ipv4_lst.iter() {
+                    rlst.push_back(Value::Ipv4(*el));
+                }
+                for el in ipv6_lst.iter() {
+                    rlst.push_back(Value::Ipv6(*el));
+                }
+                let vlst = Value::List(Rc::new(RefCell::new(rlst)));
+                self.stack.push(vlst);
+                1
+            }
+            None => 0,
+        }
+    }
+
+    /// Takes an IP prefix and a count, and returns a list of the
+    /// equal-sized subnets of that prefix.  The prefix length of the
+    /// subnets is the smallest one that yields at least the requested
+    /// number of subnets, so if the count is not a power of two, the
+    /// result will contain more subnets than were requested.
+    pub fn core_ip_split(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("ip.split-by-count requires two arguments");
+            return 0;
+        }
+
+        let count_rr = self.stack.pop().unwrap();
+        let count_opt = count_rr.to_int();
+        let count = match count_opt {
+            Some(n) if n > 0 => n as u32,
+            _ => {
+                self.print_error("second ip.split-by-count argument must be a positive integer");
+                return 0;
+            }
+        };
+        let bits = 32 - (count - 1).leading_zeros();
+
+        let ip_rr = self.stack.pop().unwrap();
+        let rlst = match ip_rr {
+            Value::Ipv4(ipv4net) => {
+                let new_len = u32::from(ipv4net.prefix_len()) + bits;
+                if new_len > 32 {
+                    self.print_error("ip.split-by-count count exceeds available address space");
+                    return 0;
+                }
+                ipv4net
+                    .subnets(new_len as u8)
+                    .unwrap()
+                    .map(Value::Ipv4)
+                    .collect()
+            }
+            Value::Ipv6(ipv6net) => {
+                let new_len = u32::from(ipv6net.prefix_len()) + bits;
+                if new_len > 128 {
+                    self.print_error("ip.split-by-count count exceeds available address space");
+                    return 0;
+                }
+                ipv6net
+                    .subnets(new_len as u8)
+                    .unwrap()
+                    .map(Value::Ipv6)
+                    .collect()
+            }
+            _ => {
+                self.print_error("first ip.split-by-count argument must be ip prefix");
+                return 0;
+            }
+        };
+
+        let vlst = Value::List(Rc::new(RefCell::new(rlst)));
+        self.stack.push(vlst);
+        1
+    }
+
+    /// Takes a base IP object and an IP object to exclude from it, and
+    /// returns a list of the prefixes that cover the addresses from
+    /// the base object that are not in the excluded object.
+    pub fn core_ip_exclude(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("ip.exclude requires two arguments");
+            return 0;
+        }
+
+        let exclude_rr = self.stack.pop().unwrap();
+        let base_rr = self.stack.pop().unwrap();
+
+        let mut rlst = VecDeque::new();
+        match (ipv4_bounds(&base_rr), ipv4_bounds(&exclude_rr)) {
+            (Some((bs, be)), Some((xs, xe))) => {
+                if xe < bs || xs > be {
+                    rlst.push_back(Ipv4Range::new(int_to_ipv4_addr(bs), int_to_ipv4_addr(be)));
+                } else {
+                    if xs > bs {
+                        rlst.push_back(Ipv4Range::new(
+                            int_to_ipv4_addr(bs),
+                            int_to_ipv4_addr(xs - 1),
+                        ));
+                    }
+                    if xe < be {
+                        rlst.push_back(Ipv4Range::new(
+                            int_to_ipv4_addr(xe + 1),
+                            int_to_ipv4_addr(be),
+                        ));
+                    }
+                }
+                let nets = rlst
+                    .into_iter()
+                    .flat_map(ipv4range_to_nets)
+                    .map(Value::Ipv4)
+                    .collect();
+                self.stack.push(Value::List(Rc::new(RefCell::new(nets))));
+                return 1;
+            }
+            (Some(_), _) | (_, Some(_)) => {
+                self.print_error("ip.exclude arguments must be of the same IP version");
+                return 0;
+            }
+            _ => {}
+        }
+
+        let one = BigUint::from(1u8);
+        match (ipv6_bounds(&base_rr), ipv6_bounds(&exclude_rr)) {
+            (Some((bs, be)), Some((xs, xe))) => {
+                let mut rlst = VecDeque::new();
+                if xe < bs || xs > be {
+                    rlst.push_back(Ipv6Range::new(int_to_ipv6_addr(bs), int_to_ipv6_addr(be)));
+                } else {
+                    if xs > bs {
+                        rlst.push_back(Ipv6Range::new(
+                            int_to_ipv6_addr(bs),
+                            int_to_ipv6_addr(xs - one.clone()),
+                        ));
+                    }
+                    if xe < be {
+                        rlst.push_back(Ipv6Range::new(
+                            int_to_ipv6_addr(xe + one),
+                            int_to_ipv6_addr(be),
+                        ));
+                    }
+                }
+                let nets = rlst
+                    .into_iter()
+                    .flat_map(ipv6range_to_nets)
+                    .map(Value::Ipv6)
+                    .collect();
+                self.stack.push(Value::List(Rc::new(RefCell::new(nets))));
+                1
+            }
+            (Some(_), _) | (_, Some(_)) => {
+                self.print_error("ip.exclude arguments must be of the same IP version");
+                0
+            }
+            _ => {
+                self.print_error("ip.exclude arguments must be ip objects");
+                0
+            }
+        }
+    }
+
+    /// Takes an IP prefix and returns the name of the reverse DNS
+    /// zone for that prefix.  IPv4 prefixes must be octet-aligned,
+    /// except for prefixes longer than /24, for which the RFC 2317
+    /// classless delegation form is used.  IPv6 prefixes must be
+    /// nibble-aligned.
+    pub fn core_ip_ptr_zone(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("ip.ptr-zone requires one argument");
+            return 0;
+        }
+
+        let ip_rr = self.stack.pop().unwrap();
+        let zone = match ip_rr {
+            Value::Ipv4(ipv4net) => {
+                let octets = ipv4net.network().octets();
+                let prefix_len = ipv4net.prefix_len() as usize;
+                let mut labels: Vec<String> = Vec::new();
+                if prefix_len.is_multiple_of(8) {
+                    for octet in octets.iter().take(prefix_len / 8) {
+                        labels.push(octet.to_string());
+                    }
+                } else if prefix_len > 24 {
+                    for octet in octets.iter().take(3) {
+                        labels.push(octet.to_string());
+                    }
+                    labels.push(format!("{}/{}", octets[3], prefix_len));
+                } else {
+                    self.print_error(
+                        "ip.ptr-zone argument must be octet-aligned (split it into /8s, /16s or /24s first)",
+                    );
+                    return 0;
+                }
+                labels.reverse();
+                labels.push("in-addr.arpa".to_string());
+                labels.join(".")
+            }
+            Value::Ipv6(ipv6net) => {
+                let prefix_len = ipv6net.prefix_len() as usize;
+                if !prefix_len.is_multiple_of(4) {
+                    self.print_error("ip.ptr-zone argument must be nibble-aligned");
+                    return 0;
+                }
+                let octets = ipv6net.network().octets();
+                let mut labels: Vec<String> = octets
+                    .iter()
+                    .flat_map(|o| vec![format!("{:x}", o >> 4), format!("{:x}", o & 0xF)])
+                    .take(prefix_len / 4)
+                    .collect();
+                labels.reverse();
+                labels.push("ip6.arpa".to_string());
+                labels.join(".")
+            }
+            _ => {
+                self.print_error("ip.ptr-zone argument must be ip prefix");
+                return 0;
+            }
+        };
+
+        let st = StringTriple::new(zone, None);
+        self.stack.push(Value::String(Rc::new(RefCell::new(st))));
+        1
+    }
+
     /// Converts an arbitrary value into a list of IP net objects.
     pub fn value_to_nets(
         &mut self,
@@ -908,4 +1392,66 @@ impl VM {
             None => 0,
         }
     }
+
+    /// Takes an IP set and returns a list of the IP prefixes that
+    /// make up the set, sorted by network address (IPv4 prefixes
+    /// first).
+    pub fn core_ips_cidrs(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("ips.cidrs requires one argument");
+            return 0;
+        }
+
+        let ipset_rr = self.stack.pop().unwrap();
+        match ipset_rr {
+            Value::IpSet(ipset) => {
+                let ipset_ref = ipset.borrow();
+                let mut ipv4_nets = ipset_ref.ipv4.iter().collect::<Vec<Ipv4Net>>();
+                ipv4_nets.sort_by_key(|a| a.network());
+                let mut ipv6_nets = ipset_ref.ipv6.iter().collect::<Vec<Ipv6Net>>();
+                ipv6_nets.sort_by_key(|a| a.network());
+
+                let lst = ipv4_nets
+                    .into_iter()
+                    .map(Value::Ipv4)
+                    .chain(ipv6_nets.into_iter().map(Value::Ipv6))
+                    .collect::<VecDeque<Value>>();
+                self.stack.push(Value::List(Rc::new(RefCell::new(lst))));
+                1
+            }
+            _ => {
+                self.print_error("ips.cidrs argument must be IP set");
+                0
+            }
+        }
+    }
+
+    /// Takes an IP set and returns the total number of addresses in
+    /// the set.
+    pub fn core_ips_size(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("ips.size requires one argument");
+            return 0;
+        }
+
+        let ipset_rr = self.stack.pop().unwrap();
+        match ipset_rr {
+            Value::IpSet(ipset) => {
+                let ipset_ref = ipset.borrow();
+                let size: BigInt = ipset_ref
+                    .ipv4
+                    .iter()
+                    .map(Value::Ipv4)
+                    .chain(ipset_ref.ipv6.iter().map(Value::Ipv6))
+                    .filter_map(|v| ip_size(&v))
+                    .sum();
+                self.stack.push(Value::BigInt(size));
+                1
+            }
+            _ => {
+                self.print_error("ips.size argument must be IP set");
+                0
+            }
+        }
+    }
 }
@@ -49,7 +49,7 @@ fn convert_from_json(v: &serde_json::value::Value) -> Value {
 }
 
 /// Convert a value into a JSON string.
-fn convert_to_json(v: &Value) -> String {
+pub fn convert_to_json(v: &Value) -> String {
     match v {
         Value::Null => "null".to_string(),
         Value::Bool(true) => "true".to_string(),
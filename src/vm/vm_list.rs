@@ -6,8 +6,13 @@ use std::rc::Rc;
 use indexmap::IndexMap;
 use ipnet::{Ipv4Net, Ipv6Net};
 use iprange::IpRange;
+use num_bigint::BigInt;
+use num_traits::Zero;
+use unicode_segmentation::UnicodeSegmentation;
 
-use chunk::{IpSet, StringTriple, Value};
+use chunk::{IpSet, RangeGenerator, StringTriple, Value};
+use opcode::OpCode;
+use vm::vm_regex::match_generator_next;
 use vm::VM;
 
 impl VM {
@@ -138,6 +143,275 @@ impl VM {
         1
     }
 
+    /// Takes a list and an index as its arguments.  Places the
+    /// element at that index onto the stack.  A negative index is
+    /// counted from the end of the list.  If the index is out of
+    /// range, places null onto the stack.
+    pub fn core_nth(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("nth requires two arguments");
+            return 0;
+        }
+
+        let index_rr = self.stack.pop().unwrap();
+        let lst_rr = self.stack.pop().unwrap();
+
+        if lst_rr.is_generator() {
+            self.stack.push(lst_rr);
+            let res = self.generator_to_list();
+            if res == 0 {
+                return 0;
+            }
+            self.stack.push(index_rr);
+            return self.core_nth();
+        }
+
+        let index = match index_rr.to_int() {
+            Some(n) => n,
+            None => {
+                self.print_error("second nth argument must be integer");
+                return 0;
+            }
+        };
+
+        match lst_rr {
+            Value::List(lst) => {
+                let lstb = lst.borrow();
+                let len = lstb.len() as i64;
+                let index = if index < 0 {
+                    len + (index as i64)
+                } else {
+                    index as i64
+                };
+                if index < 0 || index >= len {
+                    self.stack.push(Value::Null);
+                } else {
+                    self.stack.push(lstb[index as usize].clone());
+                }
+                1
+            }
+            _ => {
+                self.print_error("first nth argument must be list");
+                0
+            }
+        }
+    }
+
+    /// Takes a list, a start index, and an end index as its
+    /// arguments.  Places a new list containing the elements from
+    /// the start index up to (but not including) the end index onto
+    /// the stack.  Negative indexes are counted from the end of the
+    /// list, and indexes that are out of range are clamped to the
+    /// bounds of the list.
+    pub fn core_slice(&mut self) -> i32 {
+        if self.stack.len() < 3 {
+            self.print_error("slice requires three arguments");
+            return 0;
+        }
+
+        let end_rr = self.stack.pop().unwrap();
+        let start_rr = self.stack.pop().unwrap();
+        let lst_rr = self.stack.pop().unwrap();
+
+        if lst_rr.is_generator() {
+            self.stack.push(lst_rr);
+            let res = self.generator_to_list();
+            if res == 0 {
+                return 0;
+            }
+            self.stack.push(start_rr);
+            self.stack.push(end_rr);
+            return self.core_slice();
+        }
+
+        let start = match start_rr.to_int() {
+            Some(n) => n,
+            None => {
+                self.print_error("second slice argument must be integer");
+                return 0;
+            }
+        };
+        let end = match end_rr.to_int() {
+            Some(n) => n,
+            None => {
+                self.print_error("third slice argument must be integer");
+                return 0;
+            }
+        };
+
+        match lst_rr {
+            Value::List(lst) => {
+                let lstb = lst.borrow();
+                let len = lstb.len() as i64;
+                let clamp = |n: i32| {
+                    let n = if n < 0 { len + (n as i64) } else { n as i64 };
+                    n.clamp(0, len) as usize
+                };
+                let start = clamp(start);
+                let end = clamp(end);
+                let mut results = VecDeque::new();
+                if start < end {
+                    results.extend(lstb.range(start..end).cloned());
+                }
+                self.stack.push(Value::List(Rc::new(RefCell::new(results))));
+                1
+            }
+            _ => {
+                self.print_error("first slice argument must be list");
+                0
+            }
+        }
+    }
+
+    /// Takes either an integer, or a list containing a start
+    /// integer, an end integer, and an optional step integer, as its
+    /// single argument.  Returns a generator over the integers from
+    /// the start (zero, if only an integer is provided) up to, but
+    /// not including, the end, incrementing by the step (one, if no
+    /// step is provided).  A negative step counts downwards.
+    pub fn core_range(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("range requires one argument");
+            return 0;
+        }
+
+        let range_rr = self.stack.pop().unwrap();
+        let (start, end, step) = match range_rr {
+            Value::List(ref lst) => {
+                let lstb = lst.borrow();
+                if lstb.len() != 2 && lstb.len() != 3 {
+                    self.print_error("range list must contain two or three integers");
+                    return 0;
+                }
+                let mut bounds = Vec::new();
+                for e in lstb.iter() {
+                    match e.to_bigint() {
+                        Some(n) => bounds.push(n),
+                        None => {
+                            self.print_error("range list must contain integers");
+                            return 0;
+                        }
+                    }
+                }
+                let step = if bounds.len() == 3 {
+                    bounds.pop().unwrap()
+                } else {
+                    BigInt::from(1)
+                };
+                let end = bounds.pop().unwrap();
+                let start = bounds.pop().unwrap();
+                (start, end, step)
+            }
+            _ => match range_rr.to_bigint() {
+                Some(n) => (BigInt::zero(), n, BigInt::from(1)),
+                None => {
+                    self.print_error("range argument must be integer");
+                    return 0;
+                }
+            },
+        };
+        if step.is_zero() {
+            self.print_error("range step must not be zero");
+            return 0;
+        }
+
+        let rg = RangeGenerator::new(start, end, step);
+        self.stack
+            .push(Value::RangeGenerator(Rc::new(RefCell::new(rg))));
+        1
+    }
+
+    /// Takes a list, an index, and a value as its arguments.
+    /// Inserts the value into the list at the index, and places the
+    /// updated list onto the stack.  A negative index is counted
+    /// from the end of the list, and an index equal to the length of
+    /// the list causes the value to be appended.
+    pub fn core_insert_at(&mut self) -> i32 {
+        if self.stack.len() < 3 {
+            self.print_error("insert-at requires three arguments");
+            return 0;
+        }
+
+        let element_rr = self.stack.pop().unwrap();
+        let index_rr = self.stack.pop().unwrap();
+        let lst_rr = self.stack.pop().unwrap();
+
+        let index = match index_rr.to_int() {
+            Some(n) => n,
+            None => {
+                self.print_error("second insert-at argument must be integer");
+                return 0;
+            }
+        };
+
+        match lst_rr {
+            Value::List(ref lst) => {
+                let len = lst.borrow().len() as i64;
+                let index = if index < 0 {
+                    len + (index as i64)
+                } else {
+                    index as i64
+                };
+                if index < 0 || index > len {
+                    self.print_error("insert-at index is out of range");
+                    return 0;
+                }
+                lst.borrow_mut().insert(index as usize, element_rr);
+            }
+            _ => {
+                self.print_error("first insert-at argument must be list");
+                return 0;
+            }
+        }
+
+        self.stack.push(lst_rr);
+        1
+    }
+
+    /// Takes a list and an index as its arguments.  Removes the
+    /// element at the index from the list, and places that element
+    /// onto the stack.  A negative index is counted from the end of
+    /// the list.
+    pub fn core_remove_at(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("remove-at requires two arguments");
+            return 0;
+        }
+
+        let index_rr = self.stack.pop().unwrap();
+        let lst_rr = self.stack.pop().unwrap();
+
+        let index = match index_rr.to_int() {
+            Some(n) => n,
+            None => {
+                self.print_error("second remove-at argument must be integer");
+                return 0;
+            }
+        };
+
+        match lst_rr {
+            Value::List(lst) => {
+                let len = lst.borrow().len() as i64;
+                let index = if index < 0 {
+                    len + (index as i64)
+                } else {
+                    index as i64
+                };
+                if index < 0 || index >= len {
+                    self.print_error("remove-at index is out of range");
+                    return 0;
+                }
+                let element_rr = lst.borrow_mut().remove(index as usize).unwrap();
+                self.stack.push(element_rr);
+                1
+            }
+            _ => {
+                self.print_error("first remove-at argument must be list");
+                0
+            }
+        }
+    }
+
     pub fn opcode_shift_inner(&mut self, shiftable_rr: &mut Value) -> i32 {
         let mut repush = false;
         let mut stack_len = 0;
@@ -382,6 +656,61 @@ impl VM {
                 let el = hwi.borrow().i + 1;
                 hwi.borrow_mut().i = el;
             }
+            Value::MatchGenerator(ref mut mg_rr) => {
+                let res = match_generator_next(&mut mg_rr.borrow_mut());
+                self.stack.push(res);
+            }
+            Value::CharsGenerator(ref mut swi) => {
+                let mut swib = swi.borrow_mut();
+                let next_opt = swib.s[swib.i..]
+                    .graphemes(true)
+                    .next()
+                    .map(|g| g.to_string());
+                match next_opt {
+                    Some(g) => {
+                        swib.i += g.len();
+                        self.stack
+                            .push(Value::String(Rc::new(RefCell::new(StringTriple::new(
+                                g, None,
+                            )))));
+                    }
+                    None => {
+                        self.stack.push(Value::Null);
+                    }
+                }
+            }
+            Value::RangeGenerator(ref mut rg) => {
+                let next_opt = rg.borrow_mut().next_value();
+                match next_opt {
+                    Some(v) => {
+                        self.stack.push(v);
+                    }
+                    None => {
+                        self.stack.push(Value::Null);
+                    }
+                }
+            }
+            Value::LinesGenerator(ref mut swi) => {
+                let mut swib = swi.borrow_mut();
+                if swib.i >= swib.s.len() {
+                    self.stack.push(Value::Null);
+                    return 1;
+                }
+                let rest = &swib.s[swib.i..];
+                let (line, consumed) = match rest.find('\n') {
+                    Some(n) => {
+                        let line = &rest[..n];
+                        (line.strip_suffix('\r').unwrap_or(line), n + 1)
+                    }
+                    None => (rest, rest.len()),
+                };
+                let line = line.to_string();
+                swib.i += consumed;
+                self.stack
+                    .push(Value::String(Rc::new(RefCell::new(StringTriple::new(
+                        line, None,
+                    )))));
+            }
             Value::MultiGenerator(ref mut genlist_rr) => {
                 let mut genlist = genlist_rr.borrow_mut();
                 loop {
@@ -451,6 +780,10 @@ impl VM {
                 | Value::KeysGenerator(_)
                 | Value::ValuesGenerator(_)
                 | Value::EachGenerator(_)
+                | Value::MatchGenerator(_)
+                | Value::CharsGenerator(_)
+                | Value::LinesGenerator(_)
+                | Value::RangeGenerator(_)
         );
         self.stack.push(Value::Bool(res));
         1
@@ -646,4 +979,325 @@ impl VM {
         }
         1
     }
+
+    /// Takes the name of the calling form, a callable, and a value as
+    /// its arguments.  Calls the callable with the value, and returns
+    /// the single result of that call.  Returns None if the call
+    /// fails, or if it does not produce a result.
+    fn call_with_element(&mut self, form: &str, fn_rr: &Value, element_rr: Value) -> Option<Value> {
+        self.stack.push(element_rr);
+        let res = self.call(OpCode::Call, fn_rr.clone());
+        if !res {
+            return None;
+        }
+        if self.stack.is_empty() {
+            self.print_error(&format!("{} callable should return a value", form));
+            return None;
+        }
+        Some(self.stack.pop().unwrap())
+    }
+
+    /// Takes a list and a predicate as its arguments.  Places two
+    /// lists onto the stack: the first containing the elements for
+    /// which the predicate returns a true value, and the second
+    /// containing the other elements.
+    pub fn core_partition(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("partition requires two arguments");
+            return 0;
+        }
+
+        let res = self.opcode_tofunction();
+        if res == 0 {
+            return 0;
+        }
+
+        let fn_rr = self.stack.pop().unwrap();
+        let lst_rr = self.stack.pop().unwrap();
+
+        if lst_rr.is_generator() {
+            self.stack.push(lst_rr);
+            let res = self.generator_to_list();
+            if res == 0 {
+                return 0;
+            }
+            self.stack.push(fn_rr);
+            return self.core_partition();
+        }
+
+        let elements: Vec<Value> = match lst_rr {
+            Value::List(lst) => lst.borrow().iter().cloned().collect(),
+            Value::Set(map) => map.borrow().values().cloned().collect(),
+            _ => {
+                self.print_error("first partition argument must be list");
+                return 0;
+            }
+        };
+
+        let mut matching = VecDeque::new();
+        let mut non_matching = VecDeque::new();
+        for e in elements {
+            match self.call_with_element("partition", &fn_rr, e.clone()) {
+                Some(v) => {
+                    if v.to_bool() {
+                        matching.push_back(e);
+                    } else {
+                        non_matching.push_back(e);
+                    }
+                }
+                None => {
+                    return 0;
+                }
+            }
+        }
+        self.stack
+            .push(Value::List(Rc::new(RefCell::new(matching))));
+        self.stack
+            .push(Value::List(Rc::new(RefCell::new(non_matching))));
+        1
+    }
+
+    /// Takes a list and a function as its arguments.  Calls the
+    /// function on each element of the list, and returns a hash
+    /// mapping each result (as a string) to a list of the elements
+    /// that produced it.  Keys are ordered per when they were first
+    /// seen.
+    pub fn core_group_by(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("group-by requires two arguments");
+            return 0;
+        }
+
+        let res = self.opcode_tofunction();
+        if res == 0 {
+            return 0;
+        }
+
+        let fn_rr = self.stack.pop().unwrap();
+        let lst_rr = self.stack.pop().unwrap();
+
+        if lst_rr.is_generator() {
+            self.stack.push(lst_rr);
+            let res = self.generator_to_list();
+            if res == 0 {
+                return 0;
+            }
+            self.stack.push(fn_rr);
+            return self.core_group_by();
+        }
+
+        let elements: Vec<Value> = match lst_rr {
+            Value::List(lst) => lst.borrow().iter().cloned().collect(),
+            Value::Set(map) => map.borrow().values().cloned().collect(),
+            _ => {
+                self.print_error("first group-by argument must be list");
+                return 0;
+            }
+        };
+
+        let mut groups: IndexMap<String, Value> = IndexMap::new();
+        for e in elements {
+            let key_rr = match self.call_with_element("group-by", &fn_rr, e.clone()) {
+                Some(v) => v,
+                None => {
+                    return 0;
+                }
+            };
+            let key_opt: Option<&str>;
+            to_str!(key_rr, key_opt);
+            match key_opt {
+                Some(key) => match groups.get(key) {
+                    Some(Value::List(group)) => {
+                        group.borrow_mut().push_back(e);
+                    }
+                    _ => {
+                        let mut group = VecDeque::new();
+                        group.push_back(e);
+                        groups.insert(key.to_string(), Value::List(Rc::new(RefCell::new(group))));
+                    }
+                },
+                None => {
+                    self.print_error("group-by key must be string");
+                    return 0;
+                }
+            }
+        }
+        self.stack.push(Value::Hash(Rc::new(RefCell::new(groups))));
+        1
+    }
+
+    /// Takes the name of the calling form, a predicate, a list, set
+    /// or generator, and a boolean as its arguments.  Calls the
+    /// predicate on each element in turn, until it returns a value
+    /// that evaluates to the boolean, and returns the index and value
+    /// of that element (or None, if there is no such element).  Lists
+    /// and sets are not modified.  Returns an error if the predicate
+    /// call fails, or if the argument cannot be iterated over.
+    fn find_element(
+        &mut self,
+        form: &str,
+        fn_rr: &Value,
+        mut shiftable_rr: Value,
+        stop_on: bool,
+    ) -> Result<Option<(usize, Value)>, ()> {
+        let mut i = 0;
+        loop {
+            let element_rr = match shiftable_rr {
+                Value::List(ref lst) => {
+                    let element_opt = lst.borrow().get(i).cloned();
+                    match element_opt {
+                        Some(e) => e,
+                        None => {
+                            return Ok(None);
+                        }
+                    }
+                }
+                Value::Set(ref map) => {
+                    let element_opt = map.borrow().get_index(i).map(|(_, v)| v.clone());
+                    match element_opt {
+                        Some(e) => e,
+                        None => {
+                            return Ok(None);
+                        }
+                    }
+                }
+                _ if shiftable_rr.is_generator() => {
+                    let shift_res = self.opcode_shift_inner(&mut shiftable_rr);
+                    if shift_res == 0 {
+                        return Err(());
+                    }
+                    let element_rr = self.stack.pop().unwrap();
+                    if let Value::Null = element_rr {
+                        return Ok(None);
+                    }
+                    element_rr
+                }
+                _ => {
+                    self.print_error(&format!("first {} argument must be list", form));
+                    return Err(());
+                }
+            };
+            match self.call_with_element(form, fn_rr, element_rr.clone()) {
+                Some(v) => {
+                    if v.to_bool() == stop_on {
+                        return Ok(Some((i, element_rr)));
+                    }
+                }
+                None => {
+                    return Err(());
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// Takes the name of the calling form and the predicate result
+    /// that causes evaluation to stop as its arguments, and
+    /// implements `any?` and `all?`.  If an element for which the
+    /// predicate returns the stopping result is found, places that
+    /// result onto the stack.  Otherwise, places the opposite of the
+    /// stopping result onto the stack.
+    fn any_all(&mut self, form: &str, stop_on: bool) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error(&format!("{} requires two arguments", form));
+            return 0;
+        }
+
+        let res = self.opcode_tofunction();
+        if res == 0 {
+            return 0;
+        }
+
+        let fn_rr = self.stack.pop().unwrap();
+        let shiftable_rr = self.stack.pop().unwrap();
+
+        match self.find_element(form, &fn_rr, shiftable_rr, stop_on) {
+            Ok(Some(_)) => {
+                self.stack.push(Value::Bool(stop_on));
+                1
+            }
+            Ok(None) => {
+                self.stack.push(Value::Bool(!stop_on));
+                1
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Takes a list and a predicate as its arguments.  Returns a
+    /// boolean indicating whether the predicate returns a true value
+    /// for any element of the list.  Stops at the first such
+    /// element.
+    pub fn core_any(&mut self) -> i32 {
+        self.any_all("any?", true)
+    }
+
+    /// Takes a list and a predicate as its arguments.  Returns a
+    /// boolean indicating whether the predicate returns a true value
+    /// for every element of the list.  Stops at the first element
+    /// for which it does not.
+    pub fn core_all(&mut self) -> i32 {
+        self.any_all("all?", false)
+    }
+
+    /// Takes a list and a predicate as its arguments.  Returns the
+    /// first element of the list for which the predicate returns a
+    /// true value, or null if there is no such element.
+    pub fn core_find(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("find requires two arguments");
+            return 0;
+        }
+
+        let res = self.opcode_tofunction();
+        if res == 0 {
+            return 0;
+        }
+
+        let fn_rr = self.stack.pop().unwrap();
+        let shiftable_rr = self.stack.pop().unwrap();
+
+        match self.find_element("find", &fn_rr, shiftable_rr, true) {
+            Ok(Some((_, element_rr))) => {
+                self.stack.push(element_rr);
+                1
+            }
+            Ok(None) => {
+                self.stack.push(Value::Null);
+                1
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Takes a list and a predicate as its arguments.  Returns the
+    /// index of the first element of the list for which the
+    /// predicate returns a true value, or -1 if there is no such
+    /// element.
+    pub fn core_find_index(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("find-index requires two arguments");
+            return 0;
+        }
+
+        let res = self.opcode_tofunction();
+        if res == 0 {
+            return 0;
+        }
+
+        let fn_rr = self.stack.pop().unwrap();
+        let shiftable_rr = self.stack.pop().unwrap();
+
+        match self.find_element("find-index", &fn_rr, shiftable_rr, true) {
+            Ok(Some((i, _))) => {
+                self.stack.push(Value::Int(i as i32));
+                1
+            }
+            Ok(None) => {
+                self.stack.push(Value::Int(-1));
+                1
+            }
+            Err(_) => 0,
+        }
+    }
 }
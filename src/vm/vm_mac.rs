@@ -0,0 +1,154 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use chunk::{StringTriple, Value};
+use vm::*;
+
+/// The largest value that can be represented as a MAC address.
+const MAC_MAX: u64 = 0xFFFF_FFFF_FFFF;
+
+/// Parse a MAC address string into a u64.  Supports the common
+/// formats: colon-separated (00:11:22:33:44:55), hyphen-separated
+/// (00-11-22-33-44-55), dotted (0011.2233.4455), and bare
+/// (001122334455).  Colon- and hyphen-separated groups may omit
+/// leading zeros.
+fn parse_mac(s: &str) -> Option<u64> {
+    let groups: Vec<&str>;
+    let max_width;
+    if s.contains(':') {
+        groups = s.split(':').collect();
+        max_width = 2;
+        if groups.len() != 6 {
+            return None;
+        }
+    } else if s.contains('-') {
+        groups = s.split('-').collect();
+        max_width = 2;
+        if groups.len() != 6 {
+            return None;
+        }
+    } else if s.contains('.') {
+        groups = s.split('.').collect();
+        max_width = 4;
+        if groups.len() != 3 || groups.iter().any(|g| g.len() != 4) {
+            return None;
+        }
+    } else {
+        groups = vec![s];
+        max_width = 12;
+        if s.len() != 12 {
+            return None;
+        }
+    }
+
+    let mut n: u64 = 0;
+    for group in groups.iter() {
+        if group.is_empty()
+            || group.len() > max_width
+            || !group.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return None;
+        }
+        let part = u64::from_str_radix(group, 16).ok()?;
+        n = (n << (max_width * 4)) | part;
+    }
+    Some(n)
+}
+
+/// Format a u64 as a lowercase colon-separated MAC address string.
+fn format_mac(n: u64) -> String {
+    (0..6)
+        .rev()
+        .map(|i| format!("{:02x}", (n >> (i * 8)) & 0xFF))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+impl VM {
+    /// Helper function for popping a MAC address string from the
+    /// stack and parsing it.
+    fn pop_mac(&mut self, name: &str) -> Option<u64> {
+        if self.stack.is_empty() {
+            let err_str = format!("{} requires one argument", name);
+            self.print_error(&err_str);
+            return None;
+        }
+
+        let mac_rr = self.stack.pop().unwrap();
+        let mac_opt: Option<&str>;
+        to_str!(mac_rr, mac_opt);
+
+        match mac_opt.and_then(|s| parse_mac(s.trim())) {
+            Some(n) => Some(n),
+            None => {
+                let err_str = format!("{} argument must be valid MAC address string", name);
+                self.print_error(&err_str);
+                None
+            }
+        }
+    }
+
+    /// Takes a MAC address string in any of the common formats, and
+    /// returns it in lowercase colon-separated form.
+    pub fn core_mac(&mut self) -> i32 {
+        match self.pop_mac("mac") {
+            Some(n) => {
+                let st = StringTriple::new(format_mac(n), None);
+                self.stack.push(Value::String(Rc::new(RefCell::new(st))));
+                1
+            }
+            None => 0,
+        }
+    }
+
+    /// Takes a MAC address string and returns it as an integer.
+    pub fn core_mac_to_int(&mut self) -> i32 {
+        match self.pop_mac("mac.to-int") {
+            Some(n) => {
+                self.stack.push(Value::BigInt(BigInt::from(n)));
+                1
+            }
+            None => 0,
+        }
+    }
+
+    /// Takes an integer and returns it as a MAC address string.
+    pub fn core_mac_from_int(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("mac.from-int requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let n_opt = value_rr.to_bigint().and_then(|n| n.to_u64());
+        match n_opt {
+            Some(n) if n <= MAC_MAX => {
+                let st = StringTriple::new(format_mac(n), None);
+                self.stack.push(Value::String(Rc::new(RefCell::new(st))));
+                1
+            }
+            _ => {
+                self.print_error("mac.from-int argument must be 48-bit unsigned integer");
+                0
+            }
+        }
+    }
+
+    /// Takes a MAC address string and returns its organisationally
+    /// unique identifier (i.e. the vendor prefix, being the first
+    /// three octets) in colon-separated form.
+    pub fn core_mac_oui(&mut self) -> i32 {
+        match self.pop_mac("mac.oui") {
+            Some(n) => {
+                let oui = format_mac(n)[..8].to_string();
+                let st = StringTriple::new(oui, None);
+                self.stack.push(Value::String(Rc::new(RefCell::new(st))));
+                1
+            }
+            None => 0,
+        }
+    }
+}
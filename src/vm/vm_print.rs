@@ -5,8 +5,9 @@ use std::str;
 
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
+use termion::{clear, color, style};
 
-use chunk::{Chunk, Value};
+use chunk::{Chunk, StringTriple, Value};
 use vm::*;
 
 /// Helper function for print_stack_value.  Takes a string, an indent
@@ -81,9 +82,148 @@ fn psv_helper(
     lines_to_print - 1
 }
 
+/// Helper function for core_pp.  Returns the single-line
+/// representation of the value, or None if the value is a non-empty
+/// list, hash, or set, in which case its elements must be printed on
+/// separate lines.
+fn pp_inline(value_rr: &Value) -> Option<String> {
+    match value_rr {
+        Value::List(lst) => {
+            if lst.borrow().is_empty() {
+                Some("[]".to_string())
+            } else {
+                None
+            }
+        }
+        Value::Hash(map) | Value::Set(map) => {
+            if map.borrow().is_empty() {
+                Some("{}".to_string())
+            } else {
+                None
+            }
+        }
+        Value::Null => Some("null".to_string()),
+        Value::Bool(b) => Some(if *b { ".t" } else { ".f" }.to_string()),
+        Value::String(st) => Some(st.borrow().string.clone()),
+        Value::DateTimeNT(dt) => Some(dt.format("%F %T %Z").to_string()),
+        Value::DateTimeOT(dt) => Some(dt.format("%F %T %Z").to_string()),
+        _ => match value_rr.to_string() {
+            Ok(Some(s)) => Some(s),
+            _ => Some(value_rr.type_string()),
+        },
+    }
+}
+
+/// Helper function for core_pp.  Takes a non-empty list, hash, or
+/// set, and an indent count, and appends the lines for the elements
+/// of that value to the output string.  List and set elements are
+/// prefixed with "- ", and hash entries with the key.
+fn pp_elements(value_rr: &Value, indent: usize, out: &mut String) {
+    let entries: Vec<(String, Value)> = match value_rr {
+        Value::List(lst) => lst
+            .borrow()
+            .iter()
+            .map(|v| ("-".to_string(), v.clone()))
+            .collect(),
+        Value::Hash(map) => map
+            .borrow()
+            .iter()
+            .map(|(k, v)| (format!("{}:", k), v.clone()))
+            .collect(),
+        Value::Set(map) => map
+            .borrow()
+            .values()
+            .map(|v| ("-".to_string(), v.clone()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    for (prefix, v) in entries {
+        out.push_str(&" ".repeat(indent));
+        out.push_str(&prefix);
+        match pp_inline(&v) {
+            Some(s) => {
+                out.push(' ');
+                out.push_str(&s);
+                out.push('\n');
+            }
+            None => {
+                out.push('\n');
+                pp_elements(&v, indent + 2, out);
+            }
+        }
+    }
+}
+
+/// Helper function for core_table.  Returns the width available for
+/// printing a table: the terminal width if standard output is a
+/// terminal, else the value of the COLUMNS environment variable, else
+/// 80.
+fn table_width() -> usize {
+    if termion::is_tty(&io::stdout()) {
+        if let Some((w, _)) = term_size::dimensions() {
+            return w;
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(80)
+}
+
+/// Helper function for core_table.  Returns the cell string
+/// truncated to the given width, with "..." marking the elision.
+fn table_cell(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        s.to_string()
+    } else if width <= 3 {
+        s.chars().take(width).collect()
+    } else {
+        let mut cell = s.chars().take(width - 3).collect::<String>();
+        cell.push_str("...");
+        cell
+    }
+}
+
+/// Helper function for core_color.  Returns the ANSI escape sequence
+/// for the given color name, or None if the name is not known.  A
+/// color name may be prefixed with "bold-" for the bold variant.
+fn color_code(name: &str) -> Option<String> {
+    if let Some(base) = name.strip_prefix("bold-") {
+        return match base {
+            "bold" | "reset" => None,
+            _ => color_code(base).map(|code| format!("{}{}", style::Bold, code)),
+        };
+    }
+    match name {
+        "red" => Some(color::Fg(color::Red).to_string()),
+        "green" => Some(color::Fg(color::Green).to_string()),
+        "yellow" => Some(color::Fg(color::Yellow).to_string()),
+        "blue" => Some(color::Fg(color::Blue).to_string()),
+        "bold" => Some(style::Bold.to_string()),
+        "reset" => Some(style::Reset.to_string()),
+        _ => None,
+    }
+}
+
 impl VM {
+    /// Writes the string to the output (standard output, unless
+    /// replaced by way of set_output).  Returns an integer indicating
+    /// whether the string was able to be written.
+    fn write_output(&mut self, s: &str) -> i32 {
+        match self.output.write_all(s.as_bytes()) {
+            Ok(_) => 1,
+            Err(e) => {
+                let err_str = format!("unable to write output: {}", e);
+                self.print_error(&err_str);
+                0
+            }
+        }
+    }
+
     /// Takes a value that can be stringified as its single argument,
-    /// and prints that value to standard output.
+    /// and prints that value (without a trailing newline) to the
+    /// output (standard output, unless replaced by way of
+    /// set_output).
     pub fn opcode_print(&mut self) -> i32 {
         if self.stack.is_empty() {
             self.print_error("print requires one argument");
@@ -95,10 +235,7 @@ impl VM {
         to_str!(value_rr, value_opt);
 
         match value_opt {
-            Some(s) => {
-                print!("{}", s);
-                1
-            }
+            Some(s) => self.write_output(s),
             _ => {
                 self.print_error("print argument must be a string");
                 0
@@ -106,8 +243,8 @@ impl VM {
         }
     }
 
-    /// Takes a value that can be stringified as its single argument,
-    /// and prints that value followed by newline to standard output.
+    /// As per `opcode_print`, except that the value is followed by a
+    /// newline.
     pub fn core_println(&mut self) -> i32 {
         if self.stack.is_empty() {
             self.print_error("println requires one argument");
@@ -119,10 +256,7 @@ impl VM {
         to_str!(value_rr, value_opt);
 
         match value_opt {
-            Some(s) => {
-                println!("{}", s);
-                1
-            }
+            Some(s) => self.write_output(&format!("{}\n", s)),
             _ => {
                 self.print_error("println argument must be a string");
                 0
@@ -130,6 +264,284 @@ impl VM {
         }
     }
 
+    /// Takes a value as its single argument, and prints it to the
+    /// output as an indented tree, with each element of a list, hash,
+    /// or set on its own line.
+    pub fn core_pp(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("pp requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let out = match pp_inline(&value_rr) {
+            Some(s) => format!("{}\n", s),
+            None => {
+                let mut out = String::new();
+                pp_elements(&value_rr, 0, &mut out);
+                out
+            }
+        };
+        self.write_output(&out)
+    }
+
+    /// Takes a list of hashes as its single argument, and prints it
+    /// to the output as a table, with one row per hash and one
+    /// column per key.  Columns are narrowed (and their cells
+    /// truncated) as required to fit the table within the terminal
+    /// width.
+    pub fn core_table(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("table requires one argument");
+            return 0;
+        }
+
+        let lst_rr = self.stack.pop().unwrap();
+        let lst = match lst_rr {
+            Value::List(lst) => lst,
+            _ => {
+                self.print_error("table argument must be list");
+                return 0;
+            }
+        };
+
+        let mut columns: Vec<String> = Vec::new();
+        let mut rows: Vec<Vec<Option<String>>> = Vec::new();
+        let mut row_maps = Vec::new();
+        for row_rr in lst.borrow().iter() {
+            match row_rr {
+                Value::Hash(map) => {
+                    for k in map.borrow().keys() {
+                        if !columns.contains(k) {
+                            columns.push(k.clone());
+                        }
+                    }
+                    row_maps.push(map.clone());
+                }
+                _ => {
+                    self.print_error("table argument must be list of hashes");
+                    return 0;
+                }
+            }
+        }
+        for map in row_maps {
+            let map = map.borrow();
+            rows.push(
+                columns
+                    .iter()
+                    .map(|c| {
+                        map.get(c)
+                            .map(|v| pp_inline(v).unwrap_or_else(|| v.type_string()))
+                    })
+                    .collect(),
+            );
+        }
+        if rows.is_empty() {
+            return 1;
+        }
+
+        let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+        for row in rows.iter() {
+            for (i, cell) in row.iter().enumerate() {
+                if let Some(s) = cell {
+                    widths[i] = widths[i].max(s.chars().count());
+                }
+            }
+        }
+
+        /* Narrow the widest column until the table fits, or until
+         * no column can be narrowed further. */
+        let separator = "  ";
+        let available = table_width();
+        let min_width = 4;
+        loop {
+            let total = widths.iter().sum::<usize>() + separator.len() * (widths.len() - 1);
+            if total <= available {
+                break;
+            }
+            let (widest, width) = widths
+                .iter()
+                .cloned()
+                .enumerate()
+                .max_by_key(|&(i, w)| (w, std::cmp::Reverse(i)))
+                .unwrap();
+            if width <= min_width {
+                break;
+            }
+            widths[widest] = width - 1;
+        }
+
+        let format_line = |cells: Vec<String>| -> String {
+            let line = cells
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, &w)| format!("{:<w$}", table_cell(cell, w), w = w))
+                .collect::<Vec<String>>()
+                .join(separator);
+            line.trim_end().to_string()
+        };
+
+        let mut out = String::new();
+        out.push_str(&format_line(columns.clone()));
+        out.push('\n');
+        out.push_str(&format_line(
+            widths.iter().map(|&w| "-".repeat(w)).collect(),
+        ));
+        out.push('\n');
+        for row in rows {
+            out.push_str(&format_line(
+                row.into_iter().map(|c| c.unwrap_or_default()).collect(),
+            ));
+            out.push('\n');
+        }
+        self.write_output(&out)
+    }
+
+    /// Takes a value that can be stringified and a color name as its
+    /// arguments.  Returns the string wrapped in the ANSI escape
+    /// sequences for that color, if standard output is a terminal (or
+    /// if the CLICOLOR_FORCE environment variable is set), and
+    /// otherwise returns the string unchanged.
+    pub fn core_color(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("color requires two arguments");
+            return 0;
+        }
+
+        let name_rr = self.stack.pop().unwrap();
+        let name_opt: Option<&str>;
+        to_str!(name_rr, name_opt);
+        let code = match name_opt.map(color_code) {
+            Some(Some(code)) => code,
+            Some(None) => {
+                self.print_error("color argument must be known color name");
+                return 0;
+            }
+            None => {
+                self.print_error("color argument must be string");
+                return 0;
+            }
+        };
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        let s = match value_opt {
+            Some(s) => s,
+            None => {
+                self.print_error("first color argument must be string");
+                return 0;
+            }
+        };
+
+        let force = std::env::var("CLICOLOR_FORCE")
+            .map(|v| !v.is_empty() && v != "0")
+            .unwrap_or(false);
+        let res = if force || termion::is_tty(&io::stdout()) {
+            format!("{}{}{}", code, s, style::Reset)
+        } else {
+            s.to_string()
+        };
+        self.stack
+            .push(Value::String(Rc::new(RefCell::new(StringTriple::new(
+                res, None,
+            )))));
+        1
+    }
+
+    /// Takes a count and a total as its arguments, and draws a
+    /// progress bar for that count to standard error.  Once the count
+    /// reaches the total, a newline is printed after the bar.  Does
+    /// nothing if standard error is not a terminal.  Used by
+    /// `with-bar`.
+    pub fn core_progress_bar(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("progress-bar requires two arguments");
+            return 0;
+        }
+
+        let total_rr = self.stack.pop().unwrap();
+        let count_rr = self.stack.pop().unwrap();
+        let (count, total) = match (count_rr.to_int(), total_rr.to_int()) {
+            (Some(c), Some(t)) if c >= 0 && t > 0 => (c, t),
+            _ => {
+                self.print_error("progress-bar arguments must be non-negative integers");
+                return 0;
+            }
+        };
+
+        let mut stderr = io::stderr();
+        if !termion::is_tty(&stderr) {
+            return 1;
+        }
+
+        let count = count.min(total);
+        let percent = (count as i64 * 100) / total as i64;
+        let label = format!(" {}% ({}/{})", percent, count, total);
+        let term_width = match term_size::dimensions_stderr() {
+            Some((w, _)) => w,
+            None => 80,
+        };
+        let bar_width = term_width.saturating_sub(label.len() + 3).max(10);
+        let filled = (count as usize * bar_width) / total as usize;
+        let _ = write!(
+            stderr,
+            "\r[{}{}]{}",
+            "#".repeat(filled),
+            " ".repeat(bar_width - filled),
+            label
+        );
+        if count == total {
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+        1
+    }
+
+    /// Takes a count as its single argument, and draws a spinner
+    /// with that count to standard error.  Does nothing if standard
+    /// error is not a terminal.  Used by `progress`.
+    pub fn core_progress_spinner(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("progress-spinner requires one argument");
+            return 0;
+        }
+
+        let count_rr = self.stack.pop().unwrap();
+        let count = match count_rr.to_int() {
+            Some(c) if c >= 0 => c,
+            _ => {
+                self.print_error("progress-spinner argument must be non-negative integer");
+                return 0;
+            }
+        };
+
+        let mut stderr = io::stderr();
+        if !termion::is_tty(&stderr) {
+            return 1;
+        }
+
+        let frames = ['|', '/', '-', '\\'];
+        let frame = frames[count as usize % frames.len()];
+        let _ = write!(stderr, "\r{}{} {}", clear::CurrentLine, frame, count);
+        let _ = stderr.flush();
+        1
+    }
+
+    /// Clears the spinner drawn by progress-spinner from standard
+    /// error.  Does nothing if standard error is not a terminal.
+    /// Used by `progress`.
+    pub fn core_progress_clear(&mut self) -> i32 {
+        let mut stderr = io::stderr();
+        if !termion::is_tty(&stderr) {
+            return 1;
+        }
+
+        let _ = write!(stderr, "\r{}", clear::CurrentLine);
+        let _ = stderr.flush();
+        1
+    }
+
     /// Used by print_stack to print a single stack value.  Takes a
     /// wrapped value, the current chunk, the instruction index, the
     /// map of global functions, the current indent, the window height
@@ -155,7 +567,8 @@ impl VM {
         {
             match value_rr {
                 Value::Ipv4(_) | Value::Ipv4Range(_) | Value::Ipv6(_) | Value::Ipv6Range(_) => {
-                    let s = format!("v[{} {}]", &type_string, value_rr.to_string().unwrap());
+                    let ip_str = value_rr.to_string().unwrap().unwrap();
+                    let s = format!("v[{} {}]", &type_string, ip_str);
                     lines_to_print = psv_helper(
                         &s,
                         indent,
@@ -471,6 +884,10 @@ impl VM {
                 | Value::ValuesGenerator(_)
                 | Value::EachGenerator(_)
                 | Value::MultiGenerator(_)
+                | Value::MatchGenerator(_)
+                | Value::CharsGenerator(_)
+                | Value::LinesGenerator(_)
+                | Value::RangeGenerator(_)
                 | Value::IpSet(_) => {
                     is_generator = true;
                 }
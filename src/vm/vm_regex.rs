@@ -2,15 +2,64 @@ use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
-use regex::Regex;
+use indexmap::IndexMap;
+use regex::{Captures, Regex};
 
-use chunk::{StringTriple, Value};
+use chunk::{MatchGenerator, StringTriple, Value};
 use vm::*;
 
 lazy_static! {
     static ref RE_ADJUST: Regex = Regex::new(r"\\([\d+])").unwrap();
 }
 
+/// Converts a list of regex capture groups into a list value.  The
+/// list contains each of the capture groups in order (starting with
+/// group 0, the entire match), with null for groups that did not
+/// participate in the match.  If the regex has named groups, then a
+/// hash mapping from group name to captured value is appended to the
+/// list.
+fn groups_to_list(regex: &Regex, groups: Vec<Option<&str>>) -> Value {
+    let to_value = |group: Option<&str>| match group {
+        Some(s) => Value::String(Rc::new(RefCell::new(StringTriple::new(
+            s.to_string(),
+            None,
+        )))),
+        None => Value::Null,
+    };
+    let mut named = IndexMap::new();
+    for (i, name) in regex.capture_names().enumerate() {
+        if let Some(name) = name {
+            named.insert(name.to_string(), to_value(groups[i]));
+        }
+    }
+    let mut lst: VecDeque<Value> = groups.into_iter().map(to_value).collect();
+    if !named.is_empty() {
+        lst.push_back(Value::Hash(Rc::new(RefCell::new(named))));
+    }
+    Value::List(Rc::new(RefCell::new(lst)))
+}
+
+/// Converts a set of regex captures into a list value (see
+/// groups_to_list).
+fn captures_to_list(regex: &Regex, captures: &Captures) -> Value {
+    let groups = captures.iter().map(|m| m.map(|m| m.as_str())).collect();
+    groups_to_list(regex, groups)
+}
+
+/// Returns the next match from the match generator as a list value
+/// (see groups_to_list), or null if there are no more matches.
+pub fn match_generator_next(mg: &mut MatchGenerator) -> Value {
+    match mg.next_locations() {
+        Some(locs) => {
+            let groups = (0..locs.len())
+                .map(|i| locs.pos(i).map(|(start, end)| &mg.s[start..end]))
+                .collect();
+            groups_to_list(&mg.regex, groups)
+        }
+        None => Value::Null,
+    }
+}
+
 impl VM {
     /// Takes a value that can be stringified and a regex string as
     /// its arguments.  Tests whether the value matches as against the
@@ -51,7 +100,10 @@ impl VM {
     /// Takes a value that can be stringified, a regex string, and a
     /// replacement string as its arguments.  Runs a
     /// search-and-replace against the string based on the regex, and
-    /// puts the resulting string onto the stack.
+    /// puts the resulting string onto the stack.  The replacement
+    /// string may refer to captures by number (\1 or ${1}) or by
+    /// name (${name}), with references to nonexistent groups being
+    /// replaced with the empty string.
     pub fn core_s(&mut self) -> i32 {
         if self.stack.len() < 3 {
             self.print_error("s requires three arguments");
@@ -164,4 +216,161 @@ impl VM {
         }
         1
     }
+
+    /// Takes a value that can be stringified and a regex string as
+    /// its arguments.  If the value matches against the regex, puts
+    /// a list of the capture groups onto the stack (see
+    /// captures_to_list).  Otherwise, puts null onto the stack.
+    pub fn core_match(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("match requires two arguments");
+            return 0;
+        }
+
+        let regex_rr = self.stack.pop().unwrap();
+        let regex_opt = self.gen_regex(regex_rr);
+        if regex_opt.is_none() {
+            return 0;
+        }
+
+        let str_rr = self.stack.pop().unwrap();
+        let str_opt: Option<&str>;
+        to_str!(str_rr, str_opt);
+
+        match (regex_opt, str_opt) {
+            (Some((regex, _)), Some(s)) => {
+                let res = match regex.captures(s) {
+                    Some(captures) => captures_to_list(&regex, &captures),
+                    None => Value::Null,
+                };
+                self.stack.push(res);
+            }
+            (Some(_), _) => {
+                self.print_error("first match argument must be string");
+                return 0;
+            }
+            (_, _) => {
+                self.print_error("second match argument must be string");
+                return 0;
+            }
+        }
+        1
+    }
+
+    /// Takes a value that can be stringified and a regex string as
+    /// its arguments.  Puts a generator onto the stack that yields a
+    /// list of the capture groups for each match of the regex against
+    /// the value (see groups_to_list).  If the regex does not have
+    /// the global flag, then the generator yields at most one match.
+    pub fn core_match_all(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("match-all requires two arguments");
+            return 0;
+        }
+
+        let regex_rr = self.stack.pop().unwrap();
+        let regex_opt = self.gen_regex(regex_rr);
+        if regex_opt.is_none() {
+            return 0;
+        }
+
+        let str_rr = self.stack.pop().unwrap();
+        let str_opt: Option<&str>;
+        to_str!(str_rr, str_opt);
+
+        match (regex_opt, str_opt) {
+            (Some((regex, global)), Some(s)) => {
+                let mg = MatchGenerator::new(regex, s.to_string(), global);
+                self.stack
+                    .push(Value::MatchGenerator(Rc::new(RefCell::new(mg))));
+            }
+            (Some(_), _) => {
+                self.print_error("first match-all argument must be string");
+                return 0;
+            }
+            (_, _) => {
+                self.print_error("second match-all argument must be string");
+                return 0;
+            }
+        }
+        1
+    }
+
+    /// Takes a value that can be stringified and a regex string as
+    /// its arguments.  Splits the value on each match of the regex,
+    /// and puts a list of the resulting pieces onto the stack.  Empty
+    /// pieces (e.g. where the regex matches at the start or end of
+    /// the value) are retained.
+    pub fn core_rsplit(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("rsplit requires two arguments");
+            return 0;
+        }
+
+        let regex_rr = self.stack.pop().unwrap();
+        let regex_opt = self.gen_regex(regex_rr);
+        if regex_opt.is_none() {
+            return 0;
+        }
+
+        let str_rr = self.stack.pop().unwrap();
+        let str_opt: Option<&str>;
+        to_str!(str_rr, str_opt);
+
+        match (regex_opt, str_opt) {
+            (Some((regex, _)), Some(s)) => {
+                /* Regex::split omits the trailing piece when it is
+                 * empty, so the pieces are collected manually. */
+                let mut pieces = Vec::new();
+                let mut last = 0;
+                for m in regex.find_iter(s) {
+                    pieces.push(&s[last..m.start()]);
+                    last = m.end();
+                }
+                pieces.push(&s[last..]);
+                let lst = pieces
+                    .into_iter()
+                    .map(|piece| {
+                        Value::String(Rc::new(RefCell::new(StringTriple::new(
+                            piece.to_string(),
+                            None,
+                        ))))
+                    })
+                    .collect::<VecDeque<Value>>();
+                self.stack.push(Value::List(Rc::new(RefCell::new(lst))));
+            }
+            (Some(_), _) => {
+                self.print_error("first rsplit argument must be string");
+                return 0;
+            }
+            (_, _) => {
+                self.print_error("second rsplit argument must be string");
+                return 0;
+            }
+        }
+        1
+    }
+
+    /// Takes a value that can be stringified as its single argument.
+    /// Escapes all regex metacharacters in the value, as well as
+    /// forward slashes (so that the result is not treated as having
+    /// flags), and puts the resulting string onto the stack.
+    pub fn core_regex_quote(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("regex-quote requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        if value_opt.is_none() {
+            self.print_error("regex-quote argument must be string");
+            return 0;
+        }
+        let quoted = regex::escape(value_opt.unwrap()).replace('/', "\\/");
+        let st = Rc::new(RefCell::new(StringTriple::new(quoted, None)));
+        self.stack.push(Value::String(st));
+        1
+    }
 }
@@ -3,7 +3,89 @@ use std::cmp::Ordering;
 use chunk::Value;
 use vm::*;
 
+/// Splits a string into runs of ASCII digits and runs of other
+/// characters.
+fn natural_runs(s: &str) -> Vec<&str> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut prev_digit = None;
+    for (i, c) in s.char_indices() {
+        let is_digit = c.is_ascii_digit();
+        if let Some(pd) = prev_digit {
+            if pd != is_digit {
+                runs.push(&s[start..i]);
+                start = i;
+            }
+        }
+        prev_digit = Some(is_digit);
+    }
+    if start < s.len() {
+        runs.push(&s[start..]);
+    }
+    runs
+}
+
+/// Compares two strings such that runs of digits are compared
+/// numerically, and all other runs are compared lexically.  This
+/// means that e.g. "file9" sorts before "file10", and "1.9.0" sorts
+/// before "1.10.0".
+fn natural_cmp(s1: &str, s2: &str) -> Ordering {
+    let runs1 = natural_runs(s1);
+    let runs2 = natural_runs(s2);
+    for (r1, r2) in runs1.iter().zip(runs2.iter()) {
+        let is_num1 = r1.as_bytes()[0].is_ascii_digit();
+        let is_num2 = r2.as_bytes()[0].is_ascii_digit();
+        let ord = if is_num1 && is_num2 {
+            let n1 = r1.trim_start_matches('0');
+            let n2 = r2.trim_start_matches('0');
+            n1.len()
+                .cmp(&n2.len())
+                .then_with(|| n1.cmp(n2))
+                .then_with(|| r2.len().cmp(&r1.len()))
+        } else {
+            r1.cmp(r2)
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    runs1.len().cmp(&runs2.len())
+}
+
 impl VM {
+    /// Takes two values that can be stringified as its arguments,
+    /// and compares them using natural (version-aware) ordering.
+    /// Returns -1, 0, or 1, as per `<=>`.
+    pub fn core_vcmp(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("vcmp requires two arguments");
+            return 0;
+        }
+
+        let v2_rr = self.stack.pop().unwrap();
+        let v1_rr = self.stack.pop().unwrap();
+        let v1_opt: Option<&str>;
+        to_str!(v1_rr, v1_opt);
+        let v2_opt: Option<&str>;
+        to_str!(v2_rr, v2_opt);
+
+        match (v1_opt, v2_opt) {
+            (Some(s1), Some(s2)) => {
+                let res = match natural_cmp(s1, s2) {
+                    Ordering::Less => -1,
+                    Ordering::Equal => 0,
+                    Ordering::Greater => 1,
+                };
+                self.stack.push(Value::Int(res));
+                1
+            }
+            _ => {
+                self.print_error("vcmp arguments must be strings");
+                0
+            }
+        }
+    }
+
     /// Sorts the elements of a list or generator using behaviour per
     /// the default cmp operation.
     pub fn core_sort(&mut self) -> i32 {
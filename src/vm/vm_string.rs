@@ -5,12 +5,13 @@ use std::rc::Rc;
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use chunk::{StringTriple, Value};
+use chunk::{StringTriple, StringWithIndex, Value};
 use vm::*;
 
 lazy_static! {
     static ref CAPTURE_NUM: Regex = Regex::new("\\{(\\d+)\\}").unwrap();
     static ref CAPTURE_WITHOUT_NUM: Regex = Regex::new("\\{\\}").unwrap();
+    static ref WHITESPACE: Regex = Regex::new("\\s+").unwrap();
 }
 
 impl VM {
@@ -284,7 +285,7 @@ impl VM {
                                             Some(&element_b.string)
                                         }
                                         _ => {
-                                            element_bk = element_rr.to_string();
+                                            element_bk = element_rr.to_string().unwrap_or(None);
                                             match element_bk {
                                                 Some(s) => {
                                                     element_str = s;
@@ -433,4 +434,123 @@ impl VM {
             }
         }
     }
+
+    /// Takes a string as its single argument.  Puts a generator onto
+    /// the stack that yields each character (grapheme cluster) of
+    /// the string.
+    pub fn core_chars(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("chars requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        match value_opt {
+            Some(s) => {
+                let swi = StringWithIndex::new(0, s.to_string());
+                self.stack
+                    .push(Value::CharsGenerator(Rc::new(RefCell::new(swi))));
+                1
+            }
+            None => {
+                self.print_error("chars argument must be string");
+                0
+            }
+        }
+    }
+
+    /// Takes a string as its single argument.  Puts a generator onto
+    /// the stack that yields each line of the string, without its
+    /// terminating "\n" or "\r\n".
+    pub fn core_lines(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("lines requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        match value_opt {
+            Some(s) => {
+                let swi = StringWithIndex::new(0, s.to_string());
+                self.stack
+                    .push(Value::LinesGenerator(Rc::new(RefCell::new(swi))));
+                1
+            }
+            None => {
+                self.print_error("lines argument must be string");
+                0
+            }
+        }
+    }
+
+    /// Takes a string as its single argument.  Replaces each run of
+    /// whitespace in the string with a single space, removes
+    /// whitespace from the start and end of the string, and puts the
+    /// resulting string onto the stack.
+    pub fn core_squeeze_ws(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("squeeze-ws requires one argument");
+            return 0;
+        }
+
+        let value_rr = self.stack.pop().unwrap();
+        let value_opt: Option<&str>;
+        to_str!(value_rr, value_opt);
+        match value_opt {
+            Some(s) => {
+                let squeezed = WHITESPACE.replace_all(s.trim(), " ").to_string();
+                self.stack
+                    .push(Value::String(Rc::new(RefCell::new(StringTriple::new(
+                        squeezed, None,
+                    )))));
+                1
+            }
+            None => {
+                self.print_error("squeeze-ws argument must be string");
+                0
+            }
+        }
+    }
+
+    /// Takes a string and a substring as its arguments.  Puts the
+    /// number of non-overlapping occurrences of the substring within
+    /// the string onto the stack.
+    pub fn core_count_str(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("count-str requires two arguments");
+            return 0;
+        }
+
+        let needle_rr = self.stack.pop().unwrap();
+        let needle_opt: Option<&str>;
+        to_str!(needle_rr, needle_opt);
+
+        let haystack_rr = self.stack.pop().unwrap();
+        let haystack_opt: Option<&str>;
+        to_str!(haystack_rr, haystack_opt);
+
+        match (haystack_opt, needle_opt) {
+            (_, Some("")) => {
+                self.print_error("second count-str argument must not be empty");
+                0
+            }
+            (Some(haystack), Some(needle)) => {
+                let count = haystack.matches(needle).count();
+                self.stack.push(Value::Int(count as i32));
+                1
+            }
+            (Some(_), _) => {
+                self.print_error("second count-str argument must be string");
+                0
+            }
+            (_, _) => {
+                self.print_error("first count-str argument must be string");
+                0
+            }
+        }
+    }
 }
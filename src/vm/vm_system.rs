@@ -1,41 +1,72 @@
+use nix::errno::Errno;
 use nix::sys::signal::Signal;
 use nix::unistd::{Group, Pid, User};
 use std::cell::RefCell;
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::env;
 use std::fs;
+use std::fs::FileTimes;
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::time::SystemTime;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
+use chrono::TimeZone;
 use indexmap::IndexMap;
 use num::FromPrimitive;
 use num_bigint::BigInt;
-use sysinfo::{ProcessExt, SystemExt};
-use utime::*;
+use sysinfo::{ProcessExt, ProcessorExt, SystemExt};
 
 use chunk::{StringTriple, Value};
+use vm::vm_io::tilde_expansion;
 use vm::*;
 
 impl VM {
-    /// Takes a value that can be stringified as its single argument.
-    /// Removes the file corresponding to that path.
+    /// Takes a value that can be stringified and an optional boolean
+    /// as its arguments.  Removes the file corresponding to that
+    /// path.  If the boolean is true, then directories are also
+    /// removed, along with their contents.  Refuses to remove the
+    /// root directory, or the current directory or any of its
+    /// ancestors.
     pub fn core_rm(&mut self) -> i32 {
         if self.stack.is_empty() {
             self.print_error("rm requires one argument");
             return 0;
         }
 
+        let recursive = self.pop_optional_flag(1);
+
         let value_rr = self.stack.pop().unwrap();
         let value_opt: Option<&str>;
         to_str!(value_rr, value_opt);
 
         match value_opt {
             Some(s) => {
-                let res = std::fs::remove_file(s);
+                let s = &tilde_expansion(s);
+                let is_dir = fs::symlink_metadata(s)
+                    .map(|meta| meta.is_dir())
+                    .unwrap_or(false);
+                let res = if !is_dir {
+                    std::fs::remove_file(s)
+                } else if !recursive {
+                    self.print_error("rm argument is a directory (use recursive flag)");
+                    return 0;
+                } else {
+                    let path = fs::canonicalize(s).unwrap_or_else(|_| PathBuf::from(s));
+                    let cwd_opt = env::current_dir().ok();
+                    if path.parent().is_none()
+                        || cwd_opt.map(|cwd| cwd.starts_with(&path)).unwrap_or(false)
+                    {
+                        self.print_error("rm argument must not be root or current directory");
+                        return 0;
+                    }
+                    std::fs::remove_dir_all(path)
+                };
                 match res {
                     Ok(_) => {}
                     Err(e) => {
@@ -53,15 +84,37 @@ impl VM {
         1
     }
 
-    /// Takes two values that can be stringified as its arguments.
-    /// Copies the file corresponding to the first path to the second
-    /// path.
+    /// Pops the optional boolean flag argument accepted by some
+    /// forms (e.g. the "force" flag for cp and mv), if it is present
+    /// after the required number of arguments, and returns its value.
+    /// (Defaults to false.)
+    pub fn pop_optional_flag(&mut self, required: usize) -> bool {
+        if self.stack.len() <= required {
+            return false;
+        }
+        match self.stack.last().unwrap() {
+            Value::Bool(flag) => {
+                let flag = *flag;
+                self.stack.pop();
+                flag
+            }
+            _ => false,
+        }
+    }
+
+    /// Takes two values that can be stringified and an optional
+    /// boolean as its arguments.  Copies the file corresponding to
+    /// the first path to the second path (including its
+    /// permissions).  If the second path already exists, then the
+    /// copy only happens if the boolean argument is true.
     pub fn core_cp(&mut self) -> i32 {
         if self.stack.len() < 2 {
             self.print_error("cp requires two arguments");
             return 0;
         }
 
+        let force = self.pop_optional_flag(2);
+
         let dst_rr = self.stack.pop().unwrap();
         let dst_opt: Option<&str>;
         to_str!(dst_rr, dst_opt);
@@ -72,6 +125,12 @@ impl VM {
 
         match (src_opt, dst_opt) {
             (Some(src), Some(dst)) => {
+                let src = &tilde_expansion(src);
+                let dst = &tilde_expansion(dst);
+                if !force && Path::new(dst).exists() {
+                    self.print_error("cp destination already exists");
+                    return 0;
+                }
                 let res = std::fs::copy(src, dst);
                 match res {
                     Ok(_) => {}
@@ -94,15 +153,20 @@ impl VM {
         1
     }
 
-    /// Takes two values that can be stringified as its arguments.
-    /// Moves the file corresponding to the first path to the second
-    /// path.
+    /// Takes two values that can be stringified and an optional
+    /// boolean as its arguments.  Moves the file corresponding to the
+    /// first path to the second path.  If the paths are on different
+    /// filesystems, then the file is copied to the second path and
+    /// then removed.  If the second path already exists, then the
+    /// move only happens if the boolean argument is true.
     pub fn core_mv(&mut self) -> i32 {
         if self.stack.len() < 2 {
             self.print_error("mv requires two arguments");
             return 0;
         }
 
+        let force = self.pop_optional_flag(2);
+
         let dst_rr = self.stack.pop().unwrap();
         let dst_opt: Option<&str>;
         to_str!(dst_rr, dst_opt);
@@ -113,6 +177,21 @@ impl VM {
 
         match (src_opt, dst_opt) {
             (Some(src), Some(dst)) => {
+                let src = &tilde_expansion(src);
+                let dst = &tilde_expansion(dst);
+                if !force && Path::new(dst).exists() {
+                    self.print_error("mv destination already exists");
+                    return 0;
+                }
+                match std::fs::rename(src, dst) {
+                    Ok(_) => return 1,
+                    Err(e) if e.raw_os_error() != Some(Errno::EXDEV as i32) => {
+                        let err_str = format!("unable to move file: {}", e);
+                        self.print_error(&err_str);
+                        return 0;
+                    }
+                    Err(_) => {}
+                }
                 let res = std::fs::copy(src, dst);
                 match res {
                     Ok(_) => {
@@ -164,6 +243,8 @@ impl VM {
 
         match (src_opt, dst_opt) {
             (Some(src), Some(dst)) => {
+                let src = &tilde_expansion(src);
+                let dst = &tilde_expansion(dst);
                 let res = std::fs::rename(src, dst);
                 match res {
                     Ok(_) => 1,
@@ -204,6 +285,8 @@ impl VM {
 
         match (src_opt, dst_opt) {
             (Some(src), Some(dst)) => {
+                let src = &tilde_expansion(src);
+                let dst = &tilde_expansion(dst);
                 let res = std::os::unix::fs::symlink(src, dst);
                 match res {
                     Ok(_) => {}
@@ -226,25 +309,115 @@ impl VM {
         1
     }
 
+    /// Takes two values that can be stringified as its arguments.
+    /// Creates a symbolic link at the second path that targets the
+    /// first path.  Unlike link, this fails if anything already
+    /// exists at the second path.
+    pub fn core_symlink(&mut self) -> i32 {
+        if self.stack.len() < 2 {
+            self.print_error("symlink requires two arguments");
+            return 0;
+        }
+
+        let dst_rr = self.stack.pop().unwrap();
+        let dst_opt: Option<&str>;
+        to_str!(dst_rr, dst_opt);
+
+        let src_rr = self.stack.pop().unwrap();
+        let src_opt: Option<&str>;
+        to_str!(src_rr, src_opt);
+
+        match (src_opt, dst_opt) {
+            (Some(src), Some(dst)) => {
+                let src = &tilde_expansion(src);
+                let dst = &tilde_expansion(dst);
+                if fs::symlink_metadata(dst).is_ok() {
+                    self.print_error("symlink destination already exists");
+                    return 0;
+                }
+                let res = std::os::unix::fs::symlink(src, dst);
+                match res {
+                    Ok(_) => {}
+                    Err(e) => {
+                        let err_str = format!("unable to create symbolic link: {}", e);
+                        self.print_error(&err_str);
+                        return 0;
+                    }
+                }
+            }
+            (Some(_), _) => {
+                self.print_error("second symlink argument must be string");
+                return 0;
+            }
+            _ => {
+                self.print_error("first symlink argument must be string");
+                return 0;
+            }
+        }
+        1
+    }
+
+    /// Takes a value that can be stringified as its single argument.
+    /// Returns the target path of the symbolic link at that path.
+    pub fn core_readlink(&mut self) -> i32 {
+        if self.stack.is_empty() {
+            self.print_error("readlink requires one argument");
+            return 0;
+        }
+
+        let path_rr = self.stack.pop().unwrap();
+        let path_opt: Option<&str>;
+        to_str!(path_rr, path_opt);
+
+        let path = match path_opt {
+            Some(path) => tilde_expansion(path),
+            _ => {
+                self.print_error("readlink argument must be string");
+                return 0;
+            }
+        };
+
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if !meta.file_type().is_symlink() => {
+                self.print_error("readlink argument must be symbolic link");
+                return 0;
+            }
+            Err(e) => {
+                let err_str = format!("unable to read link: {}", e);
+                self.print_error(&err_str);
+                return 0;
+            }
+            _ => {}
+        }
+
+        match fs::read_link(&path) {
+            Ok(target) => {
+                let target_str = target.to_string_lossy().to_string();
+                self.stack
+                    .push(Value::String(Rc::new(RefCell::new(StringTriple::new(
+                        target_str, None,
+                    )))));
+                1
+            }
+            Err(e) => {
+                let err_str = format!("unable to read link: {}", e);
+                self.print_error(&err_str);
+                0
+            }
+        }
+    }
+
     /// Takes a value that can be stringified as its single argument.
     /// Changes the current working directory to that directory.  If
     /// no arguments are provided, then this changes the current
-    /// working directory to the user's home directory.
+    /// working directory to the user's home directory.  If the
+    /// argument is "-", then this changes the current working
+    /// directory to the previous working directory.
     pub fn core_cd(&mut self) -> i32 {
-        if self.stack.is_empty() {
+        let (dir, err_prefix) = if self.stack.is_empty() {
             let home_res = std::env::var("HOME");
             match home_res {
-                Ok(home) => {
-                    let res = env::set_current_dir(&home);
-                    match res {
-                        Ok(_) => {}
-                        Err(e) => {
-                            let err_str = format!("unable to cd to home: {}", e);
-                            self.print_error(&err_str);
-                            return 0;
-                        }
-                    }
-                }
+                Ok(home) => (PathBuf::from(home), "unable to cd to home"),
                 Err(e) => {
                     let err_str = format!("unable to cd to home: {}", e);
                     self.print_error(&err_str);
@@ -257,25 +430,34 @@ impl VM {
             to_str!(dir_rr, dir_opt);
 
             match dir_opt {
-                Some(dir) => {
-                    let path_dir = Path::new(&dir);
-                    let res = env::set_current_dir(&path_dir);
-                    match res {
-                        Ok(_) => {}
-                        Err(e) => {
-                            let err_str = format!("unable to cd: {}", e);
-                            self.print_error(&err_str);
-                            return 0;
-                        }
+                Some("-") => match self.previous_dir.clone() {
+                    Some(previous_dir) => (previous_dir, "unable to cd"),
+                    None => {
+                        self.print_error("unable to cd: no previous directory");
+                        return 0;
                     }
-                }
+                },
+                Some(dir) => (PathBuf::from(tilde_expansion(dir)), "unable to cd"),
                 _ => {
                     self.print_error("cd argument must be a string");
                     return 0;
                 }
             }
+        };
+
+        let current_dir_opt = env::current_dir().ok();
+        let res = env::set_current_dir(&dir);
+        match res {
+            Ok(_) => {
+                self.previous_dir = current_dir_opt;
+                1
+            }
+            Err(e) => {
+                let err_str = format!("{}: {}", err_prefix, e);
+                self.print_error(&err_str);
+                0
+            }
         }
-        1
     }
 
     /// Puts the string representation of the current working
@@ -299,22 +481,62 @@ impl VM {
         1
     }
 
-    /// Takes a value that can be stringified as its single argument.
-    /// Creates the file if it doesn't exist, and updates its
-    /// modification timestamp to the current time if it does exist,
-    /// similarly to touch(1).
+    /// Takes a value that can be stringified and an optional
+    /// date-time object as its arguments.  If no date-time object is
+    /// provided, then this creates the file if it doesn't exist, and
+    /// updates its modification timestamp to the current time if it
+    /// does exist, similarly to touch(1).  Otherwise, this sets the
+    /// access and modification timestamps of the existing file to the
+    /// given time.
     pub fn core_touch(&mut self) -> i32 {
         if self.stack.is_empty() {
             self.print_error("touch requires one argument");
             return 0;
         }
 
+        let mut time_opt = None;
+        if self.stack.len() >= 2 {
+            match self.stack.last().unwrap() {
+                Value::DateTimeNT(dt) => {
+                    time_opt = Some(dt.timestamp());
+                }
+                Value::DateTimeOT(dt) => {
+                    time_opt = Some(dt.timestamp());
+                }
+                _ => {}
+            }
+            if time_opt.is_some() {
+                self.stack.pop();
+            }
+        }
+
         let path_rr = self.stack.pop().unwrap();
         let path_opt: Option<&str>;
         to_str!(path_rr, path_opt);
 
-        match path_opt {
-            Some(path_str) => {
+        match (path_opt, time_opt) {
+            (Some(path_str), Some(time)) => {
+                let path_str = &tilde_expansion(path_str);
+                let system_time = if time >= 0 {
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(time as u64)
+                } else {
+                    SystemTime::UNIX_EPOCH - Duration::from_secs(time.unsigned_abs())
+                };
+                let times = FileTimes::new()
+                    .set_accessed(system_time)
+                    .set_modified(system_time);
+                let sft_res = fs::File::open(path_str).and_then(|f| f.set_times(times));
+                match sft_res {
+                    Ok(_) => {}
+                    Err(e) => {
+                        let err_str = format!("unable to set file times: {}", e);
+                        self.print_error(&err_str);
+                        return 0;
+                    }
+                }
+            }
+            (Some(path_str), None) => {
+                let path_str = &tilde_expansion(path_str);
                 let path = Path::new(&path_str);
                 if !path.exists() {
                     let res = fs::write(&path_str, "");
@@ -327,23 +549,10 @@ impl VM {
                         }
                     }
                 } else {
-                    let times_res = get_file_times(&path_str);
-                    match times_res {
-                        Ok((accessed, _)) => {
-                            let mtime = SystemTime::now()
-                                .duration_since(SystemTime::UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs();
-                            let sft_res = set_file_times(&path_str, accessed, mtime as i64);
-                            match sft_res {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    let err_str = format!("unable to write file: {}", e);
-                                    self.print_error(&err_str);
-                                    return 0;
-                                }
-                            }
-                        }
+                    let times = FileTimes::new().set_modified(SystemTime::now());
+                    let sft_res = fs::File::open(path_str).and_then(|f| f.set_times(times));
+                    match sft_res {
+                        Ok(_) => {}
                         Err(e) => {
                             let err_str = format!("unable to write file: {}", e);
                             self.print_error(&err_str);
@@ -369,8 +578,11 @@ impl VM {
     /// is the user ID of the owner, "gid" is the group ID of the
     /// owner, "rdev" is the device ID (for special files), "size" is
     /// the total size in bytes, "atime"/"ctime"/"mtime" are various
-    /// file modification times, "blksize" is the block size, and
-    /// "blocks" is the number of blocks allocated to the file.
+    /// file modification times (as date-time objects), "blksize" is
+    /// the block size, "blocks" is the number of blocks allocated to
+    /// the file, and "is-dir"/"is-file"/"is-symlink" indicate the
+    /// type of the file.  The mode is returned as an octal string
+    /// containing the permission bits only.
     fn stat_inner(&mut self, use_symlink: bool) -> i32 {
         if self.stack.is_empty() {
             self.print_error("stat requires one argument");
@@ -383,6 +595,7 @@ impl VM {
 
         match path_opt {
             Some(s) => {
+                let s = &tilde_expansion(s);
                 let meta_res = if use_symlink {
                     fs::symlink_metadata(&s)
                 } else {
@@ -401,7 +614,10 @@ impl VM {
                         );
                         map.insert(
                             "mode".to_string(),
-                            Value::BigInt(BigInt::from_u32(meta.mode()).unwrap()),
+                            Value::String(Rc::new(RefCell::new(StringTriple::new(
+                                format!("{:o}", meta.mode() & 0o7777),
+                                None,
+                            )))),
                         );
                         map.insert(
                             "nlink".to_string(),
@@ -425,15 +641,15 @@ impl VM {
                         );
                         map.insert(
                             "atime".to_string(),
-                            Value::BigInt(BigInt::from_i64(meta.atime()).unwrap()),
+                            Value::DateTimeNT(self.utc_tz.timestamp_opt(meta.atime(), 0).unwrap()),
                         );
                         map.insert(
                             "mtime".to_string(),
-                            Value::BigInt(BigInt::from_i64(meta.mtime()).unwrap()),
+                            Value::DateTimeNT(self.utc_tz.timestamp_opt(meta.mtime(), 0).unwrap()),
                         );
                         map.insert(
                             "ctime".to_string(),
-                            Value::BigInt(BigInt::from_i64(meta.ctime()).unwrap()),
+                            Value::DateTimeNT(self.utc_tz.timestamp_opt(meta.ctime(), 0).unwrap()),
                         );
                         map.insert(
                             "blksize".to_string(),
@@ -443,6 +659,13 @@ impl VM {
                             "blocks".to_string(),
                             Value::BigInt(BigInt::from_u64(meta.blocks()).unwrap()),
                         );
+                        let file_type = meta.file_type();
+                        map.insert("is-dir".to_string(), Value::Bool(file_type.is_dir()));
+                        map.insert("is-file".to_string(), Value::Bool(file_type.is_file()));
+                        map.insert(
+                            "is-symlink".to_string(),
+                            Value::Bool(file_type.is_symlink()),
+                        );
                         self.stack.push(Value::Hash(Rc::new(RefCell::new(map))));
                     }
                     Err(e) => {
@@ -519,8 +742,66 @@ impl VM {
         1
     }
 
-    /// Takes a process identifier and a signal name as its arguments.
-    /// Sends the relevant signal to the process.
+    /// Returns a hash containing the total, used, free, and available
+    /// memory for the system, in bytes.
+    pub fn core_sys_mem(&mut self) -> i32 {
+        self.sys.refresh_memory();
+
+        let mut map = IndexMap::new();
+        for (key, kb) in [
+            ("total", self.sys.total_memory()),
+            ("used", self.sys.used_memory()),
+            ("free", self.sys.free_memory()),
+            ("available", self.sys.available_memory()),
+        ] {
+            map.insert(key.to_string(), Value::BigInt(BigInt::from(kb) * 1024));
+        }
+        self.stack.push(Value::Hash(Rc::new(RefCell::new(map))));
+        1
+    }
+
+    /// Returns a list containing the current usage percentage for
+    /// each processor.  The usage is calculated by sampling the
+    /// processors twice, with a short delay in between.
+    pub fn core_sys_cpu(&mut self) -> i32 {
+        self.sys.refresh_cpu();
+        thread::sleep(Duration::from_millis(200));
+        self.sys.refresh_cpu();
+
+        let lst = self
+            .sys
+            .processors()
+            .iter()
+            .map(|p| Value::Float(p.cpu_usage() as f64))
+            .collect::<VecDeque<Value>>();
+        self.stack.push(Value::List(Rc::new(RefCell::new(lst))));
+        1
+    }
+
+    /// Returns a hash containing the system load averages over the
+    /// last one, five, and fifteen minutes.
+    pub fn core_sys_load(&mut self) -> i32 {
+        let load = self.sys.load_average();
+
+        let mut map = IndexMap::new();
+        map.insert("one".to_string(), Value::Float(load.one));
+        map.insert("five".to_string(), Value::Float(load.five));
+        map.insert("fifteen".to_string(), Value::Float(load.fifteen));
+        self.stack.push(Value::Hash(Rc::new(RefCell::new(map))));
+        1
+    }
+
+    /// Returns the process identifier of the current process.
+    pub fn core_getpid(&mut self) -> i32 {
+        self.stack.push(Value::Int(std::process::id() as i32));
+        1
+    }
+
+    /// Takes a process identifier and a signal as its arguments.  The
+    /// signal may be a name (e.g. "term" or "SIGTERM") or a number,
+    /// where zero checks whether the process exists without sending
+    /// a signal.  Sends the relevant signal to the process, and
+    /// returns a boolean indicating success.
     pub fn core_kill(&mut self) -> i32 {
         if self.stack.len() < 2 {
             self.print_error("kill requires two arguments");
@@ -528,29 +809,28 @@ impl VM {
         }
 
         let sig_rr = self.stack.pop().unwrap();
-        let sig_opt: Option<&str>;
-        to_str!(sig_rr, sig_opt);
+        let sig_res = match sig_rr {
+            Value::Int(0) => Some(Ok(None)),
+            Value::Int(n) => Some(Signal::try_from(n).map(Some)),
+            _ => {
+                let sig_opt: Option<&str>;
+                to_str!(sig_rr, sig_opt);
+                sig_opt.map(|sig| {
+                    let sig_uc = sig.to_uppercase();
+                    if sig_uc.starts_with("SIG") {
+                        Signal::from_str(&sig_uc).map(Some)
+                    } else {
+                        Signal::from_str(&format!("SIG{}", sig_uc)).map(Some)
+                    }
+                })
+            }
+        };
 
         let pid_rr = self.stack.pop().unwrap();
         let pid_int_opt = pid_rr.to_int();
 
-        match (pid_int_opt, sig_opt) {
-            (Some(pid), Some(sig)) => {
-                let sig_lc = sig.to_lowercase();
-                let sig_obj = match &sig_lc[..] {
-                    "hup" => Signal::SIGHUP,
-                    "int" => Signal::SIGINT,
-                    "term" => Signal::SIGTERM,
-                    "kill" => Signal::SIGKILL,
-                    "usr1" => Signal::SIGUSR1,
-                    "usr2" => Signal::SIGUSR2,
-                    "cont" => Signal::SIGCONT,
-                    "stop" => Signal::SIGSTOP,
-                    _ => {
-                        self.print_error("invalid signal");
-                        return 0;
-                    }
-                };
+        match (pid_int_opt, sig_res) {
+            (Some(pid), Some(Ok(sig_obj))) => {
                 let res = nix::sys::signal::kill(Pid::from_raw(pid), sig_obj);
                 match res {
                     Ok(_) => {}
@@ -560,8 +840,13 @@ impl VM {
                         return 0;
                     }
                 }
+                self.stack.push(Value::Bool(true));
                 1
             }
+            (Some(_), Some(Err(_))) => {
+                self.print_error("invalid signal");
+                0
+            }
             (Some(_), _) => {
                 self.print_error("second kill argument must be signal");
                 0
@@ -573,8 +858,9 @@ impl VM {
         }
     }
 
-    /// Takes a path and a numeric mode as its arguments, and updates
-    /// the path's mode accordingly.
+    /// Takes a path and a mode as its arguments, and updates the
+    /// path's mode accordingly.  The mode may be an integer, or a
+    /// string containing an octal number (e.g. "755").
     pub fn core_chmod(&mut self) -> i32 {
         if self.stack.len() < 2 {
             self.print_error("chmod requires two arguments");
@@ -582,7 +868,15 @@ impl VM {
         }
 
         let mode_rr = self.stack.pop().unwrap();
-        let mode_opt = mode_rr.to_int();
+        let mode_opt = match mode_rr {
+            Value::String(ref st) => {
+                let s = st.borrow();
+                let digits = s.string.strip_prefix("0o").unwrap_or(&s.string);
+                u32::from_str_radix(digits, 8).ok()
+            }
+            _ => mode_rr.to_int().and_then(|n| u32::try_from(n).ok()),
+        }
+        .filter(|&mode| mode <= 0o7777);
 
         let path_rr = self.stack.pop().unwrap();
         let path_opt: Option<&str>;
@@ -590,6 +884,7 @@ impl VM {
 
         match (path_opt, mode_opt) {
             (Some(path), Some(mode)) => {
+                let path = &tilde_expansion(path);
                 let f_opt = fs::metadata(&path);
                 if f_opt.is_err() {
                     self.print_error("unable to get metadata for path");
@@ -597,7 +892,7 @@ impl VM {
                 }
                 let f = f_opt.unwrap();
                 let mut perms = f.permissions();
-                perms.set_mode(mode.try_into().unwrap());
+                perms.set_mode(mode);
                 let res = fs::set_permissions(&path, perms);
                 match res {
                     Ok(_) => 1,
@@ -641,6 +936,7 @@ impl VM {
 
         match (path_opt, user_opt, group_opt) {
             (Some(path), Some(user), Some(group)) => {
+                let path = &tilde_expansion(path);
                 let user_opt_res = User::from_name(user);
                 if user_opt_res.is_err() {
                     self.print_error("second chown argument must be valid user");
@@ -665,7 +961,8 @@ impl VM {
                 }
                 let group_obj = group_opt.unwrap();
 
-                let chown_res = nix::unistd::chown(path, Some(user_obj.uid), Some(group_obj.gid));
+                let chown_res =
+                    nix::unistd::chown(path.as_str(), Some(user_obj.uid), Some(group_obj.gid));
                 match chown_res {
                     Ok(_) => 1,
                     Err(e) => {
@@ -690,21 +987,30 @@ impl VM {
         }
     }
 
-    /// Takes a path as its single argument, and attempts to make a
-    /// directory at that path.
+    /// Takes a path and an optional boolean as its arguments, and
+    /// attempts to make a directory at that path.  If the boolean is
+    /// true, then any missing parent directories are also made, and
+    /// it is not an error for the directory to exist already.
     pub fn core_mkdir(&mut self) -> i32 {
         if self.stack.is_empty() {
             self.print_error("mkdir requires one argument");
             return 0;
         }
 
+        let parents = self.pop_optional_flag(1);
+
         let dir_rr = self.stack.pop().unwrap();
         let dir_opt: Option<&str>;
         to_str!(dir_rr, dir_opt);
 
         match dir_opt {
             Some(dir) => {
-                let res = std::fs::create_dir(dir);
+                let dir = &tilde_expansion(dir);
+                let res = if parents {
+                    std::fs::create_dir_all(dir)
+                } else {
+                    std::fs::create_dir(dir)
+                };
                 match res {
                     Ok(_) => 1,
                     Err(e) => {
@@ -735,6 +1041,7 @@ impl VM {
 
         match dir_opt {
             Some(dir) => {
+                let dir = &tilde_expansion(dir);
                 let res = std::fs::remove_dir(dir);
                 match res {
                     Ok(_) => 1,
@@ -1,10 +1,20 @@
 extern crate assert_cmd;
 extern crate cosh;
+extern crate nix;
 extern crate tempfile;
 
+use assert_cmd::cargo::cargo_bin;
 use assert_cmd::Command;
+use cosh::chunk::{Chunk, StringTriple, Value};
+use cosh::opcode::OpCode;
+use cosh::vm::{VmError, VM};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::process::{Command as StdCommand, Stdio};
+use std::rc::Rc;
 use tempfile::NamedTempFile;
 
 #[test]
@@ -39,11 +49,31 @@ fn basic_error_test(input: &str, output: &str) {
     assert.success().stderr(output.to_owned() + "\n");
 }
 
+fn stdin_test(input: &str, stdin: &str, output: &str) {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "{}", input).unwrap();
+
+    let mut cmd = Command::cargo_bin("cosh").unwrap();
+    let path = file.path();
+    let assert = cmd.arg(path).write_stdin(stdin).assert();
+    let output2 = format!("{}\n", output);
+    assert.success().stdout(output2);
+}
+
 #[test]
 fn add() {
     basic_test("1 2 +;", "3");
 }
 
+#[test]
+fn add_overflow_test() {
+    basic_test("2147483647 1 +;", "2147483648");
+    basic_test("2147483647 x var; x !; x @; 1 +;", "2147483648");
+    basic_test("-2147483648 -1 +;", "-2147483649");
+    basic_test("-9223372036854775809 1.5 +;", "-9223372036854776000");
+    basic_test("18446744073709551616 1.0 +;", "18446744073709552000");
+}
+
 #[test]
 fn subtract() {
     basic_test("1 2 -;", "-1");
@@ -197,6 +227,22 @@ fn divide_error() {
     basic_error_test("1 t /;", "1:5: / requires two numbers");
 }
 
+#[test]
+fn divide_by_zero_error() {
+    basic_error_test("5 0 /;", "1:5: division by zero");
+    basic_error_test("10000000000000 0 /;", "1:18: division by zero");
+    basic_error_test("1.0 0.0 /;", "1:9: division by zero");
+    basic_error_test("0.0 0.0 /; is-nan;", "1:9: division by zero");
+    basic_error_test("1.0 0.0 /; is-inf;", "1:9: division by zero");
+    basic_error_test("5 x var; 0 x !; x @; /;", "1:22: division by zero");
+}
+
+#[test]
+fn divide_overflow() {
+    basic_test("-2147483648 -1 /;", "2147483648");
+    basic_test("-2147483648 x var; -1 x !; x @; /;", "2147483648");
+}
+
 #[test]
 fn equals_error() {
     basic_test("1 t =;", ".f");
@@ -335,6 +381,65 @@ fn ge_test() {
     basic_test("1 0 >=;", ".t");
 }
 
+#[test]
+fn boolean_test() {
+    basic_test(".t .f and;", ".f");
+    basic_test(".t 1 and;", ".t");
+    basic_test("0 x or;", ".t");
+    basic_test("0 .f or;", ".f");
+    basic_test("\"\" not;", ".t");
+    basic_test("asdf not;", ".f");
+}
+
+#[test]
+fn bigint_bool_test() {
+    basic_test("0 bigint; bool;", ".f");
+    basic_test("5 bigint; bool;", ".t");
+    basic_test("10000000000 if; 1 else; 2 then;", "1");
+    basic_test("10000000000 10000000000 -; if; 1 else; 2 then;", "2");
+}
+
+#[test]
+fn numeric_bool_test() {
+    basic_test("0 bool;", ".f");
+    basic_test("-1 bool;", ".t");
+    basic_test("0.0 bool;", ".f");
+    basic_test("-0.0 bool;", ".f");
+    basic_test("0.5 bool;", ".t");
+    basic_test("0 bigint; bool;", ".f");
+    basic_test("-0.0 if; 1 else; 2 then;", "2");
+}
+
+#[test]
+fn vcmp_test() {
+    basic_test("file10 file9 vcmp;", "1");
+    basic_test("file9 file10 vcmp;", "-1");
+    basic_test("1.10.0 1.9.0 vcmp;", "1");
+    basic_test("1.9.0 1.9.0 vcmp;", "0");
+    basic_test("a2b10 a2b9 vcmp;", "1");
+    basic_test("a10b a9c vcmp;", "1");
+    basic_test("v1.2 v1.2a vcmp;", "-1");
+    basic_test(
+        "(file10 file9 1.10.0 1.9.0) vcmp sortp;",
+        "(\n    0: 1.9.0\n    1: 1.10.0\n    2: file9\n    3: file10\n)",
+    );
+}
+
+#[test]
+fn ne_test() {
+    basic_test("a b !=;", ".t");
+    basic_test("a a !=;", ".f");
+    basic_test("1 1.0 !=;", ".f");
+}
+
+#[test]
+fn relations_test() {
+    basic_test("2 2 >=;", ".t");
+    basic_test("1 0 <=;", ".f");
+    basic_test("a b <=;", ".t");
+    basic_test("b a >=;", ".t");
+}
+
 #[test]
 fn is_null_test() {
     basic_test(
@@ -403,6 +508,28 @@ fn import_test() {
     basic_test("(1 2 3) sum;", "6");
 }
 
+#[test]
+fn generator_aggregate_test() {
+    basic_test("5 range; gmin", "0");
+    basic_test("5 range; gmax", "4");
+    basic_test("5 range; gsum", "10");
+    basic_test("5 range; gavg", "2");
+    basic_test("(3 1.5 10 -2) gmin", "-2");
+    basic_test("(3 1.5 10 -2) gmax", "10");
+    basic_test("(3 1.5 10 -2) gsum", "12.5");
+    basic_test("(3 1.5 10 -2) gavg", "3.125");
+    basic_test("(2147483647 1) gsum", "2147483648");
+    basic_test("(1 2) dup; gsum; drop; len", "2");
+    basic_test("(\"1\" \"2\") gsum", "3");
+    basic_test("(\"10\" \"9\") gmax", "10");
+    basic_test("(\"1.5\" 2) gavg", "1.75");
+    basic_test("0 range; gmin; is-null", ".t");
+    basic_test("0 range; gmax; is-null", ".t");
+    basic_test("0 range; gsum", "0");
+    basic_error_test("0 range; gavg", "1:10: gavg argument must not be empty");
+    basic_error_test("(1 a) gsum", "1:8: gsum argument must contain only numbers");
+}
+
 #[test]
 fn push_test() {
     basic_test(
@@ -443,6 +570,85 @@ fn single_quote_test() {
     basic_test("'asdf asdf' println;", "asdf asdf");
 }
 
+#[test]
+fn pp_test() {
+    basic_test(
+        "h(c (1 (2 3) h()) b h(d .t e null)) pp",
+        "b:\n  e: null\n  d: .t\nc:\n  - 1\n  -\n    - 2\n    - 3\n  - {}",
+    );
+    basic_test("'asdf asdf' pp", "asdf asdf");
+    basic_test("() pp", "[]");
+    basic_test("[1 2] pp", "anon-fn");
+    basic_test("3 range; pp", "range-gen");
+}
+
+#[test]
+fn table_test() {
+    basic_test(
+        "COLUMNS 80 setenv; (h(age 30 name alice) h(age 4 name bob)) table",
+        "name   age\n-----  ---\nalice  30\nbob    4",
+    );
+    basic_test(
+        "COLUMNS 80 setenv; (h(age 30 name alice) h(name bob)) table",
+        "name   age\n-----  ---\nalice  30\nbob",
+    );
+    basic_test(
+        "COLUMNS 20 setenv; (h(desc 'a long description' name alice)) table",
+        "name   desc\n-----  -------------\nalice  a long des...",
+    );
+    basic_test("COLUMNS 80 setenv; () table; 1", "1");
+    basic_error_test("(1 2) table", "1:8: table argument must be list of hashes");
+}
+
+#[test]
+fn color_test() {
+    basic_test("hello red color; println", "hello");
+    basic_test(
+        "CLICOLOR_FORCE 1 setenv; hello red color; println",
+        "\u{1b}[38;5;1mhello\u{1b}[m",
+    );
+    basic_test(
+        "CLICOLOR_FORCE 1 setenv; hello bold-blue color; println",
+        "\u{1b}[1m\u{1b}[38;5;4mhello\u{1b}[m",
+    );
+    basic_error_test(
+        "hello purple color",
+        "1:14: color argument must be known color name",
+    );
+}
+
+#[test]
+fn sys_test() {
+    basic_test(
+        "sys.mem; keys; take-all",
+        "(\n    0: total\n    1: used\n    2: free\n    3: available\n)",
+    );
+    basic_test("sys.mem; dup; used get; swap; total get; <=", ".t");
+    basic_test("sys.cpu; len; 0 >", ".t");
+    basic_test(
+        "sys.load; keys; take-all",
+        "(\n    0: one\n    1: five\n    2: fifteen\n)",
+    );
+}
+
+#[test]
+fn kill_test() {
+    basic_test("getpid; 0 kill", ".t");
+    basic_test("getpid; cont kill; getpid; SIGCONT kill", ".t\n.t");
+    basic_error_test("getpid; bogus kill", "1:15: invalid signal");
+    basic_error_test(
+        "2147483647 0 kill",
+        "1:14: unable to kill process: ESRCH: No such process",
+    );
+}
+
+/* Signal numbers vary by platform: 18 is SIGCONT on Linux. */
+#[cfg(target_os = "linux")]
+#[test]
+fn kill_number_test() {
+    basic_test("getpid; 18 kill", ".t");
+}
+
 #[test]
 fn regex_tests() {
     basic_test("'asdf asdf' asdf m;", ".t");
@@ -591,6 +797,21 @@ fn external_command_test() {
     basic_test("$ls tests", "tests.rs");
 }
 
+#[test]
+fn command_glob_test() {
+    basic_test("$ls tests/*.rs", "tests/tests.rs");
+    basic_test(
+        "{echo src/vm/vm_i*.rs}; shift; chomp",
+        "\"src/vm/vm_io.rs src/vm/vm_ip.rs\"",
+    );
+    basic_test("{echo *.nomatch}; shift; chomp", "*.nomatch");
+    basic_test("{echo \"src/*.rs\"}; shift; chomp", "src/*.rs");
+    basic_error_test(
+        ".t set-failglob; {echo *.nomatch}; shift; chomp",
+        "1:18: no matches found for pattern: *.nomatch",
+    );
+}
+
 #[test]
 fn bigint_test_add() {
     basic_test("1000000000000000000 1 +;", "1000000000000000001");
@@ -781,7 +1002,7 @@ fn anon_fn_test() {
 f;
 funcall;
     ",
-        "5:6: anonymous function environment has gone out of scope",
+        "5:6: anonymous function environment has gone out of scope\n  called from 8:1",
     );
 }
 
@@ -875,6 +1096,24 @@ fn date_test() {
                ".t");
 }
 
+#[test]
+fn duration_test() {
+    basic_test("3723 humanize-duration;", "\"1h 2m 3s\"");
+    basic_test("0 humanize-duration;", "0s");
+    basic_test("1.5 humanize-duration;", "\"1s 500ms\"");
+    basic_test("1h30m parse-duration;", "5400");
+    basic_test("\"1h 2m 3s\" parse-duration;", "3723");
+    basic_test("250ms parse-duration;", "0.25");
+    basic_test("0s parse-duration;", "0");
+    basic_test("-3723 humanize-duration;", "\"-1h 2m 3s\"");
+    basic_test("-3723 humanize-duration; parse-duration;", "-3723");
+    basic_test("-1.5 humanize-duration; parse-duration;", "-1.5");
+    basic_error_test(
+        "5x parse-duration;",
+        "1:4: parse-duration argument must be duration string",
+    );
+}
+
 #[test]
 fn ip_test() {
     basic_test("1.0.0.0/24 ip", "v[ip 1.0.0.0/24]");
@@ -885,6 +1124,7 @@ fn ip_test() {
     basic_test("16.0.0.0/7 ip; ip.last-addr", "17.255.255.255");
     basic_test("16.0.0.0/7 ip; ip.last-addr-int", "301989887");
     basic_test("1.0.0.0/24 ip; ip.size", "256");
+    basic_test("0.0.0.0/0 ip; ip.size", "4294967296");
     basic_test("1.0.0.0/24 ip; ip.version", "4");
     basic_test("1.0.0.0/24 ip; str", "1.0.0.0/24");
 
@@ -902,6 +1142,10 @@ fn ip_test() {
         "63808136094534496727011269389785759743",
     );
     basic_test("::/112 ip; ip.size", "65536");
+    basic_test(
+        "::/0 ip; ip.size",
+        "340282366920938463463374607431768211456",
+    );
     basic_test(":: ip; ip.version", "6");
     basic_test("ABCD::/32 ip; str", "abcd::/32");
 
@@ -912,8 +1156,20 @@ fn ip_test() {
     basic_test("16.0.0.0-17.255.255.255 ip; ip.last-addr", "17.255.255.255");
     basic_test("16.0.0.0-17.255.255.255 ip; ip.last-addr-int", "301989887");
     basic_test("1.0.0.0-1.0.0.255 ip; ip.size", "256");
+    basic_test("0.0.0.0-255.255.255.255 ip; ip.size", "4294967296");
     basic_test("1.0.0.0-1.0.0.255 ip; ip.version", "4");
     basic_test("1.0.0.0-1.0.0.255 ip; str", "1.0.0.0-1.0.0.255");
+    basic_test("10.0.0.1-10.0.0.1 ip; ip.len", "32");
+    basic_test("0.0.0.0-127.255.255.255 ip; ip.len", "1");
+    basic_test("0.0.0.0-255.255.255.255 ip; ip.len", "0");
+    basic_error_test(
+        "10.0.0.0-10.0.0.2 ip; ip.len",
+        "1:23: ip.len argument has no length",
+    );
+    basic_error_test(
+        "10.0.0.1-10.0.0.2 ip; ip.len",
+        "1:23: ip.len argument has no length",
+    );
 
     basic_test("31CC::-31CC::ffff:ffff:ffff:ffff ip; ip.addr", "31cc::");
     basic_test(
@@ -986,6 +1242,267 @@ fn ipset_test() {
     );
 }
 
+#[test]
+fn ip_summarize_test() {
+    basic_test(
+        "(10.0.0.0/26 10.0.0.64/26 10.0.0.128/26 10.0.0.192/26) ip.summarize; str map;",
+        "(\n    0: 10.0.0.0/24\n)",
+    );
+    basic_test(
+        "(1.0.0.0/24 1.0.0.128/25 1.0.1.0/24 3.0.0.0 ::1 ::) ip.summarize; str map;",
+        "(\n    0: 1.0.0.0/23\n    1: 3.0.0.0\n    2: ::/127\n)",
+    );
+}
+
+#[test]
+fn ip_exclude_test() {
+    basic_test(
+        "10.0.0.0/24 ip; 10.0.0.64/26 ip; ip.exclude; str map;",
+        "(\n    0: 10.0.0.0/26\n    1: 10.0.0.128/25\n)",
+    );
+    basic_test(
+        "10.0.0.0/24 ip; 10.0.0.64/26 ip; ip.exclude; ips; 10.0.0.64/26 ips; union; str;",
+        "10.0.0.0/24",
+    );
+    basic_test(
+        "10.0.0.0/24 ip; 10.0.0.64/26 ip; ip.exclude; ips; 10.0.0.64/26 ips; isect; str;",
+        "\"\"",
+    );
+    basic_test(
+        "10.0.0.0/24 ip; 10.0.1.0/24 ip; ip.exclude; str map;",
+        "(\n    0: 10.0.0.0/24\n)",
+    );
+    basic_test("10.0.0.0/24 ip; 10.0.0.0/23 ip; ip.exclude; len;", "0");
+    basic_test(
+        "::/126 ip; ::1 ip; ip.exclude; str map;",
+        "(\n    0: ::\n    1: ::2/127\n)",
+    );
+}
+
+#[test]
+fn ip_ptr_zone_test() {
+    basic_test("192.0.2.0/24 ip; ip.ptr-zone", "2.0.192.in-addr.arpa");
+    basic_test("10.0.0.0/8 ip; ip.ptr-zone", "10.in-addr.arpa");
+    basic_test(
+        "192.0.2.64/26 ip; ip.ptr-zone",
+        "64/26.2.0.192.in-addr.arpa",
+    );
+    basic_test("2001:db8::/32 ip; ip.ptr-zone", "8.b.d.0.1.0.0.2.ip6.arpa");
+    basic_error_test(
+        "10.0.0.0/12 ip; ip.ptr-zone",
+        "1:17: ip.ptr-zone argument must be octet-aligned (split it into /8s, /16s or /24s first)",
+    );
+}
+
+#[test]
+fn ip_network_broadcast_test() {
+    basic_test(
+        "10.0.0.0/24 ip; dup; ip.network; swap; ip.broadcast",
+        "10.0.0.0\n10.0.0.255",
+    );
+    basic_test(
+        "10.0.0.37 ip; dup; ip.network; swap; ip.broadcast",
+        "10.0.0.37\n10.0.0.37",
+    );
+    basic_test("10.0.0.5-10.0.0.9 ip; ip.broadcast", "10.0.0.9");
+    basic_test("2001:db8::/64 ip; ip.network", "2001:db8::");
+    basic_error_test(
+        "2001:db8::/64 ip; ip.broadcast",
+        "1:19: ip.broadcast argument must be IPv4 object",
+    );
+}
+
+#[test]
+fn ips_cidrs_test() {
+    basic_test(
+        "(10.0.0.0/25 10.0.0.128/25) ips; ips.cidrs; str map",
+        "(\n    0: 10.0.0.0/24\n)",
+    );
+    basic_test(
+        "(::/127 10.0.0.0/25 1.0.0.0/8) ips; ips.cidrs; str map",
+        "(\n    0: 1.0.0.0/8\n    1: 10.0.0.0/25\n    2: ::/127\n)",
+    );
+    basic_test("() ips; ips.cidrs", "()");
+    basic_test("10.0.0.0/24 ips; dup; ips.cidrs; drop; str", "10.0.0.0/24");
+    basic_error_test(
+        "10.0.0.0/24 ip; ips.cidrs",
+        "1:17: ips.cidrs argument must be IP set",
+    );
+}
+
+#[test]
+fn ips_size_test() {
+    basic_test("10.0.0.0/24 ips; ips.size", "256");
+    basic_test(
+        "(10.0.0.0/24 10.0.0.128/25 10.0.1.0/24) ips; ips.size",
+        "512",
+    );
+    basic_test("(::/112 10.0.0.1) ips; ips.size", "65537");
+    basic_test("() ips; ips.size", "0");
+    basic_test("0.0.0.0/0 ips; ips.size", "4294967296");
+}
+
+#[test]
+fn ip_random_test() {
+    basic_test(
+        "10.0.0.0/24 ip; ip.random; ip.addr-int; 167772160 -; dup; 0 >=; swap; 256 <; and",
+        ".t",
+    );
+    basic_test(
+        "10.0.0.5-10.0.0.9 ip; ip.random; ip.addr-int; 167772165 -; dup; 0 >=; swap; 5 <; and",
+        ".t",
+    );
+    basic_test(
+        "5 srand; 2001:db8::/32 ip; ip.random; str; 5 srand; 2001:db8::/32 ip; ip.random; str; =",
+        ".t",
+    );
+    basic_test("10.0.0.1 ip; ip.random; str", "10.0.0.1");
+    basic_error_test("10 ip.random", "1:4: ip.random argument must be ip object");
+}
+
+#[test]
+fn ip_next_prev_test() {
+    basic_test("10.0.0.255 ip; ip.next; str", "10.0.1.0");
+    basic_test("10.0.0.0 ip; ip.prev; str", "9.255.255.255");
+    basic_error_test(
+        "0.0.0.0 ip; ip.prev",
+        "1:13: ip.prev argument is first IPv4 address",
+    );
+    basic_error_test(
+        "255.255.255.255 ip; ip.next",
+        "1:21: ip.next argument is last IPv4 address",
+    );
+    basic_test("2001:db8::ffff ip; ip.next; str", "2001:db8::1:0");
+    basic_test("2001:db8::1 ip; ip.prev; str", "2001:db8::");
+    basic_error_test(
+        "10.0.0.0/24 ip; ip.next",
+        "1:17: ip.next argument must be single-address IP object",
+    );
+}
+
+#[test]
+fn ip_classification_test() {
+    basic_test("10.1.2.3 ip; ip.is-private", ".t");
+    basic_test("127.0.0.1 ip; ip.is-loopback", ".t");
+    basic_test(
+        "8.8.8.8 ip; dup; ip.is-private; over; ip.is-loopback; rot; ip.is-multicast",
+        ".f\n.f\n.f",
+    );
+    basic_test("ff02::1 ip; ip.is-multicast", ".t");
+    basic_test("10.0.0.0/7 ip; ip.is-private", ".f");
+}
+
+#[test]
+fn ip_split_test() {
+    basic_test(
+        "192.168.0.0/24 ip; 6 ip.split-by-count; str map;",
+        "(\n    0: 192.168.0.0/27\n    1: 192.168.0.32/27\n    2: 192.168.0.64/27\n    3: 192.168.0.96/27\n    4: 192.168.0.128/27\n    5: 192.168.0.160/27\n    6: 192.168.0.192/27\n    7: 192.168.0.224/27\n)",
+    );
+    basic_test(
+        "2001:db8::/32 ip; 2 ip.split-by-count; str map;",
+        "(\n    0: 2001:db8::/33\n    1: 2001:db8:8000::/33\n)",
+    );
+    basic_error_test(
+        "192.168.0.0/30 ip; 5 ip.split-by-count;",
+        "1:22: ip.split-by-count count exceeds available address space",
+    );
+}
+
+#[test]
+fn mac_test() {
+    basic_test("0011.2233.4455 mac", "00:11:22:33:44:55");
+    basic_test("00-11-22-AA-BB-CC mac", "00:11:22:aa:bb:cc");
+    basic_test("0:11:22:33:44:5 mac", "00:11:22:33:44:05");
+    basic_test("\"001122334455\" mac", "00:11:22:33:44:55");
+    basic_test("0011223344aa mac", "00:11:22:33:44:aa");
+    basic_test("00:00:00:00:01:00 mac.to-int", "256");
+    basic_test("256 mac.from-int", "00:00:00:00:01:00");
+    basic_test("00112233445f mac.oui", "00:11:22");
+    basic_error_test(
+        "00:11:22:33:44 mac",
+        "1:16: mac argument must be valid MAC address string",
+    );
+}
+
+#[test]
+fn hash_eq_test() {
+    basic_test("h(a 1 b 2) h(b 2 a 1) hash-eq;", ".t");
+    basic_test("h(a 1 b 2) h(a 1 b 3) hash-eq;", ".f");
+    basic_test("h(a 1 b 2) h(a 1) hash-eq;", ".f");
+    basic_test(
+        "h(a 1 b 2) x 10 set; h(b 2 a 1) x 10 set; y h(c 1) set; hash-eq;",
+        ".f",
+    );
+    basic_test(
+        "h(a (1 2) b h(c 3 d 4)) h(b h(d 4 c 3) a (1 2)) hash-eq;",
+        ".t",
+    );
+    basic_test(
+        "h(a 1) dup; x rot; set; h(a 1) dup; x rot; set; hash-eq;",
+        ".t",
+    );
+}
+
+#[test]
+fn flatten_hash_test() {
+    basic_test("h(a h(b 1)) flatten-hash;", "h(\n    \"a.b\": 1\n)");
+    basic_test(
+        "h(a.b 1) unflatten-hash;",
+        "h(\n    \"a\": h(\n        \"b\": 1\n    )\n)",
+    );
+    basic_test("h(a (x y)) flatten-hash; a.1 get;", "y");
+    basic_test("h(a.1 y) unflatten-hash; a get; 0 get;", "null");
+    basic_test(
+        "h(a h(b 1 c (x h(y 2))) d 3) dup; flatten-hash; unflatten-hash; hash-eq;",
+        ".t",
+    );
+    basic_error_test(
+        "h(a.b y a.0 x) unflatten-hash;",
+        "1:17: unflatten-hash list path elements must be integers",
+    );
+    basic_error_test(
+        "h(a 1 a.b 2) unflatten-hash;",
+        "1:15: unflatten-hash key paths must not conflict",
+    );
+    basic_error_test(
+        "h(a.b 2 a 1) unflatten-hash;",
+        "1:15: unflatten-hash key paths must not conflict",
+    );
+    basic_error_test(
+        "h(a.0 x a.00 y) unflatten-hash;",
+        "1:18: unflatten-hash key paths must not conflict",
+    );
+    basic_test("h(a.2 x a.0.b y) unflatten-hash; a get; 0 get; b get;", "y");
+}
+
+#[test]
+fn deep_eq_test() {
+    basic_test("(1 (2 3)) (1 (2 3)) eq?;", ".t");
+    basic_test("(1 2) (2 1) eq?;", ".f");
+    basic_test("h(a 1 b (1 2)) h(b (1 2) a 1) eq?;", ".t");
+    basic_test("(1 h(a (1 2))) (1 h(a (1 3))) eq?;", ".f");
+    basic_test("s(1 2) s(2 1) eq?;", ".t");
+    basic_test("(1) 1 eq?;", ".f");
+    basic_test("abc abc eq?;", ".t");
+}
+
+#[test]
+fn hash_of_test() {
+    basic_test("(1 2 3) hash-of;", "15731493360504303303");
+    basic_test("(1 (2 3)) hash-of; (1 (2 3)) hash-of; =;", ".t");
+    basic_test("h(a 1 b 2) hash-of; h(b 2 a 1) hash-of; =;", ".t");
+    basic_test("h(a 1 b 2) hash-of; h(a 1 b 3) hash-of; =;", ".f");
+    basic_test("(1 2) hash-of; (2 1) hash-of; =;", ".f");
+    basic_test("1 hash-of; 1.0 hash-of; =;", ".t");
+    basic_test("(1 -0.0) hash-of; (1.0 0) hash-of; =;", ".t");
+    basic_test("1.5 hash-of; 1 hash-of; =;", ".f");
+    basic_error_test("[1 +] hash-of;", "1:8: hash-of argument must be hashable");
+    basic_error_test(
+        "(1 [1 +]) hash-of;",
+        "1:12: hash-of argument must be hashable",
+    );
+}
+
 #[test]
 fn set_test() {
     basic_test("s(1 2 3) 4 push;", "s(\n    1\n    2\n    3\n    4\n)");
@@ -1057,6 +1574,11 @@ fn hex_test() {
     basic_test("0x5353 hex;", "21331");
     basic_test("0x5353535353535353 hex;", "6004234345560363859");
     basic_error_test("asdf hex;", "1:6: hex argument must be hexadecimal string");
+    basic_test("0xFF hex;", "255");
+    basic_test("0XFF hex;", "255");
+    basic_test("FF hex;", "255");
+    basic_error_test("a0xb hex;", "1:6: hex argument must be hexadecimal string");
+    basic_error_test("f_f hex;", "1:5: hex argument must be hexadecimal string");
 }
 
 #[test]
@@ -1064,6 +1586,59 @@ fn oct_test() {
     basic_test("777 oct;", "511");
 }
 
+#[test]
+fn negative_radix_test() {
+    basic_test("-ff hex;", "-255");
+    basic_test("-255 to-hex;", "-ff");
+    basic_test("-6004234345560363859 to-hex; hex;", "-6004234345560363859");
+    basic_test("-0b101 bin; to-bin;", "-101");
+    basic_test("-17 oct; to-oct;", "-17");
+}
+
+#[test]
+fn commafy_test() {
+    basic_test("1234567 commafy;", "1,234,567");
+    basic_test(
+        "-123456789012345678901234 commafy;",
+        "-123,456,789,012,345,678,901,234",
+    );
+    basic_test("1234.56 commafy;", "1,234.56");
+    basic_test("123 commafy;", "123");
+    basic_test("1234567 _ commafy;", "1_234_567");
+    basic_test("\"1234\" commafy;", "1,234");
+    basic_test("\"-1234.5\" commafy;", "-1,234.5");
+    basic_test("\"1234567\" _ commafy;", "1_234_567");
+    basic_error_test("abc commafy;", "1:5: commafy argument must be number");
+}
+
+#[test]
+fn bytes_test() {
+    basic_test("1536 humanize-bytes;", "\"1.5 KiB\"");
+    basic_test("2147483648 humanize-bytes;", "\"2.0 GiB\"");
+    basic_test("0 humanize-bytes;", "\"0 B\"");
+    basic_test("1500 .t humanize-bytes;", "\"1.5 kB\"");
+    basic_test("2G parse-bytes;", "2147483648");
+    basic_test("1.5K parse-bytes;", "1536");
+    basic_test("10MB parse-bytes;", "10000000");
+    basic_test("0 parse-bytes;", "0");
+    basic_test("1048575 humanize-bytes;", "\"1.0 MiB\"");
+    basic_test("999999 .t humanize-bytes;", "\"1.0 MB\"");
+    basic_test("\"1.0 ZiB\" parse-bytes;", "1180591620717411303424");
+    basic_test("2YB parse-bytes;", "2000000000000000000000000");
+    basic_error_test(
+        "-1 humanize-bytes;",
+        "1:4: humanize-bytes argument must be non-negative integer",
+    );
+    basic_error_test(
+        "5X parse-bytes;",
+        "1:4: parse-bytes argument must be byte size string",
+    );
+    basic_error_test(
+        "1.5 parse-bytes;",
+        "1:5: parse-bytes argument must not have fractional byte count",
+    );
+}
+
 #[test]
 fn lc_test() {
     basic_test("AsDf lc;", "asdf");
@@ -1094,19 +1669,123 @@ fn ucfirst_test() {
 fn reverse_test() {
     basic_test("(1 2 3) reverse;", "(\n    0: 3\n    1: 2\n    2: 1\n)");
     basic_test("asdf reverse;", "fdsa");
+    basic_test("() reverse;", "()");
+    basic_test("(1) reverse;", "(\n    0: 1\n)");
+    basic_test(
+        "(1 2 3) dup; reverse; drop",
+        "(\n    0: 1\n    1: 2\n    2: 3\n)",
+    );
+    basic_error_test(
+        "5 range; reverse",
+        "1:10: reverse argument must not be generator (use take-all)",
+    );
 }
 
 #[test]
-fn sqrt_test() {
-    basic_test("100 sqrt;", "10");
-    basic_test("100.0 sqrt;", "10");
-    basic_test("1 sqrt;", "1");
+fn sample_reservoir_test() {
+    basic_test("1000 range; 3 sample-reservoir; len;", "3");
+    basic_test("1000 range; 3 sample-reservoir; [1000 <] all;", ".t");
+    basic_test("(1 2) 5 sample-reservoir; sort; len;", "2");
+    basic_test(
+        "42 srand; 1000 range; 3 sample-reservoir; \
+         42 srand; 1000 range; 3 sample-reservoir; eq?;",
+        ".t",
+    );
+    basic_error_test(
+        "(1 2 3) -1 sample-reservoir;",
+        "1:13: sample-reservoir second argument must be non-negative integer",
+    );
 }
 
 #[test]
-fn exp_test() {
-    basic_test("2 2 **;", "4");
-    basic_test("100000000000000 50 **;", "10000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000");
+fn progress_test() {
+    basic_test(
+        "3 range; progress; take-all",
+        "(\n    0: 0\n    1: 1\n    2: 2\n)",
+    );
+    basic_test("(a b c) progress; take-all; '' join", "abc");
+}
+
+#[test]
+fn with_bar_test() {
+    basic_test("10 range; 10 with-bar; take-all; len;", "10");
+    basic_test("(a b c) 3 with-bar; take-all; 2 get;", "c");
+    basic_test("(a b c) 2 with-bar; take-all; len;", "3");
+
+    /* The bar is only drawn if standard error is a terminal, so
+     * attach standard error to a pseudo-terminal. */
+    let pty = nix::pty::openpty(None, None).unwrap();
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "(a b c) 3 with-bar; take-all; drop").unwrap();
+    let status = StdCommand::new(cargo_bin("cosh"))
+        .arg(file.path())
+        .stderr(unsafe { Stdio::from_raw_fd(pty.slave) })
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut master = unsafe { fs::File::from_raw_fd(pty.master) };
+    let mut output = Vec::new();
+    let mut buf = [0; 1024];
+    while let Ok(n) = master.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        output.extend_from_slice(&buf[..n]);
+    }
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.trim_end().ends_with("] 100% (3/3)"));
+}
+
+#[test]
+fn batch_test() {
+    basic_test(
+        "10 range; 4 batch; [len] map; take-all;",
+        "(\n    0: 4\n    1: 4\n    2: 2\n)",
+    );
+    basic_test("10 range; 4 batch; take-all; 2 get; 1 get;", "9");
+    basic_test("(a b c d) 2 batch; take-all; len;", "2");
+    basic_test("() 2 batch; take-all; len;", "0");
+}
+
+#[test]
+fn coalesce_test() {
+    basic_test("(null null 3 4) coalesce;", "3");
+    basic_test("(null null) coalesce;", "null");
+    basic_test("() coalesce;", "null");
+    basic_test("null 5 ??;", "5");
+    basic_test("0 5 ??;", "0");
+    basic_test("h(a 1) b get; default ??;", "default");
+    basic_error_test("3 coalesce;", "1:3: coalesce argument must be list");
+}
+
+#[test]
+fn default_test() {
+    basic_test("null 5 default;", "5");
+    basic_test("0 5 default;", "0");
+    basic_test("\"\" 5 default; len;", "0");
+    basic_test(".f 5 default;", ".f");
+    basic_test("h(a 1) a get; 5 default;", "1");
+    basic_error_test("5 default;", "1:3: default requires two arguments");
+}
+
+#[test]
+fn next_id_test() {
+    basic_test("next-id; next-id; next-id;", "1\n2\n3");
+    basic_test(": f next-id; ,, f; f; next-id;", "1\n2\n3");
+}
+
+#[test]
+fn sqrt_test() {
+    basic_test("100 sqrt;", "10");
+    basic_test("100.0 sqrt;", "10");
+    basic_test("1 sqrt;", "1");
+}
+
+#[test]
+fn exp_test() {
+    basic_test("2 2 **;", "4");
+    basic_test("100000000000000 50 **;", "10000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000");
     basic_test("\"100\" \"2.0\" **;", "10000");
 }
 
@@ -1117,6 +1796,34 @@ fn abs_test() {
     basic_test("-10000000000000 abs;", "10000000000000");
 }
 
+#[test]
+fn roundto_test() {
+    basic_test("3.14159 2 roundto;", "3.14");
+    basic_test("1234.5 -2 roundto;", "1200");
+    basic_test("2.5 0 roundto;", "3");
+    basic_test("1.5 308 roundto;", "1.5");
+    basic_error_test(
+        "1.5 309 roundto;",
+        "1:9: second roundto argument is out of range",
+    );
+    basic_error_test(
+        "1.5 -2147483648 roundto;",
+        "1:17: second roundto argument is out of range",
+    );
+}
+
+#[test]
+fn nan_inf_test() {
+    basic_test("-1.0 sqrt; is-nan;", ".t");
+    basic_test("10.0 400 **; is-inf;", ".t");
+    basic_test("10.0 400 **; inf; =;", ".t");
+    basic_test("1.5 is-finite;", ".t");
+    basic_test("1.5 is-nan;", ".f");
+    basic_test("nan; is-finite;", ".f");
+    basic_test("-inf; is-inf;", ".t");
+    basic_test("nan; nan; =;", ".f");
+}
+
 #[test]
 fn len_test() {
     basic_test("asdf len;", "4");
@@ -1165,11 +1872,173 @@ fn stat_test() {
     basic_test("{rm -f asdf}; take-all; drop; {rm -f temp}; take-all; drop; Cargo.toml temp cp; {ln -s temp asdf}; take-all; drop; asdf stat; size get; 500 >; asdf lstat; size get; 100 <; and; {rm -f asdf}; take-all; drop; {rm -f temp}; take-all; drop;", ".t");
 }
 
+#[test]
+fn chmod_mode_test() {
+    basic_test(
+        "chmodtest touch; chmodtest '600' chmod; chmodtest stat; mode get; chmodtest rm",
+        "600",
+    );
+    basic_test(
+        "chmodtest touch; chmodtest 640 oct; chmod; chmodtest stat; mode get; chmodtest rm",
+        "640",
+    );
+    basic_error_test(
+        "chmodmissing '9x' chmod",
+        "1:19: second chmod argument must be mode",
+    );
+    basic_error_test(
+        "chmodmissing '600' chmod",
+        "1:20: unable to get metadata for path",
+    );
+}
+
+#[test]
+fn stat_fields_test() {
+    basic_test(
+        "Cargo.toml stat; dup; is-file get; over; is-dir get; rot; is-symlink get",
+        ".t\n.f\n.f",
+    );
+    basic_test("src stat; dup; is-dir get; swap; is-file get", ".t\n.f");
+    basic_test(
+        "statmode touch; statmode 644 oct; chmod; statmode stat; mode get; statmode rm",
+        "644",
+    );
+    basic_test("Cargo.toml stat; mtime get; to-epoch; 0 >", ".t");
+    basic_error_test(
+        "/nonexistent stat",
+        "1:14: unable to stat file: No such file or directory (os error 2)",
+    );
+}
+
+#[test]
+fn touch_test() {
+    basic_test(
+        "touchtest touch; touchtest stat; is-file get; touchtest rm",
+        ".t",
+    );
+    basic_test(
+        "touchtest touch; touchtest 1000000000 from-epoch; touch; \
+         touchtest stat; dup; mtime get; to-epoch; swap; atime get; to-epoch; \
+         touchtest rm",
+        "1000000000\n1000000000",
+    );
+    basic_error_test(
+        "touchmissing 1000 from-epoch; touch",
+        "1:31: unable to set file times: No such file or directory (os error 2)",
+    );
+    /* Permission checks do not apply to root. */
+    if !nix::unistd::Uid::effective().is_root() {
+        basic_error_test(
+            "tempdir; dup; '555' chmod; dup; cd; x touch",
+            "1:39: unable to write file: Permission denied (os error 13)",
+        );
+    }
+}
+
 #[test]
 fn mv_test() {
     basic_test("mvtest touch; mvtest mvtest2 rename; mvtest2 mvtest mv; mvtest stat; size get; 0 =; {rm -f mvtest}; take-all; {rm -f mvtest2}; take-all; drop; drop;", ".t");
 }
 
+#[test]
+fn cp_mv_force_test() {
+    basic_test(
+        "Cargo.toml cptest cp; Cargo.toml f<; take-all; '' join; \
+         cptest f<; take-all; '' join; =; cptest rm",
+        ".t",
+    );
+    basic_test(
+        "mvtest3 touch; mvtest3 mvtest4 mv; \
+         {sh -c \"test -e mvtest3 && echo yes || echo no\"}; shift; chomp; \
+         mvtest4 stat; is-file get; mvtest4 rm",
+        "no\n.t",
+    );
+    basic_test(
+        "mvtest5 touch; mvtest6 touch; mvtest5 mvtest6 .t mv; \
+         {sh -c \"test -e mvtest5 && echo yes || echo no\"}; shift; chomp; mvtest6 rm",
+        "no",
+    );
+    basic_error_test(
+        "tempdir; dup; cd; a touch; b touch; a b mv",
+        "1:41: mv destination already exists",
+    );
+    basic_error_test(
+        "tempdir; dup; cd; a touch; b touch; a b cp",
+        "1:41: cp destination already exists",
+    );
+}
+
+#[test]
+fn mkdir_parents_test() {
+    basic_test(
+        "mkdirtest/a/b .t mkdir; mkdirtest/a/b stat; is-dir get; \
+         mkdirtest/a/b .t mkdir; mkdirtest .t rm",
+        ".t",
+    );
+    basic_test(
+        "mkdirtest2 mkdir; mkdirtest2 stat; is-dir get; mkdirtest2 rmdir",
+        ".t",
+    );
+    basic_error_test(
+        "src mkdir",
+        "1:5: unable to make directory: File exists (os error 17)",
+    );
+    basic_error_test(
+        "mkdirtest3/a mkdir",
+        "1:14: unable to make directory: No such file or directory (os error 2)",
+    );
+}
+
+#[test]
+fn mktemp_test() {
+    basic_test(
+        "mktemp; a var; a !; mktemp; b var; b !; a @; b @; =; not; \
+         a @; stat; is-file get; b @; stat; is-file get; a @; rm; b @; rm",
+        ".t\n.t\n.t",
+    );
+    basic_test("mktempdir; dup; stat; is-dir get; swap; rmdir", ".t");
+    basic_test("mktemp; dup; stat; mode get; swap; rm", "600");
+    basic_test("mktempdir; dup; stat; mode get; swap; rmdir", "700");
+}
+
+#[test]
+fn symlink_test() {
+    basic_test(
+        "Cargo.toml symlinktest symlink; symlinktest readlink; symlinktest rm",
+        "Cargo.toml",
+    );
+    basic_error_test(
+        "Cargo.toml readlink",
+        "1:12: readlink argument must be symbolic link",
+    );
+    basic_error_test(
+        "Cargo.toml src symlink",
+        "1:16: symlink destination already exists",
+    );
+}
+
+#[test]
+fn rm_test() {
+    basic_test(
+        "rmtest touch; rmtest rm; \
+         {sh -c \"test -e rmtest && echo yes || echo no\"}; shift; chomp",
+        "no",
+    );
+    basic_test(
+        "rmtestdir mkdir; rmtestdir/sub mkdir; rmtestdir/sub/file touch; rmtestdir .t rm; \
+         {sh -c \"test -e rmtestdir && echo yes || echo no\"}; shift; chomp",
+        "no",
+    );
+    basic_error_test(
+        "src rm",
+        "1:5: rm argument is a directory (use recursive flag)",
+    );
+    basic_error_test(
+        "tempdir; dup; cd; . .t rm",
+        "1:24: rm argument must not be root or current directory",
+    );
+}
+
 #[test]
 fn dir_test() {
     basic_test("dirtest mkdir; dirtest rmdir; .t", ".t");
@@ -1230,6 +2099,49 @@ fn append_generator_tests() {
     basic_test("2 range; 2 range; ++; 2 range; ++; '' join", "010101");
 }
 
+#[test]
+fn tilde_expansion_test() {
+    basic_test(
+        "HOME tests setenv; \"~/tests.rs\" r open; readline; chomp",
+        "\"extern crate assert_cmd;\"",
+    );
+    basic_test(
+        "HOME tests setenv; \"~\" stat; ino get; tests stat; ino get; =",
+        ".t",
+    );
+    basic_test("HOME src setenv; \"~/vm\" is-dir", ".t");
+    basic_test(
+        "HOME tests setenv; \"a~b\" touch; \
+         {sh -c \"test -e a~b && echo yes || echo no\"}; shift; chomp; \"a~b\" rm",
+        "yes",
+    );
+}
+
+#[test]
+fn mode_test() {
+    basic_test("uname", "uname");
+    let uname = StdCommand::new("uname").output().unwrap().stdout;
+    let uname = String::from_utf8(uname).unwrap();
+    basic_test("shell mode; uname", uname.trim_end());
+    basic_test("shell mode; tests", "tests");
+    basic_test("shell mode; : uname 42 ,, uname", "42");
+    basic_test("shell mode; code mode; uname", "uname");
+    basic_error_test("other mode", "1:7: mode argument must be 'shell' or 'code'");
+}
+
+#[test]
+fn cd_test() {
+    basic_test("tempdir; dup; cd; pwd; =", ".t");
+    basic_test("/ cd; pwd", "/");
+    basic_test("pwd; / cd; - cd; pwd; =", ".t");
+    basic_test("/ cd; tempdir; cd; - cd; pwd; - cd; pwd; / =", "/\n.f");
+    basic_error_test("- cd", "1:3: unable to cd: no previous directory");
+    basic_error_test(
+        "/nonexistent cd",
+        "1:14: unable to cd: No such file or directory (os error 2)",
+    );
+}
+
 #[test]
 fn env_tests() {
     basic_test("cosh_key cosh_value setenv; cosh_key getenv", "cosh_value");
@@ -1237,6 +2149,41 @@ fn env_tests() {
         "cosh_key cosh_value setenv; env; cosh_key get",
         "cosh_value",
     );
+    basic_test(
+        "cosh_key cosh_value env-set; cosh_key env-get",
+        "cosh_value",
+    );
+    basic_test("cosh_missing env-get; is-null", ".t");
+    basic_test(
+        "cosh_key cosh_value env-set; env-all; cosh_key get",
+        "cosh_value",
+    );
+    basic_test(
+        "cosh_key cosh_value env-set; cosh_key env-unset; cosh_key env-get; is-null",
+        ".t",
+    );
+    basic_test(
+        "cosh_key cosh_value env-set; {sh -c \"echo $cosh_key\"}; take-all; chomp map",
+        "(\n    0: cosh_value\n)",
+    );
+}
+
+#[test]
+fn expand_test() {
+    basic_test(
+        "HOME /home/user setenv; \"$HOME/bin\" expand",
+        "/home/user/bin",
+    );
+    basic_test(
+        "HOME /home/user setenv; \"${HOME}/bin\" expand",
+        "/home/user/bin",
+    );
+    basic_test("\"a${COSH_UNSET}b$COSH_UNSET.\" expand", "ab.");
+    basic_test("\"\\$x\" expand", "$x");
+    basic_error_test(
+        "\"$COSH_UNSET\" .t expand",
+        "1:18: unable to expand: COSH_UNSET is not set",
+    );
 }
 
 #[test]
@@ -1343,6 +2290,19 @@ fn cmp_test() {
     basic_test("150 100 <=>", "1");
 }
 
+#[test]
+fn cmp_form_test() {
+    basic_test("1 2 cmp", "-1");
+    basic_test("asdf asdf cmp", "0");
+    basic_test("1 a cmp", "-1");
+    basic_test("(1 3 2) [cmp] sortp", "(\n    0: 1\n    1: 2\n    2: 3\n)");
+    basic_error_test("(1) 1 cmp", "1:8: cmp requires two comparable values");
+    basic_error_test("nan; 1.0 cmp", "1:10: cmp requires two comparable values");
+    basic_error_test("1 h(a 1) cmp", "1:11: cmp requires two comparable values");
+    basic_error_test("1 cmp", "1:3: cmp requires two arguments");
+    basic_error_test("(1) 1 <=>", "1:8: <=> requires two comparable values");
+}
+
 #[test]
 fn fmt_test() {
     basic_test("1 2 \"{} {}\" fmt", "\"2 1\"");
@@ -1389,3 +2349,634 @@ fn newline_command_test() {
         "2"
     );
 }
+
+#[test]
+fn disassemble_to_string_test() {
+    let mut chunk = Chunk::new_standard("test".to_string());
+    for n in 1..3 {
+        let i = chunk.add_constant(Value::Int(n)).unwrap();
+        chunk.add_opcode(OpCode::Constant);
+        chunk.add_byte(0);
+        chunk.add_byte(i as u8);
+    }
+    chunk.add_opcode(OpCode::Add);
+    chunk.add_opcode(OpCode::EndFn);
+    assert_eq!(
+        chunk.disassemble_to_string("test"),
+        "== test ==\n 0   OP_CONSTANT 1\n 3   OP_CONSTANT 2\n 6   OP_ADD\n 7   OP_ENDFN\n"
+    );
+}
+
+#[test]
+fn disassemble_truncated_test() {
+    let mut chunk = Chunk::new_standard("test".to_string());
+    chunk.add_constant(Value::Int(1)).unwrap();
+    chunk.add_opcode(OpCode::Add);
+    chunk.add_opcode(OpCode::Constant);
+    assert_eq!(
+        chunk.disassemble_to_string("test"),
+        "== test ==\n 0   OP_ADD\n 1   (truncated)\n"
+    );
+    chunk.add_byte(0);
+    assert_eq!(
+        chunk.disassemble_to_string("test"),
+        "== test ==\n 0   OP_ADD\n 1   (truncated)\n"
+    );
+}
+
+#[test]
+fn chunk_save_load_test() {
+    let mut chunk = Chunk::new_standard("test".to_string());
+    let i = chunk.add_constant(Value::Int(1)).unwrap();
+    chunk.add_opcode(OpCode::Constant);
+    chunk.add_byte(0);
+    chunk.add_byte(i as u8);
+    let mut nested = Chunk::new_standard("nested".to_string());
+    nested.add_opcode(OpCode::EndFn);
+    chunk
+        .functions
+        .insert("nested".to_string(), Rc::new(RefCell::new(nested)));
+
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_str().unwrap();
+    chunk.save_to_path(path).unwrap();
+    let loaded = Chunk::load_from_path(path).unwrap();
+
+    assert_eq!(loaded.data, chunk.data);
+    assert_eq!(
+        format!("{:?}", loaded.constants),
+        format!("{:?}", chunk.constants)
+    );
+    assert_eq!(
+        loaded.disassemble_to_string("test"),
+        chunk.disassemble_to_string("test")
+    );
+    assert!(Chunk::load_from_path("/nonexistent/chunk.chc").is_err());
+}
+
+#[test]
+fn chunk_load_constant_values_test() {
+    let mut chunk = Chunk::new_standard("test".to_string());
+    chunk.add_constant(Value::Int(10)).unwrap();
+    let mut nested = Chunk::new_standard("nested".to_string());
+    nested.add_constant(Value::Int(20)).unwrap();
+    chunk
+        .functions
+        .insert("nested".to_string(), Rc::new(RefCell::new(nested)));
+
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_str().unwrap();
+    chunk.save_to_path(path).unwrap();
+    let loaded = Chunk::load_from_path(path).unwrap();
+
+    assert_eq!(format!("{:?}", loaded.get_constant_value(0)), "10");
+    let nested = loaded.functions.get("nested").unwrap();
+    assert_eq!(format!("{:?}", nested.borrow().get_constant_value(0)), "20");
+}
+
+#[test]
+fn chunk_list_constant_test() {
+    let mut chunk = Chunk::new_standard("test".to_string());
+    let lst = (1..4).map(Value::Int).collect();
+    let i = chunk
+        .add_constant(Value::List(Rc::new(RefCell::new(lst))))
+        .unwrap();
+
+    let file = NamedTempFile::new().unwrap();
+    let path = file.path().to_str().unwrap();
+    chunk.save_to_path(path).unwrap();
+    let loaded = Chunk::load_from_path(path).unwrap();
+    assert_eq!(
+        format!("{:?}", loaded.get_constant(i)),
+        format!("{:?}", chunk.get_constant(i))
+    );
+    match loaded.get_constant(i) {
+        Value::List(lst) => {
+            let ints: Vec<i32> = lst.borrow().iter().map(|v| v.to_int().unwrap()).collect();
+            assert_eq!(ints, vec![1, 2, 3]);
+        }
+        _ => panic!("expected list constant"),
+    }
+
+    let function =
+        Value::NamedFunction(Rc::new(RefCell::new(Chunk::new_standard("f".to_string()))));
+    assert!(chunk.add_constant(function).is_err());
+    assert_eq!(chunk.constants.len(), 1);
+}
+
+#[test]
+fn chunk_list_constant_load_test() {
+    /* Each load of a list constant should produce a fresh list, so
+     * that mutating one loaded value does not affect later loads. */
+    let mut chunk = Chunk::new_standard("test".to_string());
+    let lst = (1..4).map(Value::Int).collect();
+    let l = chunk
+        .add_constant(Value::List(Rc::new(RefCell::new(lst))))
+        .unwrap();
+    let n = chunk.add_constant(Value::Int(4)).unwrap();
+    for i in [l, n, l] {
+        chunk.add_opcode(OpCode::Constant);
+        chunk.add_byte(0);
+        chunk.add_byte(i as u8);
+        if i == n {
+            chunk.add_opcode(OpCode::Push);
+            chunk.add_opcode(OpCode::Drop);
+        }
+    }
+    chunk.add_opcode(OpCode::Pop);
+    chunk.add_opcode(OpCode::Str);
+    chunk.add_opcode(OpCode::Print);
+    chunk.add_opcode(OpCode::EndFn);
+
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::new(false, false, Rc::new(RefCell::new(HashMap::new())));
+    vm.set_output(Box::new(SharedBuffer(buffer.clone())));
+    vm.run(Rc::new(RefCell::new(chunk)));
+
+    let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+    assert_eq!(output, "3");
+}
+
+#[test]
+fn vm_error_test() {
+    let st = StringTriple::new("abc".to_string(), None);
+    let value_rr = Value::String(Rc::new(RefCell::new(st)));
+    match value_rr.to_string() {
+        Err(VmError::UnexpectedString) => {}
+        res => panic!("expected error, got {:?}", res),
+    }
+    assert_eq!(Value::Int(5).to_string().unwrap(), Some("5".to_string()));
+
+    let mut chunk = Chunk::new_standard("test".to_string());
+    let function =
+        Value::NamedFunction(Rc::new(RefCell::new(Chunk::new_standard("f".to_string()))));
+    match chunk.add_constant(function) {
+        Err(VmError::UnsupportedConstant(_)) => {}
+        res => panic!("expected error, got {:?}", res),
+    }
+}
+
+#[test]
+fn vm_stack_api_test() {
+    let mut vm = VM::new(false, false, Rc::new(RefCell::new(HashMap::new())));
+    assert_eq!(vm.stack_len(), 0);
+    assert!(vm.peek(0).is_none());
+    assert!(vm.pop_result().is_none());
+
+    let mut input: Box<dyn BufRead> = Box::new(BufReader::new("1 2 abc".as_bytes()));
+    let global_functions = Rc::new(RefCell::new(HashMap::new()));
+    vm.interpret(global_functions, &mut input, "(main)");
+
+    assert_eq!(vm.stack_len(), 3);
+    assert_eq!(vm.peek(2).unwrap().to_int(), Some(1));
+    assert!(vm.peek(3).is_none());
+    match vm.pop_result() {
+        Some(Value::String(st)) => assert_eq!(st.borrow().string, "abc"),
+        res => panic!("expected string, got {:?}", res),
+    }
+    assert_eq!(vm.stack_len(), 2);
+    assert_eq!(vm.peek(0).unwrap().to_int(), Some(2));
+}
+
+#[test]
+fn match_test() {
+    basic_test(
+        "2024-01-02 \"(\\d+)-(\\d+)-(\\d+)\" match;",
+        "(\n    0: 2024-01-02\n    1: 2024\n    2: 01\n    3: 02\n)",
+    );
+    basic_test("asdf \"\\d+\" match;", "null");
+    basic_test(
+        "2024-01-02 \"(?P<y>\\d+)-(?P<m>\\d+)\" match;",
+        "(\n    0: 2024-01\n    1: 2024\n    2: 01\n    3: h(\n        \"y\": 2024\n        \"m\": 01\n    )\n)",
+    );
+    basic_test("ab \"a(x)?b\" match;", "(\n    0: ab\n    1: null\n)");
+}
+
+#[test]
+fn match_all_test() {
+    basic_test(
+        "a1b22c333 \\d+/g match-all; [0 get] map; take-all;",
+        "(\n    0: 1\n    1: 22\n    2: 333\n)",
+    );
+    basic_test("abc \\d+/g match-all; take-all;", "()");
+    basic_test(
+        "a1b22c333 '(\\d)\\d*/g' match-all; 2 take;",
+        "(\n    0: (\n        0: 1\n        1: 1\n    )\n    1: (\n        0: 22\n        1: 2\n    )\n)",
+    );
+    basic_test(
+        "a1b22c333 \\d+ match-all; take-all;",
+        "(\n    0: (\n        0: 1\n    )\n)",
+    );
+}
+
+#[test]
+fn rsplit_test() {
+    basic_test("a1b22c \\d+ rsplit;", "(\n    0: a\n    1: b\n    2: c\n)");
+    basic_test("123 \\d+ rsplit;", "(\n    0: \"\"\n    1: \"\"\n)");
+    basic_test("abc \\d+ rsplit;", "(\n    0: abc\n)");
+}
+
+#[test]
+fn named_capture_replace_test() {
+    basic_test("abc '(?P<x>b)' '[${x}]' s;", "a[b]c");
+    basic_test("2024-01 '(?P<y>\\d+)-(\\d+)' '\\2/${y}' s;", "01/2024");
+    basic_test("abc '(?P<x>b)' '[${nope}]' s;", "a[]c");
+}
+
+#[test]
+fn regex_quote_test() {
+    basic_test("'a.b*c' regex-quote; println;", "a\\.b\\*c");
+    basic_test("'a.b*c' dup; regex-quote; m;", ".t");
+    basic_test("axbbc 'a.b*c' regex-quote; m;", ".f");
+    basic_test("abc regex-quote;", "abc");
+}
+
+#[test]
+fn len_graphemes_test() {
+    basic_test("héllo len;", "5");
+    basic_test("héllo byte-len;", "6");
+    basic_test("'👍🏽x' len;", "2");
+    basic_test("'👍🏽x' byte-len;", "9");
+}
+
+#[test]
+fn chars_test() {
+    basic_test("abc chars; take-all;", "(\n    0: a\n    1: b\n    2: c\n)");
+    basic_test(
+        "'e\u{301}x' chars; take-all;",
+        "(\n    0: e\u{301}\n    1: x\n)",
+    );
+    basic_test("abcdefgh chars; 2 take;", "(\n    0: a\n    1: b\n)");
+}
+
+#[test]
+fn lines_test() {
+    basic_test("\"a\\nb\\n\" lines; take-all;", "(\n    0: a\n    1: b\n)");
+    basic_test("\"a\\r\\nb\" lines; take-all;", "(\n    0: a\n    1: b\n)");
+    basic_test("abc lines; take-all;", "(\n    0: abc\n)");
+}
+
+#[test]
+fn squeeze_ws_test() {
+    basic_test("\"  a \\t b\\n c \" squeeze-ws;", "\"a b c\"");
+    basic_test("\" \\t\\n \" squeeze-ws;", "\"\"");
+    basic_test("\"a b\" squeeze-ws;", "\"a b\"");
+}
+
+#[test]
+fn count_str_test() {
+    basic_test("banana a count-str;", "3");
+    basic_test("banana x count-str;", "0");
+    basic_error_test(
+        "banana '' count-str;",
+        "1:11: second count-str argument must not be empty",
+    );
+}
+
+#[test]
+fn title_swap_case_test() {
+    basic_test("'hello world' title-case;", "\"Hello World\"");
+    basic_test("Hello swap-case;", "hELLO");
+    basic_test("\"don't stop\" title-case;", "\"Don't Stop\"");
+}
+
+#[test]
+fn codepoints_test() {
+    basic_test("abc codepoints;", "(\n    0: 97\n    1: 98\n    2: 99\n)");
+    basic_test("abc codepoints; from-codepoints;", "abc");
+    basic_test("'a😀' codepoints;", "(\n    0: 97\n    1: 128512\n)");
+    basic_test("'a😀' codepoints; from-codepoints;", "a😀");
+    basic_error_test(
+        "(97 55296) from-codepoints;",
+        "1:13: from-codepoints argument must be list of characters",
+    );
+}
+
+#[test]
+fn srand_test() {
+    basic_test(
+        "5 srand; (1 2 3) [drop; 1 rand] map; 5 srand; (1 2 3) [drop; 1 rand] map; eq?;",
+        ".t",
+    );
+    basic_test("5 srand; 1 rand; 6 srand; 1 rand; =;", ".f");
+    basic_test(
+        "5 srand; 100 range; 5 sample-reservoir; 5 srand; 100 range; 5 sample-reservoir; eq?;",
+        ".t",
+    );
+}
+
+#[test]
+fn rand_int_test() {
+    basic_test(
+        "(1 2 3 4 5 6 7 8 9 10) [drop; 0 10 rand-int] map; [dup; 0 >=; swap; 10 <; and] all;",
+        ".t",
+    );
+    basic_test("5 6 rand-int;", "5");
+    basic_test(
+        "100000000000000000000 100000000000000000001 rand-int;",
+        "100000000000000000000",
+    );
+    basic_error_test(
+        "10 0 rand-int;",
+        "1:6: rand-int lower bound must be less than upper bound",
+    );
+}
+
+#[test]
+fn sample_shuffle_test() {
+    basic_test(
+        "5 srand; 10 range; shuffle; 5 srand; 10 range; shuffle; eq?;",
+        ".t",
+    );
+    basic_test(
+        "(1 2 3 4 5) dup; shuffle; drop;",
+        "(\n    0: 1\n    1: 2\n    2: 3\n    3: 4\n    4: 5\n)",
+    );
+    basic_test("(1 2 3 4 5) shuffle; sort; len;", "5");
+    basic_test("(a) sample;", "a");
+    basic_test("() sample;", "null");
+}
+
+#[test]
+fn uuid_test() {
+    basic_test(
+        "uuid; '^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$' m;",
+        ".t",
+    );
+    basic_test("uuid; - split; 2 get; chars; shift;", "4");
+    basic_test("uuid; - split; 3 get; '^[89ab]' m;", ".t");
+    basic_test("uuid; uuid; =;", ".f");
+}
+
+#[test]
+fn digest_test() {
+    basic_test(
+        "'' sha256;",
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    );
+    basic_test(
+        "abc sha256;",
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+    );
+    basic_test("abc md5;", "900150983cd24fb0d6963f7d28e17f72");
+    basic_test("abc sha1;", "a9993e364706816aba3e25717850c26c9cd0d89d");
+    basic_test("abc md5; len;", "32");
+    basic_test("abc sha1; len;", "40");
+    basic_test("abc sha256; len;", "64");
+    basic_test("abc sha512; len;", "128");
+    basic_error_test("(1) sha256;", "1:6: sha256 argument must be string");
+}
+
+#[test]
+fn error_trace_test() {
+    basic_error_test(
+        "
+: inner
+    x 1 +; ,,
+: outer
+    1; inner; ,,
+outer;
+",
+        "3:9: + requires two numbers\n  called from 5:8\n  called from 6:1",
+    );
+}
+
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn error_output_test() {
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::new(false, false, Rc::new(RefCell::new(HashMap::new())));
+    vm.set_error_output(Box::new(SharedBuffer(buffer.clone())));
+
+    let mut input: Box<dyn BufRead> = Box::new(BufReader::new("1 asdf +".as_bytes()));
+    let global_functions = Rc::new(RefCell::new(HashMap::new()));
+    vm.interpret(global_functions, &mut input, "(main)");
+
+    let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+    assert_eq!(output, "1:8: + requires two numbers\n");
+}
+
+#[test]
+fn read_test() {
+    stdin_test("read; read", "first\nsecond\n", "first\nsecond");
+    stdin_test("read; is-null", "", ".t");
+    stdin_test("read; read; is-null", "last", "last\n.t");
+}
+
+#[test]
+fn stdin_lines_test() {
+    stdin_test(
+        "stdin-lines; take-all",
+        "a\nb\nc\n",
+        "(\n    0: a\n    1: b\n    2: c\n)",
+    );
+    stdin_test("stdin-lines; take-all", "", "()");
+    stdin_test(
+        "stdin-lines; [len] map; take-all",
+        "abc\nde",
+        "(\n    0: 3\n    1: 2\n)",
+    );
+}
+
+#[test]
+fn print_output_test() {
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::new(false, false, Rc::new(RefCell::new(HashMap::new())));
+    vm.set_output(Box::new(SharedBuffer(buffer.clone())));
+
+    let mut input: Box<dyn BufRead> = Box::new(BufReader::new(
+        "a print; b println; c print; 1 println; 2.5 print".as_bytes(),
+    ));
+    let global_functions = Rc::new(RefCell::new(HashMap::new()));
+    vm.interpret(global_functions, &mut input, "(main)");
+
+    let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+    assert_eq!(output, "ab\nc1\n2.5");
+}
+
+#[test]
+fn pp_table_output_test() {
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::new(false, false, Rc::new(RefCell::new(HashMap::new())));
+    vm.set_output(Box::new(SharedBuffer(buffer.clone())));
+
+    let mut input: Box<dyn BufRead> = Box::new(BufReader::new(
+        "(1 (2)) pp; (h(a 1) h(a 2)) table".as_bytes(),
+    ));
+    let global_functions = Rc::new(RefCell::new(HashMap::new()));
+    vm.interpret(global_functions, &mut input, "(main)");
+
+    let output = String::from_utf8(buffer.borrow().clone()).unwrap();
+    assert_eq!(output, "- 1\n-\n  - 2\na\n-\n1\n2\n");
+}
+
+#[test]
+fn try_test() {
+    basic_test("[1 2 +] try", "3");
+    basic_test(
+        "[5 0 /] try; dup; message get; println; dup; line get; println; col get",
+        "division by zero\n1\n6",
+    );
+    basic_test(
+        "1 [2 x +] try; message get; println",
+        "+ requires two numbers\n1",
+    );
+    basic_test("1 2 [drop; drop; 5 0 /] try; drop; depth", "1\n2\n2");
+}
+
+#[test]
+fn assert_test() {
+    basic_test("1 assert; 2", "2");
+    basic_test("(1 2) (1 2) assert-eq; 3", "3");
+    basic_test("abc assert; 2", "2");
+    basic_test("1 \"bad value\" assert-msg; 2", "2");
+    basic_error_test("0 assert", "1:3: assertion failed");
+    basic_test("1 abc assert", "1");
+    basic_error_test("1 \"\" assert", "1:6: assertion failed");
+    basic_error_test(
+        "0 \"bad value\" assert-msg",
+        "1:15: assertion failed: bad value",
+    );
+    basic_error_test(
+        "0 (1) assert-msg",
+        "1:8: assert-msg second argument must be a string",
+    );
+    basic_error_test(
+        "(1 2) (1 3) assert-eq",
+        "1:15: assert-eq failed: [1,2] != [1,3]",
+    );
+}
+
+#[test]
+fn expect_type_test() {
+    basic_test("5 int expect-type", "5");
+    basic_error_test(
+        "abc list expect-type",
+        "1:10: expected list argument, got str",
+    );
+    basic_test("::/64 ip; ip expect-type; str", "::/64");
+}
+
+#[test]
+fn nth_test() {
+    basic_test("(a b c) 1 nth", "b");
+    basic_test("(a b c) -1 nth", "c");
+    basic_test("(a b c) 3 nth", "null");
+}
+
+#[test]
+fn slice_test() {
+    basic_test("(a b c) 1 5 slice", "(\n    0: b\n    1: c\n)");
+    basic_test("(a b c) 2 1 slice", "()");
+}
+
+#[test]
+fn insert_remove_at_test() {
+    basic_test(
+        "(a b c) 1 x insert-at",
+        "(\n    0: a\n    1: x\n    2: b\n    3: c\n)",
+    );
+    basic_test(
+        "(a b c) dup; 0 remove-at; println",
+        "a\n(\n    0: b\n    1: c\n)",
+    );
+    basic_error_test(
+        "(a b c) 3 remove-at",
+        "1:12: remove-at index is out of range",
+    );
+}
+
+#[test]
+fn range_test() {
+    basic_test(
+        "(0 5) range; take-all",
+        "(\n    0: 0\n    1: 1\n    2: 2\n    3: 3\n    4: 4\n)",
+    );
+    basic_test(
+        "(10 0 -2) range; take-all",
+        "(\n    0: 10\n    1: 8\n    2: 6\n    3: 4\n    4: 2\n)",
+    );
+    basic_error_test("(0 5 0) range", "1:10: range step must not be zero");
+}
+
+#[test]
+fn partition_test() {
+    basic_test(
+        "(1 2 3 4 5 6) [dup; 2 /; 2 *; =] partition",
+        "(\n    0: 2\n    1: 4\n    2: 6\n)\n(\n    0: 1\n    1: 3\n    2: 5\n)",
+    );
+    basic_test("(1 2) [drop; .t] partition", "(\n    0: 1\n    1: 2\n)\n()");
+    basic_test("() [drop; .t] partition", "()\n()");
+}
+
+#[test]
+fn group_by_test() {
+    basic_test(
+        "(apple avocado banana) [chars; shift] group-by",
+        "h(\n    \"a\": (\n        0: apple\n        1: avocado\n    )\n    \"b\": (\n        0: banana\n    )\n)",
+    );
+    basic_test(
+        "(1 2 3 4 5) [dup; 2 /; 2 *; -] group-by; keys; take-all",
+        "(\n    0: 1\n    1: 0\n)",
+    );
+    basic_test("() [chars; shift] group-by", "h()");
+}
+
+#[test]
+fn any_all_native_test() {
+    basic_test("(1 3 4) [dup; 2 /; 2 *; =] any?", ".t");
+    basic_test("(1 2 3) [0 >] all?", ".t");
+    basic_test("() [0 >] any?", ".f");
+    basic_test("() [0 >] all?", ".t");
+    basic_test("(1 2 3) [dup; println; 2 <] all?", "1\n2\n.f");
+    basic_test("s(1 2 3) [2 =] any?", ".t");
+    basic_test("s(1 2 3) [0 >] all?", ".t");
+    basic_test("s(1 2 3) s var; s !; s @; [2 =] any?; drop; s @; len", "3");
+    basic_test("s(1 2 3) s var; s !; s @; [0 >] all?; drop; s @; len", "3");
+}
+
+#[test]
+fn find_test() {
+    basic_test("(1 3 6 8) [dup; 2 /; 2 *; =] find", "6");
+    basic_test("(1 2 3) [5 =] find-index", "-1");
+    basic_test("() [5 =] find", "null");
+    basic_test("s(1 2 3) [3 =] find", "3");
+    basic_test("s(1 2 3) [2 =] find-index", "1");
+    basic_test("s(1 2 3) s var; s !; s @; [3 =] find; drop; s @; len", "3");
+    basic_test(
+        "s(1 2 3) s var; s !; s @; [3 =] find-index; drop; s @; len",
+        "3",
+    );
+}
+
+#[test]
+fn pick_tuck_test() {
+    basic_test("1 2 3 2 pick", "1\n2\n3\n1");
+    basic_test("1 2 tuck", "2\n1\n2");
+    basic_error_test("1 2 3 pick", "1:7: pick index is out of range");
+}
+
+#[test]
+fn double_stack_words_test() {
+    basic_test("a b c d 2dup", "a\nb\nc\nd\nc\nd");
+    basic_test("a b c d 2drop", "a\nb");
+    basic_test("a b c d 2swap", "c\nd\na\nb");
+}
+
+#[test]
+fn nip_test() {
+    basic_test("1 2 nip", "2");
+    basic_error_test("1 nip", "1:3: nip requires two arguments");
+}